@@ -2,23 +2,25 @@ use std::collections::HashMap;
 use std::error::Error;
 
 use eventsource_stream::Eventsource;
-use futures::{StreamExt, TryFutureExt, stream};
+use futures::{StreamExt, stream};
 use reqwest::RequestBuilder;
 
 use crate::{
     claude::types::{
-        BlockDelta, ClaudeMessage, ClaudeRequest, ClaudeResponse, ClaudeTool, ContentBlock,
-        DEFAULT_MAX_TOKENS, ResponseBlock, StreamContentBlock, StreamingEvent, ThinkingConfig,
-        synth_tool_use_id,
+        BlockDelta, ClaudeMessage, ClaudeRequest, ClaudeResponse, ClaudeTool, ClaudeToolChoice,
+        ContentBlock, DEFAULT_MAX_TOKENS, ResponseBlock, StreamContentBlock, StreamingEvent,
+        ThinkingConfig, synth_tool_use_id,
     },
     client::{
-        Completion, FunctionCall, MessageType, Model, ModelRequest, StreamEvent, StreamResult,
-        Usage,
+        Completion, CompletionPart, FunctionCall, HttpTransport, MessageType, Model, ModelRequest,
+        StreamEvent, StreamResult, Usage, api_error, log_request, read_json, reject_gcs_file_uris,
+        route, send,
     },
 };
 
 pub trait ClaudeClient: Model {
     fn create_request_body(&self, request: ModelRequest, stream: bool) -> ClaudeRequest {
+        let request = request.with_defaults(self.defaults());
         let settings = request.settings.clone();
 
         let max_tokens = settings
@@ -48,27 +50,29 @@ pub trait ClaudeClient: Model {
             .unwrap_or_default()
             .iter()
             .map(|m| match &m.message_type {
-                MessageType::Text => ClaudeMessage {
-                    role: match m.role {
-                        Some(crate::client::Role::Model) => "assistant",
-                        _ => "user",
-                    },
-                    content: vec![ContentBlock::Text {
-                        text: m.content.clone(),
-                    }],
-                },
-                MessageType::FunctionCall(fc) => ClaudeMessage {
+                MessageType::FunctionCall(fc) if m.parts.is_empty() => ClaudeMessage {
                     role: "assistant",
                     content: vec![ContentBlock::ToolUse {
-                        id: synth_tool_use_id(&fc.name),
+                        id: fc.id.clone().unwrap_or_else(|| synth_tool_use_id(&fc.name)),
                         name: fc.name.clone(),
                         input: fc.args.clone(),
                     }],
                 },
-                MessageType::FunctionResponse { name, response } => ClaudeMessage {
+                MessageType::Text | MessageType::FunctionCall(_) => ClaudeMessage {
+                    role: match m.role {
+                        Some(crate::client::Role::Model) => "assistant",
+                        _ => "user",
+                    },
+                    content: m
+                        .content_parts()
+                        .into_iter()
+                        .map(ContentBlock::from_part)
+                        .collect(),
+                },
+                MessageType::FunctionResponse { name, response, id } => ClaudeMessage {
                     role: "user",
                     content: vec![ContentBlock::ToolResult {
-                        tool_use_id: synth_tool_use_id(name),
+                        tool_use_id: id.clone().unwrap_or_else(|| synth_tool_use_id(name)),
                         content: response
                             .as_ref()
                             .map(|v| v.to_string())
@@ -89,7 +93,12 @@ pub trait ClaudeClient: Model {
             system: request.system.clone(),
             messages,
             temperature,
+            top_p: settings.as_ref().and_then(|s| s.top_p),
             tools,
+            tool_choice: request
+                .function_calling_mode
+                .as_ref()
+                .map(ClaudeToolChoice::from_mode),
             thinking,
             stream: if stream { Some(true) } else { None },
         }
@@ -99,25 +108,43 @@ pub trait ClaudeClient: Model {
         &self,
         request: ModelRequest,
     ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
+        request.validate()?;
+        reject_gcs_file_uris(&request, "Claude")?;
+        log_request("claude", &self.model_name(), &request);
         let endpoint = self.get_endpoint();
+        let client = request.client.clone();
+        let max_response_bytes = request.max_response_bytes;
+        let enum_variants = request.enum_response.clone();
         let body = self.create_request_body(request, false);
-        let response = self.build_request(&endpoint, &body).await?.send().await?;
+        let response = send(
+            self.build_request(&endpoint, &body).await?,
+            route(client.as_ref(), self.transport()),
+        )
+        .await?;
 
-        let status = response.status();
-        if !status.is_success() {
-            let err = response.text().map_err(|e| e.to_string()).await?;
-            return Err(format!("Claude request failed with status {}: {}", status, err).into());
+        if !response.status().is_success() {
+            return Err(api_error(response, max_response_bytes).await?.into());
         }
 
-        let body: ClaudeResponse = response.json().await?;
+        let body: ClaudeResponse = read_json(response, max_response_bytes).await?;
 
         let mut text = String::new();
-        let mut function: Option<FunctionCall> = None;
+        let mut function_calls: Vec<FunctionCall> = vec![];
+        let mut parts = vec![];
         for block in body.content {
             match block {
-                ResponseBlock::Text { text: t } => text.push_str(&t),
-                ResponseBlock::ToolUse { name, input, .. } => {
-                    function = Some(FunctionCall { name, args: input });
+                ResponseBlock::Text { text: t } => {
+                    text.push_str(&t);
+                    CompletionPart::push_text(&mut parts, &t);
+                }
+                ResponseBlock::ToolUse { id, name, input } => {
+                    let call = FunctionCall {
+                        name,
+                        args: input,
+                        id: Some(id),
+                    };
+                    parts.push(CompletionPart::FunctionCall(call.clone()));
+                    function_calls.push(call);
                 }
                 ResponseBlock::Other => {}
             }
@@ -126,12 +153,23 @@ pub trait ClaudeClient: Model {
         let total = body.usage.input_tokens + body.usage.output_tokens;
         Ok(Completion {
             completion: text,
-            usage: Usage {
+            usage: Some(Usage {
                 prompt_tokens: body.usage.input_tokens,
                 completion_tokens: body.usage.output_tokens,
                 total_tokens: total,
-            },
-            function,
+            }),
+            function: function_calls.first().cloned(),
+            function_calls,
+            model: self.model_name(),
+            model_version: body.model,
+            response_id: body.id,
+            logprobs: None,
+            avg_logprobs: None,
+            safety_ratings: None,
+            enum_variants,
+            ordered_parts: Some(parts),
+            media: vec![],
+            truncated: false,
         })
     }
 
@@ -139,18 +177,21 @@ pub trait ClaudeClient: Model {
         &self,
         request: ModelRequest,
     ) -> Result<StreamResult, Box<dyn Error + Send + Sync>> {
+        request.validate()?;
+        reject_gcs_file_uris(&request, "Claude")?;
+        log_request("claude", &self.model_name(), &request);
         let endpoint = self.get_endpoint();
+        let client = request.client.clone();
+        let max_response_bytes = request.max_response_bytes;
         let body = self.create_request_body(request, true);
-        let response = self.build_request(&endpoint, &body).await?.send().await?;
+        let response = send(
+            self.build_request(&endpoint, &body).await?,
+            route(client.as_ref(), self.transport()),
+        )
+        .await?;
 
-        let status = response.status();
-        if !status.is_success() {
-            let err = response.text().map_err(|e| e.to_string()).await?;
-            return Err(format!(
-                "Claude streaming request failed with status {}: {}",
-                status, err
-            )
-            .into());
+        if !response.status().is_success() {
+            return Err(api_error(response, max_response_bytes).await?.into());
         }
 
         // State threaded through `unfold`. Defined at module scope below.
@@ -190,6 +231,12 @@ pub trait ClaudeClient: Model {
         Ok(Box::pin(out))
     }
 
+    /// Where built requests are sent instead of the model's client, if
+    /// anywhere.
+    fn transport(&self) -> Option<&dyn HttpTransport> {
+        None
+    }
+
     fn get_endpoint(&self) -> String;
 
     async fn build_request(
@@ -209,8 +256,12 @@ fn handle_event(ev: StreamingEvent, state: &mut State) {
             index,
             content_block,
         } => {
-            if let StreamContentBlock::ToolUse { name, .. } = content_block {
-                state.tool_block_insert(index, name);
+            if let StreamContentBlock::ToolUse { id, name, .. } = content_block {
+                state.push_event(StreamEvent::FunctionCallStarted {
+                    name: name.clone(),
+                    id: Some(id.clone()),
+                });
+                state.tool_block_insert(index, id, name);
             }
         }
         StreamingEvent::ContentBlockDelta { index, delta } => match delta {
@@ -225,7 +276,7 @@ fn handle_event(ev: StreamingEvent, state: &mut State) {
             BlockDelta::Other => {}
         },
         StreamingEvent::ContentBlockStop { index } => {
-            if let Some((name, json_buf)) = state.tool_block_take(index) {
+            if let Some((id, name, json_buf)) = state.tool_block_take(index) {
                 let args: HashMap<String, serde_json::Value> = if json_buf.is_empty() {
                     HashMap::new()
                 } else {
@@ -240,7 +291,11 @@ fn handle_event(ev: StreamingEvent, state: &mut State) {
                         }
                     }
                 };
-                state.push_event(StreamEvent::FunctionCall(FunctionCall { name, args }));
+                state.push_event(StreamEvent::FunctionCall(FunctionCall {
+                    name,
+                    args,
+                    id: Some(id),
+                }));
             }
         }
         StreamingEvent::MessageDelta { usage, .. } => {
@@ -278,6 +333,7 @@ struct State {
 }
 
 struct ToolBlockAcc {
+    id: String,
     name: String,
     json_buf: String,
 }
@@ -292,10 +348,11 @@ impl State {
     fn prompt_tokens(&self) -> i32 {
         self.prompt_tokens
     }
-    fn tool_block_insert(&mut self, index: u32, name: String) {
+    fn tool_block_insert(&mut self, index: u32, id: String, name: String) {
         self.tool_blocks.insert(
             index,
             ToolBlockAcc {
+                id,
                 name,
                 json_buf: String::new(),
             },
@@ -306,9 +363,9 @@ impl State {
             acc.json_buf.push_str(s);
         }
     }
-    fn tool_block_take(&mut self, index: u32) -> Option<(String, String)> {
+    fn tool_block_take(&mut self, index: u32) -> Option<(String, String, String)> {
         self.tool_blocks
             .remove(&index)
-            .map(|acc| (acc.name, acc.json_buf))
+            .map(|acc| (acc.id, acc.name, acc.json_buf))
     }
 }
@@ -1,4 +1,4 @@
-use std::error::Error;
+use std::{error::Error, sync::Arc};
 
 use async_trait::async_trait;
 use reqwest::RequestBuilder;
@@ -8,13 +8,115 @@ use crate::{
         base::ClaudeClient,
         types::{ClaudeModel, ClaudeRequest},
     },
-    client::{Completion, Model, ModelRequest, StreamResult},
+    client::{
+        ClientOptions, Completion, CompletionCache, HttpTimeouts, HttpTransport, HttpVersionPref,
+        Model, ModelDefaults, ModelObserver, ModelRequest, ProxyConfig, RateLimiter, StreamResult,
+    },
 };
 
+/// A Claude model on the Anthropic API, authenticated with an API key.
+///
+/// Build one with `ClaudeApiModel::new` and the `with_*` methods; fields may be
+/// added in future versions.
+#[non_exhaustive]
 pub struct ClaudeApiModel {
     pub api_key: String,
     pub client: reqwest::Client,
     pub model: ClaudeModel,
+    /// When set, every request is sent through it instead of `client`, e.g. a
+    /// `reqwest_middleware::ClientWithMiddleware` with the `middleware`
+    /// feature.
+    pub transport: Option<Arc<dyn HttpTransport>>,
+    /// See `ModelDefaults`.
+    pub defaults: Option<ModelDefaults>,
+    /// See `CompletionCache`.
+    pub cache: Option<Arc<CompletionCache>>,
+    /// See `Model::rate_limiter`.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// See `Model::observer`.
+    pub observer: Option<Arc<dyn ModelObserver>>,
+    // What `client` was last built from by a `with_*` method.
+    pub(crate) client_options: ClientOptions,
+}
+
+impl ClaudeApiModel {
+    /// `model` with `api_key`, sent through a default `reqwest::Client`.
+    pub fn new(api_key: impl Into<String>, model: ClaudeModel) -> ClaudeApiModel {
+        ClaudeApiModel {
+            api_key: api_key.into(),
+            client: reqwest::Client::new(),
+            model,
+            transport: None,
+            defaults: None,
+            cache: None,
+            rate_limiter: None,
+            observer: None,
+            client_options: ClientOptions::default(),
+        }
+    }
+
+    /// Sends requests through `client`, e.g. one with custom TLS settings,
+    /// instead of a default `reqwest::Client`.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Rebuilds the client with `timeouts`, replacing one set with
+    /// `with_client`. Fails if the client can't be built.
+    pub fn with_timeouts(mut self, timeouts: HttpTimeouts) -> Result<Self, reqwest::Error> {
+        self.client_options.timeouts = timeouts;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Rebuilds the client to speak `version`, replacing one set with
+    /// `with_client`. Fails if the client can't be built.
+    pub fn with_http_version(mut self, version: HttpVersionPref) -> Result<Self, reqwest::Error> {
+        self.client_options.http_version = version;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Rebuilds the client to send requests through the proxies in `proxy`,
+    /// replacing one set with `with_client`. Fails if a proxy URL doesn't
+    /// parse.
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Result<Self, reqwest::Error> {
+        self.client_options.proxy = proxy;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Sends every request through `transport` instead of `client`.
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Settings used where a request leaves them unset.
+    pub fn with_defaults(mut self, defaults: ModelDefaults) -> Self {
+        self.defaults = Some(defaults);
+        self
+    }
+
+    /// Caches completions in `cache`; see `CompletionCache`.
+    pub fn with_cache(mut self, cache: Arc<CompletionCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Waits on `limiter` before every request built with `new_request`.
+    /// Share one limiter between all models that draw from the same quota.
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Reports every request built with `new_request` to `observer`.
+    pub fn with_observer(mut self, observer: Arc<dyn ModelObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
 }
 
 #[async_trait]
@@ -36,9 +138,29 @@ impl Model for ClaudeApiModel {
     fn model_name(&self) -> String {
         self.model.to_string()
     }
+
+    fn defaults(&self) -> Option<&ModelDefaults> {
+        self.defaults.as_ref()
+    }
+
+    fn cache(&self) -> Option<&CompletionCache> {
+        self.cache.as_deref()
+    }
+
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_deref()
+    }
+
+    fn observer(&self) -> Option<&dyn ModelObserver> {
+        self.observer.as_deref()
+    }
 }
 
 impl ClaudeClient for ClaudeApiModel {
+    fn transport(&self) -> Option<&dyn HttpTransport> {
+        self.transport.as_deref()
+    }
+
     fn get_endpoint(&self) -> String {
         "https://api.anthropic.com/v1/messages".to_string()
     }
@@ -1,6 +1,5 @@
 use std::env;
 
-use futures::StreamExt;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -13,11 +12,10 @@ use crate::{
 };
 
 fn make_model(model: ClaudeModel) -> ClaudeApiModel {
-    ClaudeApiModel {
-        client: reqwest::Client::new(),
-        api_key: env::var("CLAUDE_KEY").expect("CLAUDE_KEY env var must be set"),
+    ClaudeApiModel::new(
+        env::var("CLAUDE_KEY").expect("CLAUDE_KEY env var must be set"),
         model,
-    }
+    )
 }
 
 fn default_settings() -> Settings {
@@ -25,7 +23,10 @@ fn default_settings() -> Settings {
         max_tokens: Some(8000),
         timeout: None,
         temperature: None,
+        top_p: None,
         thinking_budget: None,
+        privacy_mode: None,
+        merge_consecutive_roles: None,
     }
 }
 
@@ -61,6 +62,7 @@ async fn run_generate_content(model: ClaudeModel) {
         .completion()
         .await;
     assert!(response.is_ok(), "completion failed: {:?}", response.err());
+    assert_eq!(response.unwrap().model, m.model_name());
 }
 
 async fn run_with_messages(model: ClaudeModel) {
@@ -162,7 +164,14 @@ async fn run_stream_generate_content(model: ClaudeModel) {
                 assert!(total_tokens > 0);
             }
             StreamEvent::FunctionCall(_) => {}
+            StreamEvent::FunctionCallStarted { .. } => {}
             StreamEvent::Error(e) => panic!("stream event should not be an error: {}", e),
+            StreamEvent::Truncated(_) => panic!("stream should not be truncated"),
+            StreamEvent::Stopped(_) => panic!("stream should not be stopped"),
+            StreamEvent::Cancelled => panic!("stream should not be cancelled"),
+            StreamEvent::Filtered { finish_reason, .. } => {
+                panic!("stream should not be filtered: {}", finish_reason)
+            }
         }
     }
 
@@ -197,6 +206,12 @@ async fn run_stream_function_call(model: ClaudeModel) {
                 assert_eq!(fc.name, "get_weather");
             }
             StreamEvent::Error(e) => panic!("stream event should not be an error: {}", e),
+            StreamEvent::Truncated(_) => panic!("stream should not be truncated"),
+            StreamEvent::Stopped(_) => panic!("stream should not be stopped"),
+            StreamEvent::Cancelled => panic!("stream should not be cancelled"),
+            StreamEvent::Filtered { finish_reason, .. } => {
+                panic!("stream should not be filtered: {}", finish_reason)
+            }
             _ => {}
         }
     }
@@ -345,24 +360,266 @@ fn test_claude_tool_emits_required_and_name_and_description() {
 
 #[test]
 fn test_model_name_claude_api() {
-    let m = ClaudeApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy-key".to_string(),
-        model: ClaudeModel::Sonnet4_5,
-    };
+    let m = ClaudeApiModel::new("dummy-key", ClaudeModel::Sonnet4_5);
     assert_eq!(m.model_name(), "claude-sonnet-4-5");
 
-    let m = ClaudeApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy-key".to_string(),
-        model: ClaudeModel::Opus4_6,
-    };
+    let m = ClaudeApiModel::new("dummy-key", ClaudeModel::Opus4_6);
     assert_eq!(m.model_name(), "claude-opus-4-6");
 
-    let m = ClaudeApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy-key".to_string(),
-        model: ClaudeModel::Opus4_7,
-    };
+    let m = ClaudeApiModel::new("dummy-key", ClaudeModel::Opus4_7);
     assert_eq!(m.model_name(), "claude-opus-4-7");
 }
+
+#[test]
+fn test_max_tokens_precedence_request_then_model_then_crate() {
+    use crate::claude::{base::ClaudeClient, types::DEFAULT_MAX_TOKENS};
+    use crate::client::{ModelDefaults, Settings};
+
+    let max_tokens = |defaults: Option<ModelDefaults>, settings: Option<Settings>| {
+        let m = ClaudeApiModel {
+            defaults,
+            ..ClaudeApiModel::new("dummy-key", ClaudeModel::Sonnet4_5)
+        };
+        let mut builder = m.new_request();
+        builder.with_message(Message::user("hi".to_string()));
+        if let Some(settings) = settings {
+            builder.with_settings(settings);
+        }
+        let json =
+            serde_json::to_value(m.create_request_body(builder.to_model_request(), false)).unwrap();
+        json["max_tokens"].clone()
+    };
+    let model_defaults = || {
+        Some(ModelDefaults {
+            max_tokens: Some(1000),
+            ..Default::default()
+        })
+    };
+    let request_settings = Some(Settings {
+        max_tokens: Some(200),
+        ..Default::default()
+    });
+
+    assert_eq!(max_tokens(model_defaults(), request_settings), 200);
+    assert_eq!(max_tokens(model_defaults(), None), 1000);
+    assert_eq!(max_tokens(None, None), DEFAULT_MAX_TOKENS);
+}
+
+fn tool_choice_for(mode: crate::client::FunctionCallingMode) -> serde_json::Value {
+    use crate::claude::base::ClaudeClient;
+
+    let m = ClaudeApiModel::new("dummy-key", ClaudeModel::Sonnet4_5);
+    let request = m
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .with_tool(Tool::new("get_weather", "Get the weather"))
+        .with_function_calling_mode(mode)
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request, false)).unwrap();
+    json.get("tool_choice").cloned().unwrap()
+}
+
+#[test]
+fn test_text_and_function_call_turn_is_sent_as_one_assistant_message() {
+    use crate::claude::base::ClaudeClient;
+    use crate::client::{Completion, FunctionCall};
+
+    let completion = Completion {
+        completion: "Let me check.".to_string(),
+        function: Some(FunctionCall {
+            name: "get_weather".to_string(),
+            args: std::collections::HashMap::from([(
+                "city".to_string(),
+                serde_json::json!("Paris"),
+            )]),
+            id: Some("toolu_1".to_string()),
+        }),
+        ..Default::default()
+    };
+    let m = ClaudeApiModel::new("dummy-key", ClaudeModel::Sonnet4_5);
+    let request = m
+        .new_request()
+        .with_message(Message::user("weather in Paris?".to_string()))
+        .with_message(Message::from_completion(&completion))
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request, false)).unwrap();
+
+    assert_eq!(
+        json["messages"][1],
+        serde_json::json!({
+            "role": "assistant",
+            "content": [
+                { "type": "text", "text": "Let me check." },
+                { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": { "city": "Paris" } }
+            ]
+        })
+    );
+}
+
+#[test]
+fn test_function_calling_mode_maps_to_claude_tool_choice() {
+    use crate::client::FunctionCallingMode;
+
+    assert_eq!(
+        tool_choice_for(FunctionCallingMode::Auto),
+        serde_json::json!({ "type": "auto" })
+    );
+    assert_eq!(
+        tool_choice_for(FunctionCallingMode::Any),
+        serde_json::json!({ "type": "any" })
+    );
+    assert_eq!(
+        tool_choice_for(FunctionCallingMode::None),
+        serde_json::json!({ "type": "none" })
+    );
+    assert_eq!(
+        tool_choice_for(FunctionCallingMode::Function("get_weather".to_string())),
+        serde_json::json!({ "type": "tool", "name": "get_weather" })
+    );
+}
+
+// Sends every request to a local mock server, keeping its path.
+struct ToMockServer(String);
+
+#[async_trait::async_trait]
+impl crate::client::HttpTransport for ToMockServer {
+    async fn execute(
+        &self,
+        mut request: reqwest::Request,
+    ) -> Result<reqwest::Response, Box<dyn std::error::Error + Send + Sync>> {
+        let url = format!("{}{}", self.0, request.url().path());
+        *request.url_mut() = url.parse()?;
+        Ok(reqwest::Client::new().execute(request).await?)
+    }
+}
+
+#[tokio::test]
+async fn test_stream_announces_function_call_before_its_arguments() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![MockResponse::sse(&[
+        r#"{"type":"message_start","message":{"usage":{"input_tokens":12,"output_tokens":1}}}"#,
+        r#"{"type":"content_block_start","index":0,"content_block":{"type":"tool_use","id":"toolu_01","name":"get_weather","input":{}}}"#,
+        r#"{"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"{\"city\": "}}"#,
+        r#"{"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"\"Paris\"}"}}"#,
+        r#"{"type":"content_block_stop","index":0}"#,
+        r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":9}}"#,
+        r#"{"type":"message_stop"}"#,
+    ])])
+    .await;
+    let m = ClaudeApiModel::new("dummy-key", ClaudeModel::Sonnet4_5)
+        .with_transport(Arc::new(ToMockServer(server.url.clone())));
+
+    let mut stream = m
+        .new_request()
+        .with_message(Message::user("Weather in Paris?".to_string()))
+        .with_tool(Tool::new("get_weather", "Get the weather for a city"))
+        .stream()
+        .await
+        .unwrap();
+    let mut events = vec![];
+    while let Some(event) = stream.next().await {
+        events.push(event);
+    }
+
+    assert!(matches!(
+        &events[0],
+        StreamEvent::FunctionCallStarted { name, id }
+            if name == "get_weather" && id.as_deref() == Some("toolu_01")
+    ));
+    match &events[1] {
+        StreamEvent::FunctionCall(call) => {
+            assert_eq!(call.name, "get_weather");
+            assert_eq!(call.args["city"], "Paris");
+            assert_eq!(call.id.as_deref(), Some("toolu_01"));
+        }
+        other => panic!("expected the completed call, got {:?}", other),
+    }
+    assert!(events[2].is_final());
+}
+
+#[tokio::test]
+async fn test_fallback_model_falls_back_after_claude_rate_limit() {
+    use crate::client::FallbackModel;
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![
+        MockResponse::json(429, r#"{"type":"error","error":{"type":"rate_limit_error"}}"#),
+        MockResponse::json(
+            200,
+            r#"{"content":[{"type":"text","text":"hello"}],"usage":{"input_tokens":3,"output_tokens":1}}"#,
+        ),
+    ])
+    .await;
+    let model_at = |model| {
+        ClaudeApiModel::new("dummy-key", model)
+            .with_transport(Arc::new(ToMockServer(server.url.clone())))
+    };
+    let model = FallbackModel::new(vec![
+        Box::new(model_at(ClaudeModel::Opus4_7)),
+        Box::new(model_at(ClaudeModel::Sonnet4_5)),
+    ]);
+
+    let completion = model
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .completion()
+        .await
+        .expect("the second model should answer");
+
+    assert_eq!(completion.completion, "hello");
+    assert_eq!(completion.model, "claude-sonnet-4-5");
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_gcs_file_uris_are_rejected() {
+    use crate::client::LangrustError;
+
+    let m = ClaudeApiModel::new("dummy-key", ClaudeModel::Sonnet4_5);
+    let err = m
+        .new_request()
+        .with_message(
+            Message::user("Summarize".to_string())
+                .with_file_uri("gs://bucket/report.pdf", "application/pdf"),
+        )
+        .completion()
+        .await
+        .expect_err("gs:// is Gemini only");
+
+    assert_eq!(
+        err.downcast_ref::<LangrustError>(),
+        Some(&LangrustError::InvalidRequest(
+            "Claude can't fetch gs:// file URIs, only https:// URLs; got `gs://bucket/report.pdf`"
+                .to_string()
+        ))
+    );
+}
+
+#[tokio::test]
+async fn test_requests_go_through_the_transport_when_set() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"content":[{"type":"text","text":"hello"}],"usage":{"input_tokens":3,"output_tokens":1}}"#,
+    )])
+    .await;
+    let m = ClaudeApiModel::new("dummy-key", ClaudeModel::Sonnet4_5)
+        .with_transport(Arc::new(ToMockServer(server.url.clone())));
+
+    let completion = m
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .completion()
+        .await
+        .unwrap();
+
+    assert_eq!(completion.completion, "hello");
+    let requests = server.requests();
+    assert_eq!(requests[0].path, "/v1/messages");
+    assert_eq!(requests[0].header("x-api-key"), Some("dummy-key"));
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::client::Tool;
+use crate::client::{ContentPart, FunctionCallingMode, Tool, encode_base64};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -58,6 +58,51 @@ pub enum ContentBlock {
         tool_use_id: String,
         content: String,
     },
+    Image {
+        source: MediaSource,
+    },
+    Document {
+        source: MediaSource,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MediaSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+impl ContentBlock {
+    /// Images go in `image` blocks; everything else (PDFs) in `document`.
+    /// Function calls become `tool_use` blocks.
+    pub fn from_part(part: ContentPart) -> ContentBlock {
+        let (mime_type, source) = match part {
+            ContentPart::Text(text) => return ContentBlock::Text { text },
+            ContentPart::FunctionCall(fc) => {
+                return ContentBlock::ToolUse {
+                    id: fc.id.unwrap_or_else(|| synth_tool_use_id(&fc.name)),
+                    name: fc.name,
+                    input: fc.args,
+                };
+            }
+            ContentPart::InlineData { mime_type, data } => {
+                let source = MediaSource::Base64 {
+                    media_type: mime_type.clone(),
+                    data: encode_base64(&data),
+                };
+                (mime_type, source)
+            }
+            ContentPart::FileData {
+                mime_type,
+                file_uri,
+            } => (mime_type, MediaSource::Url { url: file_uri }),
+        };
+        match mime_type.starts_with("image/") {
+            true => ContentBlock::Image { source },
+            false => ContentBlock::Document { source },
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -108,6 +153,26 @@ impl ClaudeTool {
     }
 }
 
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClaudeToolChoice {
+    Auto,
+    Any,
+    None,
+    Tool { name: String },
+}
+
+impl ClaudeToolChoice {
+    pub fn from_mode(mode: &FunctionCallingMode) -> ClaudeToolChoice {
+        match mode {
+            FunctionCallingMode::Auto => ClaudeToolChoice::Auto,
+            FunctionCallingMode::Any => ClaudeToolChoice::Any,
+            FunctionCallingMode::None => ClaudeToolChoice::None,
+            FunctionCallingMode::Function(name) => ClaudeToolChoice::Tool { name: name.clone() },
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct ClaudeRequest {
     pub model: String,
@@ -118,8 +183,12 @@ pub struct ClaudeRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<ClaudeTool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ClaudeToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking: Option<ThinkingConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
@@ -134,7 +203,6 @@ pub enum ResponseBlock {
         text: String,
     },
     ToolUse {
-        #[allow(dead_code)]
         id: String,
         name: String,
         input: HashMap<String, Value>,
@@ -151,6 +219,10 @@ pub struct ClaudeUsage {
 
 #[derive(Debug, Deserialize)]
 pub struct ClaudeResponse {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
     pub content: Vec<ResponseBlock>,
     pub usage: ClaudeUsage,
     #[allow(dead_code)]
@@ -212,7 +284,6 @@ pub enum StreamContentBlock {
         text: String,
     },
     ToolUse {
-        #[allow(dead_code)]
         id: String,
         name: String,
         // `input` is streamed as `input_json_delta`s; starts empty.
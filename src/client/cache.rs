@@ -0,0 +1,131 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use serde_json::json;
+use tokio::time::Instant;
+
+use super::{Completion, LangrustError, ModelRequest};
+
+/// In-memory cache of completions for deterministic requests, so an identical
+/// call is answered without hitting the API again.
+///
+/// Attach it to a model with the model's `with_cache`;
+/// `ModelRequestBuilder::completion` then looks up every request with a
+/// temperature of `0` and stores what it returns. Only the builder consults
+/// the cache: direct `Model::completion` calls always reach the API. Other
+/// requests, streams, and requests with a `before_send` hook are never
+/// cached. Holds up to `capacity` completions, evicting the least recently
+/// used, and each is served for at most `ttl` after it was stored.
+#[derive(Debug)]
+pub struct CompletionCache {
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    // Bumped on every use; the entry with the lowest `last_used` is evicted.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    completion: Completion,
+    stored: Instant,
+    last_used: u64,
+}
+
+impl CompletionCache {
+    /// Fails with `LangrustError::InvalidRequest` if `capacity` is `0`, as
+    /// such a cache could hold nothing.
+    pub fn new(capacity: usize, ttl: Duration) -> Result<CompletionCache, LangrustError> {
+        if capacity == 0 {
+            return Err(LangrustError::InvalidRequest(
+                "cache capacity must be non-zero".to_string(),
+            ));
+        }
+        Ok(CompletionCache {
+            capacity,
+            ttl,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Completions currently stored, expired ones included until they are
+    /// looked up or evicted.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+
+    // The key `request` to `model` is cached under, or `None` if it isn't
+    // deterministic and mustn't be cached.
+    pub(crate) fn key(model: &str, request: &ModelRequest) -> Option<String> {
+        let temperature = request.settings.as_ref().and_then(|s| s.temperature);
+        if temperature != Some(0) || request.before_send.is_some() {
+            return None;
+        }
+        Some(
+            json!({
+                "model": model,
+                "system": request.system,
+                "messages": request.messages,
+                "settings": request.settings,
+                "tools": request.tools,
+                "function_calling_mode": format!("{:?}", request.function_calling_mode),
+                "response_schema": request.response_schema,
+                "enum_response": request.enum_response,
+                "logprobs": request.logprobs,
+                "response_modalities": format!("{:?}", request.response_modalities),
+                "safety_settings": format!("{:?}", request.safety_settings),
+                "extra_generation_config": request.extra_generation_config,
+            })
+            .to_string(),
+        )
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Completion> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(key)?;
+        if entry.stored.elapsed() >= self.ttl {
+            state.entries.remove(key);
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.completion.clone())
+    }
+
+    pub(crate) fn insert(&self, key: String, completion: Completion) {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.capacity {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+        state.entries.insert(
+            key,
+            Entry {
+                completion,
+                stored: Instant::now(),
+                last_used: clock,
+            },
+        );
+    }
+}
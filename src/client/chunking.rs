@@ -0,0 +1,152 @@
+use std::ops::Range;
+
+use super::LangrustError;
+
+/// Estimated token count of `text`, at four bytes per token rounded up. The
+/// rate limiter and `with_max_stream_tokens` count tokens the same way.
+pub fn estimate_text_tokens(text: &str) -> usize {
+    estimate_tokens_for_len(text.len())
+}
+
+// `estimate_text_tokens` of a text `len` bytes long, for callers that only
+// keep a running length.
+pub(crate) fn estimate_tokens_for_len(len: usize) -> usize {
+    len.div_ceil(4)
+}
+
+/// Splits `text` into chunks of at most `max_tokens` estimated tokens each, for
+/// documents that don't fit in one request (e.g. map-reduce summarization).
+///
+/// Chunks end at sentence or paragraph boundaries where possible, falling back
+/// to word boundaries for a sentence longer than a chunk and to a hard cut for
+/// a single oversized word. Each chunk after the first starts with up to
+/// `overlap` tokens of whole sentences (or words) from the end of the previous
+/// one, so context isn't lost at the seams. Chunks are trimmed of surrounding
+/// whitespace.
+///
+/// Fails with `LangrustError::InvalidRequest` if `max_tokens` is zero or
+/// `overlap` isn't below `max_tokens`.
+pub fn chunk_text(
+    text: &str,
+    max_tokens: usize,
+    overlap: usize,
+) -> Result<Vec<String>, LangrustError> {
+    if max_tokens == 0 {
+        return Err(LangrustError::InvalidRequest(
+            "max_tokens must be non-zero".to_string(),
+        ));
+    }
+    if overlap >= max_tokens {
+        return Err(LangrustError::InvalidRequest(
+            "overlap must be less than max_tokens".to_string(),
+        ));
+    }
+    let max_len = max_tokens * 4;
+    let overlap_len = overlap * 4;
+
+    let units = units(text, max_len);
+    // Text of units[from..to] as it would appear in a chunk.
+    let span = |from: usize, to: usize| text[units[from].start..units[to - 1].end].trim();
+
+    let mut chunks = Vec::new();
+    let mut first = 0;
+    while first < units.len() {
+        let mut end = first + 1;
+        while end < units.len() && span(first, end + 1).len() <= max_len {
+            end += 1;
+        }
+        let chunk = span(first, end);
+        if !chunk.is_empty() {
+            chunks.push(chunk.to_string());
+        }
+        if end == units.len() {
+            break;
+        }
+
+        // Start the next chunk with the tail of this one that fits in the
+        // overlap, shrunk if needed so the next unit still fits after it.
+        let mut next = end;
+        while next - 1 > first && span(next - 1, end).len() <= overlap_len {
+            next -= 1;
+        }
+        while next < end && span(next, end + 1).len() > max_len {
+            next += 1;
+        }
+        first = next;
+    }
+    Ok(chunks)
+}
+
+// Byte ranges covering `text` in order, each at most `max_len` long: sentences,
+// or the words (or slices) of sentences that are too long.
+fn units(text: &str, max_len: usize) -> Vec<Range<usize>> {
+    let mut units = Vec::new();
+    for sentence in split_after(text, 0..text.len(), sentence_end) {
+        if sentence.len() <= max_len {
+            units.push(sentence);
+            continue;
+        }
+        for word in split_after(text, sentence, |_, c, _| c.is_whitespace()) {
+            if word.len() <= max_len {
+                units.push(word);
+                continue;
+            }
+            let mut start = word.start;
+            while start < word.end {
+                let mut end = (start + max_len).min(word.end);
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                units.push(start..end);
+                start = end;
+            }
+        }
+    }
+    units
+}
+
+// A sentence ends at `.`, `!` or `?` followed by whitespace, or at a blank
+// line.
+fn sentence_end(prev: Option<char>, c: char, next: Option<char>) -> bool {
+    let next_is_space = next.is_some_and(char::is_whitespace);
+    match c {
+        '.' | '!' | '?' => next_is_space,
+        '\n' => prev == Some('\n'),
+        _ => false,
+    }
+}
+
+// Splits `range` of `text` after every character where `boundary` holds,
+// keeping the whitespace that follows with the piece before it.
+fn split_after(
+    text: &str,
+    range: Range<usize>,
+    boundary: impl Fn(Option<char>, char, Option<char>) -> bool,
+) -> Vec<Range<usize>> {
+    let slice = &text[range.clone()];
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut prev = None;
+    let mut chars = slice.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, n)| n);
+        if boundary(prev, c, next) {
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, n)) = chars.peek()
+                && n.is_whitespace()
+            {
+                end = j + n.len_utf8();
+                chars.next();
+            }
+            pieces.push(range.start + start..range.start + end);
+            start = end;
+            prev = None;
+            continue;
+        }
+        prev = Some(c);
+    }
+    if start < slice.len() {
+        pieces.push(range.start + start..range.end);
+    }
+    pieces
+}
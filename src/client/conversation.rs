@@ -0,0 +1,89 @@
+use std::{error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Completion, LangrustError, Message, MessageType, Model, ModelRequestBuilder, Role, Settings,
+};
+
+/// A chat session that can be persisted and resumed: the system prompt, the
+/// full message history and the settings it was run with.
+///
+/// Serializes to plain JSON; function calls and function results keep their
+/// `message_type`, so a restored history replays exactly as it was sent.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Conversation {
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub settings: Option<Settings>,
+}
+
+impl Conversation {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Conversation, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Writes the conversation to `path` as JSON, replacing any existing file.
+    pub fn save_json(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn load_json(path: impl AsRef<Path>) -> Result<Conversation, Box<dyn Error + Send + Sync>> {
+        Ok(Conversation::from_json(&fs::read_to_string(path)?)?)
+    }
+
+    /// Replaces the user message at `index` with `new_content`, drops every
+    /// turn after it, and sends the history to `model` again, as a chat UI
+    /// does when a user edits an earlier message. The model's answer is
+    /// appended and returned.
+    ///
+    /// The edited message becomes plain text; anything attached to it is
+    /// dropped. Fails with `LangrustError::InvalidRequest`, leaving the
+    /// conversation untouched, if `index` is out of range or isn't a user's
+    /// text message. If the request fails, the history ends at the edited
+    /// message, ready to be sent again.
+    pub async fn edit_and_regenerate(
+        &mut self,
+        model: &dyn Model,
+        index: usize,
+        new_content: String,
+    ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
+        let Some(message) = self.messages.get(index) else {
+            return Err(LangrustError::InvalidRequest(format!(
+                "cannot edit message {}: the conversation has {} messages",
+                index,
+                self.messages.len()
+            ))
+            .into());
+        };
+        if message.role != Some(Role::User) || message.message_type != MessageType::Text {
+            return Err(LangrustError::InvalidRequest(format!(
+                "cannot edit message {}: only a user's text message can be edited",
+                index
+            ))
+            .into());
+        }
+        self.messages.truncate(index);
+        self.messages.push(Message::user(new_content));
+
+        let mut builder = ModelRequestBuilder::new(model);
+        builder.with_messages(self.messages.clone());
+        if let Some(system) = &self.system {
+            builder.with_system(system.clone());
+        }
+        if let Some(settings) = &self.settings {
+            builder.with_settings(settings.clone());
+        }
+        let completion = builder.completion().await?;
+        self.messages.push(Message::from_completion(&completion));
+        Ok(completion)
+    }
+}
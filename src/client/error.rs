@@ -0,0 +1,241 @@
+use std::{error::Error, fmt, time::Duration};
+
+use super::{SafetyRatings, Usage, transport::read_capped};
+
+/// Errors raised by langrust itself, as opposed to transport or decoding
+/// errors bubbled up from `reqwest`/`serde`.
+///
+/// They are returned boxed through the usual
+/// `Box<dyn Error + Send + Sync>`; use `downcast_ref::<LangrustError>()` to
+/// match on them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LangrustError {
+    /// The request was rejected locally, before anything was sent.
+    InvalidRequest(String),
+    /// The inline data attached to the request exceeds what the provider
+    /// accepts in a single request. Sizes are in bytes, base64-encoded.
+    RequestTooLarge { bytes: usize, limit: usize },
+    /// The provider refused the prompt itself, e.g. Gemini's
+    /// `promptFeedback.blockReason`. No content was generated, but the
+    /// prompt may still be billed: `usage` is what the provider reported.
+    PromptBlocked {
+        reason: String,
+        safety_ratings: SafetyRatings,
+        usage: Option<Usage>,
+    },
+    /// The credentials are unusable, caught before anything was sent, e.g. an
+    /// empty API key.
+    Auth(String),
+    /// The request failed in transport or was rejected by the provider. Only
+    /// returned where a method yields `LangrustError` directly, e.g.
+    /// `completion_json`; elsewhere the underlying error is returned as is.
+    Request(String),
+    /// The provider answered, but not with what was asked for, e.g. text that
+    /// doesn't parse as the requested type.
+    InvalidResponse(String),
+    /// The response is JSON but doesn't match the requested schema: the value
+    /// at `path` (e.g. `$.address.zip`) isn't `expected`.
+    SchemaMismatch { path: String, expected: String },
+    /// The provider, or a proxy in front of it, answered with a non-success
+    /// status. `body` holds the response bytes as received, undecoded;
+    /// `body_kind` tells a provider's JSON error apart from a gateway's HTML
+    /// page or a body that isn't text at all.
+    ApiError {
+        status: u16,
+        content_type: Option<String>,
+        body_kind: ErrorBodyKind,
+        body: Vec<u8>,
+    },
+    /// The response body passed the `limit` in bytes set with
+    /// `ModelRequestBuilder::with_max_response_bytes`, and was not read
+    /// further.
+    ResponseTooLarge { limit: usize },
+    /// Every model of a `FallbackModel` failed with an error worth falling
+    /// back on: the name of each model tried, in order, with its error.
+    AllModelsFailed { errors: Vec<(String, String)> },
+    /// A stream sent no event within the `timeout` set with
+    /// `ModelRequestBuilder::with_first_token_timeout`.
+    FirstTokenTimeout { timeout: Duration },
+    /// A stream ran past the `limit` set with
+    /// `ModelRequestBuilder::with_max_stream_duration`. Arrives as the
+    /// stream's closing `StreamEvent::Error`.
+    StreamTimeout { limit: Duration },
+}
+
+/// What the body of an error response holds, judged from its `Content-Type`
+/// and its bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorBodyKind {
+    Json,
+    Html,
+    Text,
+    /// Not valid UTF-8, e.g. a compressed body the client didn't decode.
+    Binary,
+}
+
+impl fmt::Display for ErrorBodyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorBodyKind::Json => "JSON",
+            ErrorBodyKind::Html => "HTML",
+            ErrorBodyKind::Text => "text",
+            ErrorBodyKind::Binary => "binary",
+        })
+    }
+}
+
+impl ErrorBodyKind {
+    pub(crate) fn detect(content_type: Option<&str>, body: &[u8]) -> ErrorBodyKind {
+        let Ok(text) = std::str::from_utf8(body) else {
+            return ErrorBodyKind::Binary;
+        };
+        let content_type = content_type.unwrap_or_default().to_ascii_lowercase();
+        let start = text.trim_start().to_ascii_lowercase();
+        if content_type.contains("json") || start.starts_with('{') || start.starts_with('[') {
+            ErrorBodyKind::Json
+        } else if content_type.contains("html")
+            || start.starts_with("<!doctype html")
+            || start.starts_with("<html")
+        {
+            ErrorBodyKind::Html
+        } else {
+            ErrorBodyKind::Text
+        }
+    }
+}
+
+// Longest preview of an error body shown in `LangrustError::ApiError`'s
+// message: characters of a text body, bytes of a binary one.
+const TEXT_PREVIEW_CHARS: usize = 1000;
+const BINARY_PREVIEW_BYTES: usize = 32;
+
+// The start of an error body: as text when it is text, as hex otherwise.
+fn body_preview(kind: ErrorBodyKind, body: &[u8]) -> String {
+    if kind == ErrorBodyKind::Binary {
+        let hex: Vec<String> = body
+            .iter()
+            .take(BINARY_PREVIEW_BYTES)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let more = if body.len() > BINARY_PREVIEW_BYTES {
+            " .."
+        } else {
+            ""
+        };
+        return format!("{} bytes: {}{}", body.len(), hex.join(" "), more);
+    }
+    let text = String::from_utf8_lossy(body);
+    let text = text.trim();
+    match text.char_indices().nth(TEXT_PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Reads a non-success response into `LangrustError::ApiError`, keeping at
+/// most `limit` bytes of its body. Fails with the transport error if the
+/// body can't be read.
+pub(crate) async fn api_error(
+    response: reqwest::Response,
+    limit: Option<usize>,
+) -> Result<LangrustError, Box<dyn Error + Send + Sync>> {
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let (body, _) = read_capped(response, limit).await?;
+    Ok(LangrustError::ApiError {
+        status,
+        body_kind: ErrorBodyKind::detect(content_type.as_deref(), &body),
+        content_type,
+        body,
+    })
+}
+
+impl fmt::Display for LangrustError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LangrustError::InvalidRequest(msg) => write!(f, "invalid request: {}", msg),
+            LangrustError::RequestTooLarge { bytes, limit } => write!(
+                f,
+                "request too large: {} bytes of inline data exceeds the {} byte limit; \
+                 upload large files through the File API instead",
+                bytes, limit
+            ),
+            LangrustError::PromptBlocked { reason, .. } => {
+                write!(f, "prompt blocked by the provider: {}", reason)
+            }
+            LangrustError::Auth(msg) => write!(f, "authentication error: {}", msg),
+            LangrustError::Request(msg) => write!(f, "request failed: {}", msg),
+            LangrustError::InvalidResponse(msg) => write!(f, "invalid response: {}", msg),
+            LangrustError::SchemaMismatch { path, expected } => write!(
+                f,
+                "response does not match the schema at `{}`: expected {}",
+                path, expected
+            ),
+            LangrustError::ApiError {
+                status,
+                content_type,
+                body_kind,
+                body,
+            } => {
+                write!(
+                    f,
+                    "request failed with status {} ({} body",
+                    status, body_kind
+                )?;
+                if let Some(content_type) = content_type {
+                    write!(f, ", {}", content_type)?;
+                }
+                write!(f, "): {}", body_preview(*body_kind, body))
+            }
+            LangrustError::ResponseTooLarge { limit } => write!(
+                f,
+                "response too large: the body exceeds the {} byte limit",
+                limit
+            ),
+            LangrustError::AllModelsFailed { errors } => {
+                write!(f, "all models failed")?;
+                for (i, (model, error)) in errors.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { "; " };
+                    write!(f, "{}{}: {}", separator, model, error)?;
+                }
+                Ok(())
+            }
+            LangrustError::FirstTokenTimeout { timeout } => {
+                write!(f, "stream timed out: no event within {:?}", timeout)
+            }
+            LangrustError::StreamTimeout { limit } => {
+                write!(f, "stream timed out: still running after {:?}", limit)
+            }
+        }
+    }
+}
+
+impl LangrustError {
+    /// Whether the same request could succeed if sent again or to another
+    /// model: an `ApiError` for a timeout (408), rate limit (429) or server
+    /// error (5xx). Errors in the request itself never are.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LangrustError::ApiError { status, .. } => {
+                matches!(status, 408 | 429) || (500..600).contains(status)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Error for LangrustError {}
+
+impl From<Box<dyn Error + Send + Sync>> for LangrustError {
+    /// Unwraps a boxed `LangrustError`; any other error becomes `Request`.
+    fn from(e: Box<dyn Error + Send + Sync>) -> LangrustError {
+        match e.downcast::<LangrustError>() {
+            Ok(e) => *e,
+            Err(e) => LangrustError::Request(e.to_string()),
+        }
+    }
+}
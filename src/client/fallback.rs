@@ -0,0 +1,89 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+
+use super::{
+    Completion, CompletionsFuture, LangrustError, Model, ModelRequest, StreamResult, retry,
+};
+
+/// A `Model` that tries each of `models` in order until one succeeds, e.g.
+/// a Pro model, then a Flash model, then another provider.
+///
+/// It only moves on after a failure another model might not have: a
+/// transport error or a `LangrustError` whose `is_retryable()` is true, such
+/// as a rate limit or server error. Any other error, e.g. an invalid request,
+/// is returned straight away. When every model fails, the error is
+/// `LangrustError::AllModelsFailed` with each model's error.
+///
+/// Each model fills in its own `defaults`; streams fall back only while
+/// opening, not once events have started.
+pub struct FallbackModel {
+    pub models: Vec<Box<dyn Model + Send + Sync>>,
+}
+
+impl FallbackModel {
+    pub fn new(models: Vec<Box<dyn Model + Send + Sync>>) -> FallbackModel {
+        FallbackModel { models }
+    }
+}
+
+#[async_trait]
+impl Model for FallbackModel {
+    async fn completion(
+        &self,
+        request: ModelRequest,
+    ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
+        let mut errors = Vec::new();
+        for model in &self.models {
+            match model.completion(request.for_model(&**model)).await {
+                Err(e) if retry::is_retryable(&*e) => {
+                    errors.push((model.model_name(), e.to_string()))
+                }
+                result => return result,
+            }
+        }
+        Err(LangrustError::AllModelsFailed { errors }.into())
+    }
+
+    async fn stream_completion(
+        &self,
+        request: ModelRequest,
+    ) -> Result<StreamResult, Box<dyn Error + Send + Sync>> {
+        let mut errors = Vec::new();
+        for model in &self.models {
+            match model.stream_completion(request.for_model(&**model)).await {
+                Err(e) if retry::is_retryable(&*e) => {
+                    errors.push((model.model_name(), e.to_string()))
+                }
+                result => return result,
+            }
+        }
+        Err(LangrustError::AllModelsFailed { errors }.into())
+    }
+
+    fn completions(&self, request: ModelRequest, n: u32) -> CompletionsFuture<'_> {
+        Box::pin(async move {
+            let mut errors = Vec::new();
+            for model in &self.models {
+                match model.completions(request.for_model(&**model), n).await {
+                    Err(e) if retry::is_retryable(&*e) => {
+                        errors.push((model.model_name(), e.to_string()))
+                    }
+                    result => return result,
+                }
+            }
+            Err(LangrustError::AllModelsFailed { errors }.into())
+        })
+    }
+
+    /// The names of the models, in the order they are tried.
+    fn model_name(&self) -> String {
+        let names: Vec<String> = self.models.iter().map(|m| m.model_name()).collect();
+        names.join(" -> ")
+    }
+
+    /// Only when every model can, since any of them may end up answering.
+    fn supports_tools(&self) -> bool {
+        self.models.iter().all(|m| m.supports_tools())
+    }
+}
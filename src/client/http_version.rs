@@ -0,0 +1,32 @@
+/// Which HTTP version the model's `reqwest::Client` speaks, set with the
+/// model's `with_http_version`. Set once, when the client is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HttpVersionPref {
+    /// HTTP/2 where the server offers it during the TLS handshake, HTTP/1.1
+    /// otherwise.
+    #[default]
+    Auto,
+    /// HTTP/1.1 only, for proxies that break on HTTP/2.
+    Http1Only,
+    /// HTTP/2 from the first byte, without negotiation. Fails against
+    /// servers that only speak HTTP/1.1.
+    Http2Prior,
+}
+
+impl HttpVersionPref {
+    /// Applies the preference to `builder`. Combines with
+    /// `HttpTimeouts::apply` when both are wanted.
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        match self {
+            HttpVersionPref::Auto => builder,
+            HttpVersionPref::Http1Only => builder.http1_only(),
+            HttpVersionPref::Http2Prior => builder.http2_prior_knowledge(),
+        }
+    }
+
+    /// A client with this preference and reqwest's defaults otherwise, ready
+    /// to pass as a model's `client`.
+    pub fn client(&self) -> Result<reqwest::Client, reqwest::Error> {
+        self.apply(reqwest::Client::builder()).build()
+    }
+}
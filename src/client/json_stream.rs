@@ -0,0 +1,199 @@
+use std::pin::Pin;
+
+use futures::{Stream, stream};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{CompletionStream, StreamEvent};
+
+/// Events yielded by `ModelRequestBuilder::json_stream`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonStreamEvent<T> {
+    /// The JSON received so far, with unfinished strings cut where the text
+    /// ends and containers closed. Fields appear once their value has started
+    /// (strings) or finished (numbers, booleans, nested values). Only sent when
+    /// it differs from the previous state.
+    Partial(Value),
+    /// The whole response, parsed as `T`. Always the last event on success.
+    Complete(T),
+    Error(String),
+}
+
+pub type JsonStream<T> = Pin<Box<dyn Stream<Item = JsonStreamEvent<T>> + Send>>;
+
+pub(crate) fn json_events<T: DeserializeOwned + Send + 'static>(
+    events: CompletionStream,
+) -> JsonStream<T> {
+    let state = (events, String::new(), None::<Value>, false);
+    Box::pin(stream::unfold(
+        state,
+        |(mut events, mut text, mut last, done)| async move {
+            if done {
+                return None;
+            }
+            loop {
+                match events.next().await {
+                    Some(StreamEvent::Delta(delta)) => {
+                        text.push_str(&delta);
+                        if let Some(partial) = parse_partial(&text)
+                            && last.as_ref() != Some(&partial)
+                        {
+                            last = Some(partial.clone());
+                            let event = JsonStreamEvent::Partial(partial);
+                            return Some((event, (events, text, last, false)));
+                        }
+                    }
+                    Some(StreamEvent::Error(e)) => {
+                        return Some((JsonStreamEvent::Error(e), (events, text, last, true)));
+                    }
+                    Some(StreamEvent::Truncated(tokens)) => {
+                        let error = format!("stream truncated after about {} tokens", tokens);
+                        return Some((JsonStreamEvent::Error(error), (events, text, last, true)));
+                    }
+                    Some(StreamEvent::Cancelled) => {
+                        let error = "stream was cancelled".to_string();
+                        return Some((JsonStreamEvent::Error(error), (events, text, last, true)));
+                    }
+                    Some(StreamEvent::Filtered { finish_reason, .. }) => {
+                        let error = format!("stream stopped by the provider: {}", finish_reason);
+                        return Some((JsonStreamEvent::Error(error), (events, text, last, true)));
+                    }
+                    Some(StreamEvent::Usage(_))
+                    | Some(StreamEvent::Stopped(_))
+                    | Some(StreamEvent::FunctionCallStarted { .. })
+                    | Some(StreamEvent::FunctionCall(_)) => {}
+                    None => {
+                        let event = match serde_json::from_str::<T>(&text) {
+                            Ok(value) => JsonStreamEvent::Complete(value),
+                            Err(e) => JsonStreamEvent::Error(e.to_string()),
+                        };
+                        return Some((event, (events, text, last, true)));
+                    }
+                }
+            }
+        },
+    ))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Frame {
+    // Expecting a key or `}`.
+    ObjectKey,
+    // Inside a key, or between a key and its value.
+    ObjectPendingValue,
+    // Expecting a value or `]`.
+    ArrayValue,
+    // After a value, expecting `,` or the closing bracket.
+    AfterValue(char),
+}
+
+/// Best-effort parse of a JSON document that may be cut off anywhere. Returns
+/// the value as of the last point where the text could be closed off validly,
+/// or `None` if there is no such point yet.
+pub(crate) fn parse_partial(text: &str) -> Option<Value> {
+    let trimmed = text.trim_start();
+    if !trimmed.starts_with(['{', '[']) {
+        return serde_json::from_str(trimmed).ok();
+    }
+
+    // Closing brackets of the frames on the stack, innermost last.
+    let closers = |stack: &[Frame]| -> String {
+        stack
+            .iter()
+            .rev()
+            .map(|f| match f {
+                Frame::ObjectKey | Frame::ObjectPendingValue => '}',
+                Frame::ArrayValue => ']',
+                Frame::AfterValue(c) => *c,
+            })
+            .collect()
+    };
+
+    let mut stack: Vec<Frame> = Vec::new();
+    // Prefix length and suffix that turn `text` into a complete document.
+    let mut safe: Option<(usize, String)> = None;
+    let mut in_string = false;
+    let mut in_key = false;
+    let mut escape = 0u8;
+    let mut in_scalar = false;
+
+    let value_done = |stack: &mut Vec<Frame>| {
+        if let Some(top) = stack.last_mut() {
+            *top = match top {
+                Frame::ArrayValue => Frame::AfterValue(']'),
+                _ => Frame::AfterValue('}'),
+            };
+        }
+    };
+
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        if in_string {
+            if escape > 0 {
+                escape = match (escape, c) {
+                    (1, 'u') => 4,
+                    _ => escape - 1,
+                };
+            } else if c == '\\' {
+                escape = 1;
+            } else if c == '"' {
+                in_string = false;
+                if in_key {
+                    in_key = false;
+                } else {
+                    value_done(&mut stack);
+                    safe = Some((end, closers(&stack)));
+                }
+                continue;
+            }
+            if !in_key && escape == 0 {
+                safe = Some((end, format!("\"{}", closers(&stack))));
+            }
+            continue;
+        }
+
+        if in_scalar && (c.is_whitespace() || matches!(c, ',' | '}' | ']')) {
+            in_scalar = false;
+            value_done(&mut stack);
+            safe = Some((i, closers(&stack)));
+        }
+
+        match c {
+            '{' => {
+                stack.push(Frame::ObjectKey);
+                safe = Some((end, closers(&stack)));
+            }
+            '[' => {
+                stack.push(Frame::ArrayValue);
+                safe = Some((end, closers(&stack)));
+            }
+            '}' | ']' => {
+                stack.pop();
+                if stack.is_empty() {
+                    return serde_json::from_str(&text[..end]).ok();
+                }
+                value_done(&mut stack);
+                safe = Some((end, closers(&stack)));
+            }
+            '"' => {
+                in_string = true;
+                in_key = stack.last() == Some(&Frame::ObjectKey);
+                if in_key {
+                    *stack.last_mut().unwrap() = Frame::ObjectPendingValue;
+                } else {
+                    safe = Some((end, format!("\"{}", closers(&stack))));
+                }
+            }
+            ',' => match stack.last_mut() {
+                Some(top @ Frame::AfterValue('}')) => *top = Frame::ObjectKey,
+                Some(top) => *top = Frame::ArrayValue,
+                None => {}
+            },
+            c if c.is_whitespace() || c == ':' => {}
+            _ => in_scalar = true,
+        }
+    }
+
+    let (len, suffix) = safe?;
+    serde_json::from_str(&format!("{}{}", &text[..len], suffix)).ok()
+}
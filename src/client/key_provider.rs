@@ -0,0 +1,76 @@
+use std::{error::Error, future::Future, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::Mutex, time::Instant};
+
+/// Source of API keys for deployments that hand out short-lived keys.
+///
+/// Consulted before every request, so an implementation can rotate keys (and
+/// cache them between rotations) without the model being rebuilt.
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+    async fn api_key(&self) -> Result<String, Box<dyn Error + Send + Sync>>;
+}
+
+/// Wraps a `KeyProvider` whose keys are slow or costly to fetch, reusing each
+/// key for `ttl`. When the cache is cold, concurrent requests share a single
+/// fetch instead of each starting their own. Failed fetches aren't cached.
+pub struct CachedKeyProvider {
+    inner: Arc<dyn KeyProvider>,
+    ttl: Duration,
+    cache: TokenCache,
+}
+
+impl CachedKeyProvider {
+    pub fn new(inner: Arc<dyn KeyProvider>, ttl: Duration) -> CachedKeyProvider {
+        CachedKeyProvider {
+            inner,
+            ttl,
+            cache: TokenCache::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl KeyProvider for CachedKeyProvider {
+    async fn api_key(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.cache
+            .get_or_fetch(self.ttl, || self.inner.api_key())
+            .await
+    }
+}
+
+// A token and when it was fetched, refreshed single-flight: the lock is held
+// across the fetch, so callers arriving meanwhile wait for it and then read
+// its result.
+pub(crate) struct TokenCache {
+    cached: Mutex<Option<(String, Instant)>>,
+}
+
+impl TokenCache {
+    pub(crate) const fn new() -> TokenCache {
+        TokenCache {
+            cached: Mutex::const_new(None),
+        }
+    }
+
+    pub(crate) async fn get_or_fetch<F, Fut>(
+        &self,
+        ttl: Duration,
+        fetch: F,
+    ) -> Result<String, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, Box<dyn Error + Send + Sync>>>,
+    {
+        let mut cached = self.cached.lock().await;
+        if let Some((token, fetched)) = cached.as_ref()
+            && fetched.elapsed() < ttl
+        {
+            return Ok(token.clone());
+        }
+        let token = fetch().await?;
+        *cached = Some((token.clone(), Instant::now()));
+        Ok(token)
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    error::Error,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use super::{Completion, CompletionsFuture, LangrustError, Model, ModelRequest, StreamResult};
+
+/// A `Model` that spreads requests round-robin over `models`, e.g. the same
+/// `GeminiApiModel` under several API keys, to raise the aggregate quota.
+///
+/// A model that answers with a rate limit (`ApiError` with status 429) is
+/// skipped for `cooldown`; if every model is cooling down, the rotation
+/// carries on regardless. Errors are returned as they are, not retried on
+/// another model; wrap it in a `FallbackModel` for that.
+pub struct LoadBalancedModel {
+    models: Vec<Box<dyn Model + Send + Sync>>,
+    cooldown: Duration,
+    next: AtomicUsize,
+    // When each model's rate limit cooldown ends, by index.
+    limited_until: Mutex<Vec<Option<Instant>>>,
+}
+
+impl LoadBalancedModel {
+    /// Fails with `LangrustError::InvalidRequest` if `models` is empty.
+    pub fn new(
+        models: Vec<Box<dyn Model + Send + Sync>>,
+        cooldown: Duration,
+    ) -> Result<LoadBalancedModel, LangrustError> {
+        if models.is_empty() {
+            return Err(LangrustError::InvalidRequest(
+                "LoadBalancedModel needs at least one model".to_string(),
+            ));
+        }
+        Ok(LoadBalancedModel {
+            limited_until: Mutex::new(vec![None; models.len()]),
+            models,
+            cooldown,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    // Index of the model the next request goes to: the next in rotation
+    // that isn't cooling down, or just the next if all are.
+    fn pick(&self) -> usize {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let limited_until = self.limited_until.lock().unwrap();
+        let now = Instant::now();
+        let count = self.models.len();
+        (0..count)
+            .map(|offset| (start + offset) % count)
+            .find(|&i| limited_until[i].is_none_or(|until| until <= now))
+            .unwrap_or(start % count)
+    }
+
+    fn record<T>(&self, index: usize, result: &Result<T, Box<dyn Error + Send + Sync>>) {
+        let rate_limited = result.as_ref().err().is_some_and(|e| {
+            matches!(
+                e.downcast_ref::<LangrustError>(),
+                Some(LangrustError::ApiError { status: 429, .. })
+            )
+        });
+        self.limited_until.lock().unwrap()[index] = match rate_limited {
+            true => Some(Instant::now() + self.cooldown),
+            false => None,
+        };
+    }
+}
+
+#[async_trait]
+impl Model for LoadBalancedModel {
+    async fn completion(
+        &self,
+        request: ModelRequest,
+    ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
+        let index = self.pick();
+        let model = &self.models[index];
+        let result = model.completion(request.for_model(&**model)).await;
+        self.record(index, &result);
+        result
+    }
+
+    async fn stream_completion(
+        &self,
+        request: ModelRequest,
+    ) -> Result<StreamResult, Box<dyn Error + Send + Sync>> {
+        let index = self.pick();
+        let model = &self.models[index];
+        let result = model.stream_completion(request.for_model(&**model)).await;
+        self.record(index, &result);
+        result
+    }
+
+    fn completions(&self, request: ModelRequest, n: u32) -> CompletionsFuture<'_> {
+        Box::pin(async move {
+            let index = self.pick();
+            let model = &self.models[index];
+            let result = model.completions(request.for_model(&**model), n).await;
+            self.record(index, &result);
+            result
+        })
+    }
+
+    /// The first model's name; the models are expected to be the same model
+    /// under different keys.
+    fn model_name(&self) -> String {
+        self.models[0].model_name()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.models.iter().all(|m| m.supports_tools())
+    }
+}
@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+
+use crate::client::{ContentPart, MessageType, ModelRequest, Role};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Controls how message content shows up in the `tracing` events emitted for
+/// each request. Set per request through `Settings::privacy_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PrivacyMode {
+    /// Full redaction: message bodies are never logged, only their length.
+    Full,
+    /// Message bodies are logged with e-mail addresses and long digit runs
+    /// (phone, card and account numbers) replaced by `[REDACTED]`.
+    #[default]
+    Redacted,
+    /// No redaction: message bodies are logged verbatim.
+    None,
+}
+
+impl PrivacyMode {
+    /// The form of `content` that may be logged under this mode, or `None`
+    /// if it must not be logged at all.
+    pub fn apply(&self, content: &str) -> Option<String> {
+        match self {
+            PrivacyMode::Full => None,
+            PrivacyMode::Redacted => Some(redact_pii(content)),
+            PrivacyMode::None => Some(content.to_string()),
+        }
+    }
+}
+
+/// Emits one `debug` event for the request and one `trace` event per message.
+pub(crate) fn log_request(provider: &str, model: &str, request: &ModelRequest) {
+    let mode = request
+        .settings
+        .as_ref()
+        .and_then(|s| s.privacy_mode)
+        .unwrap_or_default();
+    let messages = request.messages.as_deref().unwrap_or_default();
+
+    tracing::debug!(
+        provider,
+        model,
+        messages = messages.len(),
+        tools = request.tools.as_ref().map_or(0, |ts| ts.len()),
+        "sending request"
+    );
+
+    for (index, message) in messages.iter().enumerate() {
+        let role = match message.role {
+            Some(Role::Model) => "model",
+            _ => "user",
+        };
+        match mode.apply(&message.content) {
+            Some(content) => tracing::trace!(provider, index, role, content = %content, "message"),
+            None => tracing::trace!(
+                provider,
+                index,
+                role,
+                content_len = message.content.len(),
+                "message"
+            ),
+        }
+    }
+}
+
+// Longest excerpt of a message or system prompt in a request summary.
+const SUMMARY_EXCERPT_CHARS: usize = 80;
+
+/// See `ModelRequest::debug_summary`.
+pub(crate) fn summarize_request(request: &ModelRequest) -> String {
+    let mode = request
+        .settings
+        .as_ref()
+        .and_then(|s| s.privacy_mode)
+        .unwrap_or_default();
+    let messages = request.messages.as_deref().unwrap_or_default();
+    let mut lines = vec![];
+
+    if let Some(system) = &request.system {
+        lines.push(format!("system: {}", excerpt(mode, system)));
+    }
+    lines.push(format!("messages: {}", messages.len()));
+    for (index, message) in messages.iter().enumerate() {
+        let role = match message.role {
+            Some(Role::Model) => "model",
+            _ => "user",
+        };
+        let body = match &message.message_type {
+            MessageType::Text => excerpt(mode, &message.content),
+            MessageType::FunctionCall(call) => format!("function call `{}`", call.name),
+            MessageType::FunctionResponse { name, .. } => format!("function result `{}`", name),
+        };
+        let attachments = message
+            .parts
+            .iter()
+            .filter(|p| !matches!(p, ContentPart::Text(_) | ContentPart::FunctionCall(_)))
+            .count();
+        match attachments {
+            0 => lines.push(format!("  [{}] {}: {}", index, role, body)),
+            n => lines.push(format!(
+                "  [{}] {}: {} (+{} attachments)",
+                index, role, body, n
+            )),
+        }
+    }
+
+    let tools: Vec<&str> = request
+        .tools
+        .iter()
+        .flatten()
+        .map(|t| t.name.as_str())
+        .collect();
+    if !tools.is_empty() {
+        lines.push(format!("tools: {}", tools.join(", ")));
+    }
+    if let Some(mode) = &request.function_calling_mode {
+        lines.push(format!("function calling: {:?}", mode));
+    }
+
+    let mut settings = vec![];
+    if let Some(s) = &request.settings {
+        if let Some(v) = s.max_tokens {
+            settings.push(format!("max_tokens={}", v));
+        }
+        if let Some(v) = s.temperature {
+            settings.push(format!("temperature={}", v));
+        }
+        if let Some(v) = s.top_p {
+            settings.push(format!("top_p={}", v));
+        }
+        if let Some(v) = s.thinking_budget {
+            settings.push(format!("thinking_budget={}", v));
+        }
+        if let Some(v) = s.timeout {
+            settings.push(format!("timeout={}s", v));
+        }
+    }
+    if !settings.is_empty() {
+        lines.push(format!("settings: {}", settings.join(" ")));
+    }
+
+    if let Some(variants) = &request.enum_response {
+        lines.push(format!("response: one of {} variants", variants.len()));
+    } else if request.response_schema.is_some() {
+        lines.push("response: JSON schema".to_string());
+    }
+    if let Some(n) = request.logprobs {
+        lines.push(format!("logprobs: {}", n));
+    }
+    if let Some(modalities) = &request.response_modalities {
+        lines.push(format!("response modalities: {:?}", modalities));
+    }
+    if let Some(safety) = &request.safety_settings {
+        lines.push(format!("safety settings: {}", safety.0.len()));
+    }
+    if let Some(extra) = request
+        .extra_generation_config
+        .as_ref()
+        .and_then(|v| v.as_object())
+    {
+        let keys: Vec<&str> = extra.keys().map(String::as_str).collect();
+        lines.push(format!("extra generation config: {}", keys.join(", ")));
+    }
+    if request.before_send.is_some() {
+        lines.push("before_send hook: set".to_string());
+    }
+    // The client's default headers may hold credentials, so only its
+    // presence is reported.
+    if request.client.is_some() {
+        lines.push("client: custom".to_string());
+    }
+    lines.join("\n")
+}
+
+// `content` as it may appear in a summary: redacted per `mode`, without
+// anything shaped like a credential, and cut to `SUMMARY_EXCERPT_CHARS`.
+fn excerpt(mode: PrivacyMode, content: &str) -> String {
+    let len = content.chars().count();
+    let Some(content) = mode.apply(content) else {
+        return format!("<{} chars>", len);
+    };
+    let content = redact_secrets(&content);
+    match content.char_indices().nth(SUMMARY_EXCERPT_CHARS) {
+        Some((end, _)) => format!("{:?}... ({} chars)", &content[..end], len),
+        None => format!("{:?}", content),
+    }
+}
+
+// Replaces tokens shaped like a Google API key (`AIza...`), an OpenAI or
+// Anthropic key (`sk-...`) or a Google OAuth token (`ya29....`). A token is a
+// run of alphanumerics, `-`, `_` and `.`, so `key=AIza...` is caught as well as
+// a key on its own; everything between tokens is kept as it was.
+fn redact_secrets(content: &str) -> String {
+    let is_token_char = |c: char| c.is_alphanumeric() || "-_.".contains(c);
+    let mut redacted = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find(is_token_char) {
+        redacted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !is_token_char(c)).unwrap_or(rest.len());
+        let run = &rest[..end];
+        let token = match run.trim_end_matches('.') {
+            "" => run,
+            token => token,
+        };
+        let secret = (token.starts_with("AIza") && token.len() >= 30)
+            || (token.starts_with("sk-") && token.len() >= 20)
+            || (token.starts_with("ya29.") && token.len() >= 20);
+        redacted.push_str(if secret { REDACTED } else { token });
+        rest = &rest[token.len()..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+fn redact_pii(content: &str) -> String {
+    let words: Vec<String> = content
+        .split(' ')
+        .map(|word| {
+            if looks_like_email(word) {
+                REDACTED.to_string()
+            } else {
+                word.to_string()
+            }
+        })
+        .collect();
+    redact_digit_runs(&words.join(" "))
+}
+
+fn looks_like_email(word: &str) -> bool {
+    let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+    match word.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty() && domain.contains('.') && !domain.starts_with('.')
+        }
+        None => false,
+    }
+}
+
+// Replaces runs of 7+ digits, allowing the separators people type inside
+// phone and card numbers.
+fn redact_digit_runs(content: &str) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let mut out = String::with_capacity(content.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].is_ascii_digit() {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut end = i;
+        let mut digits = 0;
+        while i < chars.len() && (chars[i].is_ascii_digit() || "-. ()".contains(chars[i])) {
+            if chars[i].is_ascii_digit() {
+                digits += 1;
+                end = i + 1;
+            }
+            i += 1;
+        }
+
+        if digits >= 7 {
+            out.push_str(REDACTED);
+        } else {
+            out.extend(&chars[start..end]);
+        }
+        // Separators trailing the run are not part of it.
+        out.extend(&chars[end..i]);
+    }
+    out
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use super::{FunctionCall, LangrustError, ModelRequest};
+
+/// One piece of a message's content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentPart {
+    Text(String),
+    /// Raw bytes sent inline with the request (base64-encoded on the wire).
+    InlineData {
+        mime_type: String,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    /// A file the provider fetches itself, such as a `gs://` object or a
+    /// public `https://` URL. Nothing is uploaded.
+    FileData {
+        mime_type: String,
+        file_uri: String,
+    },
+    /// A function call the model made, in a model turn that also carries
+    /// text. See `Message::from_completion`.
+    FunctionCall(FunctionCall),
+}
+
+impl ContentPart {
+    /// Size of the part's inline data once base64-encoded, which is what
+    /// counts against a provider's request size limit. Zero for text.
+    pub fn inline_data_size(&self) -> usize {
+        match self {
+            ContentPart::InlineData { data, .. } => data.len().div_ceil(3) * 4,
+            _ => 0,
+        }
+    }
+}
+
+/// URI schemes a `ContentPart::FileData` may use.
+pub(crate) const FILE_URI_SCHEMES: [&str; 2] = ["gs://", "https://"];
+
+/// Rejects `gs://` file URIs for providers other than Gemini, which can only
+/// fetch `https://` URLs themselves.
+pub(crate) fn reject_gcs_file_uris(
+    request: &ModelRequest,
+    provider: &str,
+) -> Result<(), LangrustError> {
+    for message in request.messages.iter().flatten() {
+        for part in &message.parts {
+            if let ContentPart::FileData { file_uri, .. } = part
+                && file_uri.starts_with("gs://")
+            {
+                return Err(LangrustError::InvalidRequest(format!(
+                    "{} can't fetch gs:// file URIs, only https:// URLs; got `{}`",
+                    provider, file_uri
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A file the model returned, e.g. a generated image.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaPart {
+    pub mime_type: String,
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+pub(crate) fn encode_base64(data: &[u8]) -> String {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    STANDARD.encode(data)
+}
+
+pub(crate) mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer, de};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(de::Error::custom)
+    }
+}
@@ -1,44 +1,350 @@
 use schemars::{JsonSchema, schema_for};
 use serde_json::{self, Value};
-use std::{collections::HashMap, error::Error, pin::Pin};
+use std::{
+    collections::HashMap,
+    error::Error,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use futures::Stream;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
 
+mod cache;
+mod chunking;
+mod conversation;
+mod error;
+mod fallback;
+mod http_version;
+mod json_stream;
+mod key_provider;
+mod load_balance;
+mod logging;
+mod media;
+mod observer;
+mod proxy;
+mod rate_limit;
+#[cfg(any(test, feature = "testing"))]
+mod replay;
+mod retry;
+mod safety;
+mod schema_check;
+mod stream;
+mod template;
 #[cfg(test)]
 mod tests;
+mod timeouts;
+mod tools;
+mod transport;
+
+pub use cache::CompletionCache;
+pub use chunking::{chunk_text, estimate_text_tokens};
+pub use conversation::Conversation;
+pub(crate) use error::api_error;
+pub use error::{ErrorBodyKind, LangrustError};
+pub use fallback::FallbackModel;
+pub use http_version::HttpVersionPref;
+pub use json_stream::{JsonStream, JsonStreamEvent};
+pub(crate) use key_provider::TokenCache;
+pub use key_provider::{CachedKeyProvider, KeyProvider};
+pub use load_balance::LoadBalancedModel;
+pub use logging::PrivacyMode;
+pub(crate) use logging::log_request;
+use media::FILE_URI_SCHEMES;
+pub use media::{ContentPart, MediaPart};
+pub(crate) use media::{base64_bytes, encode_base64, reject_gcs_file_uris};
+pub use observer::{ErrorEvent, ModelObserver, RequestEvent, ResponseEvent};
+pub use proxy::ProxyConfig;
+pub use rate_limit::RateLimiter;
+#[cfg(any(test, feature = "testing"))]
+pub use replay::ReplayModel;
+pub use retry::{JitterMode, RetryConfig};
+pub use safety::{
+    HarmBlockThreshold, HarmCategory, HarmProbability, SafetyRating, SafetyRatings, SafetySetting,
+    SafetySettings,
+};
+use stream::{CompletionAccumulator, DEFAULT_STREAM_BUFFER, open_with_first_token_timeout};
+pub use stream::{CompletionStream, StreamHandle};
+pub use template::PromptTemplate;
+pub use timeouts::HttpTimeouts;
+pub use tools::ToolResult;
+pub use transport::HttpTransport;
+pub(crate) use transport::{ClientOptions, read_json, route, send};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FunctionCall {
     pub name: String,
     pub args: HashMap<String, Value>,
+    /// Provider-assigned ID of the call, used to match the result to it when
+    /// the model makes several calls at once. `None` if the provider sent none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Completion {
     pub completion: String,
-    pub usage: Usage,
+    /// Token usage, or `None` when the provider didn't report any (as
+    /// opposed to reporting zero tokens).
+    pub usage: Option<Usage>,
+    /// The first function call of the turn; `function_calls` holds all of
+    /// them.
     pub function: Option<FunctionCall>,
+    /// Every function call of the turn, in order, e.g. when Gemini makes
+    /// parallel calls. Read it through `tool_calls()`.
+    pub function_calls: Vec<FunctionCall>,
+    pub model: String,
+    /// Exact model snapshot reported by the provider, when it sends one.
+    pub model_version: Option<String>,
+    /// Provider-assigned ID of the response, useful for support requests.
+    pub response_id: Option<String>,
+    /// One entry per generated token, when requested with `with_logprobs`.
+    pub logprobs: Option<Vec<TokenLogprobs>>,
+    /// Mean log probability of the generated tokens, a cheap confidence
+    /// signal: the closer to 0, the more confident. Reported by Gemini.
+    pub avg_logprobs: Option<f64>,
+    /// Safety ratings of the response, or of the prompt when the provider
+    /// only rated that. Reported by Gemini.
+    pub safety_ratings: Option<SafetyRatings>,
+    /// The values allowed by `with_enum_response`, checked by `as_enum`.
+    pub enum_variants: Option<Vec<String>>,
+    // The turn's text and function calls in the order the provider returned
+    // them, for `parts()`. Private so that it can't be set apart from
+    // `completion` and `function_calls`, which it must agree with.
+    pub(crate) ordered_parts: Option<Vec<CompletionPart>>,
+    /// Media the model generated, such as images from Gemini's image models,
+    /// decoded from base64.
+    pub media: Vec<MediaPart>,
+    /// Whether the stream this completion was read from was cut short by
+    /// `ModelRequestBuilder::with_max_stream_tokens`, leaving `completion`
+    /// partial.
+    pub truncated: bool,
+}
+
+/// One piece of a model turn, as returned by `Completion::parts`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionPart {
+    Text(String),
+    FunctionCall(FunctionCall),
+}
+
+impl CompletionPart {
+    /// Appends `text` to `parts`, extending the last part if it is text too.
+    pub(crate) fn push_text(parts: &mut Vec<CompletionPart>, text: &str) {
+        match parts.last_mut() {
+            Some(CompletionPart::Text(last)) => last.push_str(text),
+            _ if text.is_empty() => {}
+            _ => parts.push(CompletionPart::Text(text.to_string())),
+        }
+    }
+
+    /// The text of `parts`, joined.
+    pub(crate) fn text(parts: &[CompletionPart]) -> String {
+        parts
+            .iter()
+            .filter_map(|part| match part {
+                CompletionPart::Text(text) => Some(text.as_str()),
+                CompletionPart::FunctionCall(_) => None,
+            })
+            .collect()
+    }
+
+    /// The function calls among `parts`, in order.
+    pub(crate) fn calls(parts: &[CompletionPart]) -> Vec<FunctionCall> {
+        parts
+            .iter()
+            .filter_map(|part| match part {
+                CompletionPart::FunctionCall(call) => Some(call.clone()),
+                CompletionPart::Text(_) => None,
+            })
+            .collect()
+    }
+}
+
+impl Completion {
+    /// The turn's text and function calls in order, so interleaved text and
+    /// calls can be told apart. Without an order from the provider, or once
+    /// `completion` or `function_calls` has been changed, the text comes
+    /// first and the function calls last.
+    pub fn parts(&self) -> Vec<CompletionPart> {
+        if let Some(parts) = &self.ordered_parts
+            && CompletionPart::text(parts) == self.completion
+            && CompletionPart::calls(parts) == self.tool_calls()
+        {
+            return parts.clone();
+        }
+        let mut parts = vec![];
+        CompletionPart::push_text(&mut parts, &self.completion);
+        parts.extend(
+            self.tool_calls()
+                .iter()
+                .cloned()
+                .map(CompletionPart::FunctionCall),
+        );
+        parts
+    }
+
+    /// Whether the model asked for a tool to be called instead of answering.
+    pub fn is_tool_call(&self) -> bool {
+        !self.tool_calls().is_empty()
+    }
+
+    /// Every tool call the model asked for, in order; empty for a text
+    /// answer. Falls back to `function` when `function_calls` is empty, as
+    /// in a completion built by hand with only `function` set.
+    pub fn tool_calls(&self) -> &[FunctionCall] {
+        match self.function_calls.is_empty() {
+            true => self.function.as_slice(),
+            false => &self.function_calls,
+        }
+    }
+
+    /// Parses the completion as JSON and checks it against `T`'s schema before
+    /// deserializing, so a mismatch names the offending field: a
+    /// `LangrustError::SchemaMismatch` with its path and the type expected
+    /// there. Text that isn't JSON is a `LangrustError::InvalidResponse`.
+    pub fn parse_validated<T: JsonSchema + DeserializeOwned>(&self) -> Result<T, LangrustError> {
+        let value: Value = serde_json::from_str(&self.completion).map_err(|e| {
+            LangrustError::InvalidResponse(format!("expected a JSON response: {}", e))
+        })?;
+        let schema = serde_json::to_value(schema_for!(T)).unwrap_or_default();
+        schema_check::check(&value, &schema).map_err(|m| LangrustError::SchemaMismatch {
+            path: m.path,
+            expected: m.expected,
+        })?;
+        serde_json::from_value(value).map_err(|e| {
+            LangrustError::InvalidResponse(format!(
+                "expected JSON matching the response schema: {}",
+                e
+            ))
+        })
+    }
+
+    /// The answer to a `with_enum_response` request, if it is one of the
+    /// allowed values. `None` when the model answered outside the set or no
+    /// enum response was requested.
+    pub fn as_enum(&self) -> Option<&str> {
+        let answer = self.completion.trim();
+        self.enum_variants
+            .as_ref()?
+            .iter()
+            .find(|v| *v == answer)
+            .map(|v| v.as_str())
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
+/// The token the model chose at one position, and the most likely candidates
+/// for that position (highest first).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenLogprobs {
+    pub chosen: TokenLogprob,
+    pub top: Vec<TokenLogprob>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
     pub total_tokens: i32,
 }
 
-#[derive(Debug, Clone)]
+/// Events yielded by `Model::stream_completion`.
+///
+/// Token usage is reported at most once, in a `Usage` event ending a stream
+/// that runs to completion; no event before it carries usage. Streams cut
+/// short have none, as do streams whose provider reported no usage.
+///
+/// Serializes as `{"<kind>": <payload>}`, e.g. `{"delta": "Hi"}`, the form
+/// `CompletionStream::into_sse` sends.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum StreamEvent {
     Delta(String),
     Usage(Usage),
+    /// A function call the model has started, sent as soon as its name is
+    /// known so a UI can show "calling get_weather..." while the arguments
+    /// are still streaming. A `FunctionCall` event with the complete call
+    /// follows. Claude and OpenAI announce calls this way; Gemini only sends
+    /// complete calls, so its streams go straight to `FunctionCall`.
+    FunctionCallStarted {
+        name: String,
+        id: Option<String>,
+    },
     FunctionCall(FunctionCall),
     Error(String),
+    /// Closes a stream cut short by `ModelRequestBuilder::with_max_stream_tokens`,
+    /// with the estimated number of completion tokens received. No `Usage`
+    /// event follows.
+    Truncated(u32),
+    /// Closes a stream cut short by `ModelRequestBuilder::with_stop_on` when
+    /// the text reached its sentinel, given here. The text before the
+    /// sentinel has been delivered; no `Usage` event follows.
+    Stopped(String),
+    /// Closes a stream aborted through its `StreamHandle`. No `Usage` event
+    /// follows.
+    Cancelled,
+    /// Closes a stream the provider stopped for safety, e.g. Gemini's
+    /// `finishReason: SAFETY`, with that reason and the text delivered
+    /// before it. Follows the stream's `Usage` event, if any.
+    Filtered {
+        finish_reason: String,
+        partial_text: String,
+    },
+}
+
+impl StreamEvent {
+    /// Whether this is the closing event of a successful stream.
+    pub fn is_final(&self) -> bool {
+        matches!(self, StreamEvent::Usage(_))
+    }
+
+    pub fn usage(&self) -> Option<&Usage> {
+        match self {
+            StreamEvent::Usage(u) => Some(u),
+            _ => None,
+        }
+    }
+
+    // The name the event serializes under, used as its SSE `event:` field.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            StreamEvent::Delta(_) => "delta",
+            StreamEvent::Usage(_) => "usage",
+            StreamEvent::FunctionCallStarted { .. } => "function_call_started",
+            StreamEvent::FunctionCall(_) => "function_call",
+            StreamEvent::Error(_) => "error",
+            StreamEvent::Truncated(_) => "truncated",
+            StreamEvent::Stopped(_) => "stopped",
+            StreamEvent::Cancelled => "cancelled",
+            StreamEvent::Filtered { .. } => "filtered",
+        }
+    }
 }
 
 pub type StreamResult = Pin<Box<dyn Stream<Item = StreamEvent> + Send>>;
 
+/// What `Model::completions` returns.
+pub type CompletionsFuture<'a> = Pin<
+    Box<dyn Future<Output = Result<Vec<Completion>, Box<dyn Error + Send + Sync>>> + Send + 'a>,
+>;
+
+/// Callback given the JSON body of a request just before it is sent, free to
+/// change it. See `ModelRequestBuilder::with_before_send`.
+pub type BeforeSendHook = Arc<dyn Fn(&mut Value) + Send + Sync>;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Role {
     #[serde(rename = "model")]
@@ -47,13 +353,17 @@ pub enum Role {
     User,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum MessageType {
     Text,
     FunctionCall(FunctionCall),
     FunctionResponse {
         name: String,
         response: Option<serde_json::Value>,
+        /// ID of the call this answers, from `FunctionCall::id`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
     },
 }
 
@@ -67,8 +377,12 @@ impl Default for MessageType {
 pub struct Message {
     pub content: String,
     pub role: Option<Role>,
-    #[serde(skip)]
+    #[serde(default)]
     pub message_type: MessageType,
+    /// Ordered content of a multi-part message. Empty for plain text
+    /// messages, whose only part is `content`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ContentPart>,
 }
 
 impl Message {
@@ -77,6 +391,7 @@ impl Message {
             content: content,
             role: Some(Role::User),
             message_type: MessageType::Text,
+            parts: vec![],
         }
     }
 
@@ -85,6 +400,7 @@ impl Message {
             content: content,
             role: Some(Role::Model),
             message_type: MessageType::Text,
+            parts: vec![],
         }
     }
 
@@ -94,10 +410,21 @@ impl Message {
             content,
             role: Some(Role::Model),
             message_type: MessageType::FunctionCall(function_call),
+            parts: vec![],
         }
     }
 
     pub fn function_result<T: Serialize>(name: String, value: T) -> Message {
+        Message::function_response(name, None, value)
+    }
+
+    /// The result of `call`, carrying its ID so that it can't be mistaken for
+    /// the result of another call made in the same turn.
+    pub fn function_result_for<T: Serialize>(call: &FunctionCall, value: T) -> Message {
+        Message::function_response(call.name.clone(), call.id.clone(), value)
+    }
+
+    fn function_response<T: Serialize>(name: String, id: Option<String>, value: T) -> Message {
         let response = serde_json::to_value(&value).ok();
         let content = serde_json::json!({
             "name": name,
@@ -107,7 +434,80 @@ impl Message {
         Message {
             content,
             role: Some(Role::User),
-            message_type: MessageType::FunctionResponse { name, response },
+            message_type: MessageType::FunctionResponse { name, response, id },
+            parts: vec![],
+        }
+    }
+
+    /// The assistant turn a completion represents, ready to append to the
+    /// history of the next request. Function calls that came with text, or
+    /// with each other, are kept together in order, as a multi-part message
+    /// whose `message_type` is the first call.
+    pub fn from_completion(completion: &Completion) -> Message {
+        match completion.tool_calls() {
+            [] => Message::model(completion.completion.clone()),
+            [fc] if completion.completion.is_empty() => Message::function_call(fc.clone()),
+            [first, ..] => Message {
+                content: completion.completion.clone(),
+                role: Some(Role::Model),
+                message_type: MessageType::FunctionCall(first.clone()),
+                parts: completion
+                    .parts()
+                    .into_iter()
+                    .map(|part| match part {
+                        CompletionPart::Text(text) => ContentPart::Text(text),
+                        CompletionPart::FunctionCall(fc) => ContentPart::FunctionCall(fc),
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    /// Appends raw bytes (an image, a PDF, ...) after the message's current
+    /// content. Gemini caps inline data at about 20MB per request; upload
+    /// larger files through the File API instead.
+    pub fn with_inline_data(mut self, mime_type: &str, data: Vec<u8>) -> Message {
+        if self.parts.is_empty() && !self.content.is_empty() {
+            self.parts.push(ContentPart::Text(self.content.clone()));
+        }
+        self.parts.push(ContentPart::InlineData {
+            mime_type: mime_type.to_string(),
+            data,
+        });
+        self
+    }
+
+    /// Appends a reference to a file the provider fetches itself, such as a
+    /// Cloud Storage object (`gs://bucket/file.pdf`, Gemini only) or a public
+    /// `https://` URL, without uploading it. Other schemes are rejected when
+    /// the request is sent.
+    pub fn with_file_uri(mut self, uri: &str, mime_type: &str) -> Message {
+        if self.parts.is_empty() && !self.content.is_empty() {
+            self.parts.push(ContentPart::Text(self.content.clone()));
+        }
+        self.parts.push(ContentPart::FileData {
+            mime_type: mime_type.to_string(),
+            file_uri: uri.to_string(),
+        });
+        self
+    }
+
+    /// Appends text after the message's current content, e.g. a question
+    /// following an image, so text and media interleave in one turn.
+    /// `content` keeps the message's first text.
+    pub fn with_text(mut self, text: &str) -> Message {
+        if self.parts.is_empty() && !self.content.is_empty() {
+            self.parts.push(ContentPart::Text(self.content.clone()));
+        }
+        self.parts.push(ContentPart::Text(text.to_string()));
+        self
+    }
+
+    /// The message's content as an ordered list of parts.
+    pub fn content_parts(&self) -> Vec<ContentPart> {
+        match self.parts.is_empty() {
+            true => vec![ContentPart::Text(self.content.clone())],
+            false => self.parts.clone(),
         }
     }
 }
@@ -124,6 +524,17 @@ pub trait Model {
         request: ModelRequest,
     ) -> Result<StreamResult, Box<dyn Error + Send + Sync>>;
 
+    /// Up to `n` alternative completions of the same request, one per
+    /// candidate the provider returns. Only the Gemini models support it.
+    /// Each completion's `usage` is that of the whole request, not of its
+    /// candidate, so summing them over-counts.
+    // Returns a boxed future rather than being an `async fn` so that the
+    // default doesn't require `Self: Sync`, which `dyn Model` isn't.
+    fn completions(&self, _request: ModelRequest, _n: u32) -> CompletionsFuture<'_> {
+        let error = format!("{} does not support multiple candidates", self.model_name());
+        Box::pin(async move { Err(error.into()) })
+    }
+
     fn new_request(&self) -> ModelRequestBuilder<'_>
     where
         Self: Sized,
@@ -132,16 +543,138 @@ pub trait Model {
     }
 
     fn model_name(&self) -> String;
+
+    /// Whether the model can be given tools to call. Requests with tools are
+    /// rejected by `ModelRequest::validate_for` when it can't.
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    /// Settings used where a request leaves them unset.
+    fn defaults(&self) -> Option<&ModelDefaults> {
+        None
+    }
+
+    /// The model's `CompletionCache`, if it has one.
+    fn cache(&self) -> Option<&CompletionCache> {
+        None
+    }
+
+    /// The limiter every request built with `new_request` waits on, unless
+    /// the request sets its own with `ModelRequestBuilder::with_rate_limiter`.
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        None
+    }
+
+    /// The observer told about every request built with `new_request`,
+    /// unless the request sets its own with
+    /// `ModelRequestBuilder::with_observer`.
+    fn observer(&self) -> Option<&dyn ModelObserver> {
+        None
+    }
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct Settings {
     pub max_tokens: Option<i16>,
     pub timeout: Option<i16>,
     pub temperature: Option<i16>,
+    /// Nucleus sampling: only the most likely tokens adding up to this
+    /// probability are considered. `None` leaves it to the provider's default.
+    pub top_p: Option<f32>,
+    /// Tokens the model may spend thinking. `None` leaves it to the provider's
+    /// default; on Gemini `0` disables thinking and `-1` lets the model decide.
+    /// Thinking tokens count against `max_tokens`, so a low `max_tokens` can
+    /// leave nothing for the answer; see
+    /// `ModelRequestBuilder::with_thinking_budget`.
+    pub thinking_budget: Option<i16>,
+    /// How message content is logged; `PrivacyMode::Redacted` when unset.
+    pub privacy_mode: Option<PrivacyMode>,
+    /// Whether consecutive messages with the same role are merged into one
+    /// turn before sending, as Gemini requires alternating roles. Defaults to
+    /// `true`; only the Gemini models read it.
+    pub merge_consecutive_roles: Option<bool>,
+}
+
+impl Settings {
+    /// Temperature 0 and top-p 1: the most likely answer every time, as far
+    /// as the provider allows. For extraction, classification and tests.
+    pub fn deterministic() -> Settings {
+        Settings::preset(GenerationPreset::Deterministic)
+    }
+
+    /// Temperature 1 and top-p 0.95: varied answers that stay on topic. For
+    /// brainstorming and creative writing.
+    pub fn creative() -> Settings {
+        Settings::preset(GenerationPreset::Creative)
+    }
+
+    /// Default settings with the sampling parameters of `preset`.
+    pub fn preset(preset: GenerationPreset) -> Settings {
+        let mut settings = Settings::default();
+        settings.apply_preset(preset);
+        settings
+    }
+
+    fn apply_preset(&mut self, preset: GenerationPreset) {
+        let (temperature, top_p) = match preset {
+            GenerationPreset::Deterministic => (0, 1.0),
+            GenerationPreset::Creative => (1, 0.95),
+        };
+        self.temperature = Some(temperature);
+        self.top_p = Some(top_p);
+    }
+
+    /// These settings with every field they leave unset taken from `base`.
+    pub fn or(self, base: Settings) -> Settings {
+        Settings {
+            max_tokens: self.max_tokens.or(base.max_tokens),
+            timeout: self.timeout.or(base.timeout),
+            temperature: self.temperature.or(base.temperature),
+            top_p: self.top_p.or(base.top_p),
+            thinking_budget: self.thinking_budget.or(base.thinking_budget),
+            privacy_mode: self.privacy_mode.or(base.privacy_mode),
+            merge_consecutive_roles: self
+                .merge_consecutive_roles
+                .or(base.merge_consecutive_roles),
+        }
+    }
+
+    /// Fills every field `defaults` covers that is still unset.
+    pub(crate) fn or_defaults(mut self, defaults: &ModelDefaults) -> Settings {
+        self.max_tokens = self.max_tokens.or(defaults.max_tokens);
+        self.temperature = self.temperature.or(defaults.temperature);
+        self.top_p = self.top_p.or(defaults.top_p);
+        self.thinking_budget = self.thinking_budget.or(defaults.thinking_budget);
+        self
+    }
+}
+
+/// Generation settings a model uses for requests that don't set their own,
+/// held in each model's `defaults` field.
+///
+/// A setting is taken from the request's `Settings` first, then from the
+/// model's `ModelDefaults`, and otherwise left to the crate default, which is
+/// the provider's own default except for Claude's `max_tokens` (8192, as the
+/// API requires one) and Gemini's `temperature` (0). Defaults apply to direct
+/// `Model::completion` and `Model::stream_completion` calls as well as to
+/// requests built with `new_request`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ModelDefaults {
+    pub max_tokens: Option<i16>,
+    pub temperature: Option<i16>,
+    pub top_p: Option<f32>,
     pub thinking_budget: Option<i16>,
 }
 
+/// Starting points for the sampling parameters; see `Settings::deterministic`
+/// and `Settings::creative`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenerationPreset {
+    Deterministic,
+    Creative,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ToolParameters {
     #[serde(rename = "type")]
@@ -165,6 +698,21 @@ pub struct Tool {
     pub name: String,
     pub description: String,
     pub parameters: Option<ToolParameters>,
+    /// Descriptions in other languages, keyed by locale (e.g. `fr`). The
+    /// request's `with_locale` picks one; `description` and the parameters'
+    /// own descriptions are the fallback.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub translations: HashMap<String, ToolTranslation>,
+}
+
+/// A tool's description, and those of some of its parameters, in one locale.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ToolTranslation {
+    pub description: String,
+    /// Parameter descriptions by parameter name. Parameters missing here keep
+    /// the description from their schema.
+    #[serde(default)]
+    pub parameters: HashMap<String, String>,
 }
 
 impl Tool {
@@ -173,22 +721,146 @@ impl Tool {
             name: name.to_string(),
             description: description.to_string(),
             parameters: None,
+            translations: HashMap::new(),
         }
     }
 
+    /// Adds the tool's description in `locale`.
+    pub fn with_translation(mut self, locale: &str, description: &str) -> Tool {
+        self.translations
+            .entry(locale.to_string())
+            .or_default()
+            .description = description.to_string();
+        self
+    }
+
+    /// Adds the description of `parameter` in `locale`. Set the tool's own
+    /// description in that locale with `with_translation`.
+    pub fn with_parameter_translation(
+        mut self,
+        locale: &str,
+        parameter: &str,
+        description: &str,
+    ) -> Tool {
+        self.translations
+            .entry(locale.to_string())
+            .or_default()
+            .parameters
+            .insert(parameter.to_string(), description.to_string());
+        self
+    }
+
+    /// The tool as sent for `locale`: its descriptions replaced by those of
+    /// the matching translation, and no translations left. A locale with a
+    /// region, e.g. `fr-CA`, falls back to its language, `fr`; without a
+    /// match the tool is sent as written.
+    pub fn localized(&self, locale: Option<&str>) -> Tool {
+        let mut tool = Tool {
+            translations: HashMap::new(),
+            ..self.clone()
+        };
+        let Some(locale) = locale else {
+            return tool;
+        };
+        let language = locale.split(['-', '_']).next().unwrap_or(locale);
+        let Some(translation) = self
+            .translations
+            .get(locale)
+            .or_else(|| self.translations.get(language))
+        else {
+            return tool;
+        };
+        if !translation.description.is_empty() {
+            tool.description = translation.description.clone();
+        }
+        if let Some(parameters) = &mut tool.parameters {
+            for (name, description) in &translation.parameters {
+                if let Some(Value::Object(schema)) = parameters.properties.get_mut(name) {
+                    schema.insert(
+                        "description".to_string(),
+                        Value::String(description.clone()),
+                    );
+                }
+            }
+        }
+        tool
+    }
+
     pub fn with_parameter<T: JsonSchema>(self) -> Result<Tool, serde_json::Error> {
         let arg_schema = schema_for!(T);
         let json_value = serde_json::to_value(&arg_schema)?;
         let parameters: ToolParameters = serde_json::from_value(json_value)?;
         match self.parameters {
             None => Ok(Tool {
-                name: self.name,
-                description: self.description,
                 parameters: Some(parameters),
+                ..self
             }),
             Some(_) => Ok(self),
         }
     }
+
+    /// Checks that every name in the parameters' `required` list is one of
+    /// their `properties`, which Gemini otherwise rejects.
+    pub fn validate(&self) -> Result<(), LangrustError> {
+        let Some(parameters) = &self.parameters else {
+            return Ok(());
+        };
+        match parameters
+            .required
+            .iter()
+            .find(|name| !parameters.properties.contains_key(*name))
+        {
+            Some(name) => Err(LangrustError::InvalidRequest(format!(
+                "tool `{}` requires parameter `{}`, which is not among its properties",
+                self.name, name
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What to do when several tools share a name, which providers reject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateToolPolicy {
+    /// Send only the tool added last, in the position of the first one.
+    #[default]
+    LastWins,
+    /// Fail the request with `LangrustError::InvalidRequest`.
+    Error,
+}
+
+/// How `ModelRequest::merge` combines the messages and tools of two requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListMergePolicy {
+    /// The base request's items, then the override's.
+    #[default]
+    Concatenate,
+    /// The override's items when it has any, otherwise the base request's.
+    Replace,
+}
+
+/// Whether and which tools the model may call. Translated to each provider's
+/// native setting: Gemini `toolConfig.functionCallingConfig.mode`, OpenAI and
+/// Anthropic `tool_choice`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FunctionCallingMode {
+    /// The model decides whether to call a tool.
+    Auto,
+    /// The model must call one of the tools.
+    Any,
+    /// The model must not call any tool.
+    None,
+    /// The model must call the named tool.
+    Function(String),
+}
+
+/// A kind of output to ask a multimodal-output model for, with
+/// `ModelRequestBuilder::with_response_modalities`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modality {
+    Text,
+    Image,
+    Audio,
 }
 
 #[derive(Clone)]
@@ -196,18 +868,274 @@ pub struct ModelRequestBuilder<'a> {
     pub model: &'a dyn Model,
     pub system: Option<String>,
     pub messages: Option<Vec<Message>>,
+    pub pinned_messages: Option<Vec<Message>>,
     pub settings: Option<Settings>,
     pub tools: Option<Vec<Tool>>,
+    pub duplicate_tool_policy: Option<DuplicateToolPolicy>,
+    pub function_calling_mode: Option<FunctionCallingMode>,
+    pub response_schema: Option<Value>,
+    pub enum_response: Option<Vec<String>>,
+    pub logprobs: Option<i32>,
+    pub response_modalities: Option<Vec<Modality>>,
+    pub safety_settings: Option<SafetySettings>,
+    pub extra_generation_config: Option<Value>,
+    pub retry: Option<RetryConfig>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub observer: Option<Arc<dyn ModelObserver>>,
+    pub max_stream_tokens: Option<u32>,
+    pub stop_on: Option<String>,
+    pub first_token_timeout: Option<Duration>,
+    pub max_stream_duration: Option<Duration>,
+    pub stream_buffer: Option<usize>,
+    pub before_send: Option<BeforeSendHook>,
+    pub client: Option<reqwest::Client>,
+    pub max_response_bytes: Option<usize>,
+    pub locale: Option<String>,
 }
 
 unsafe impl<'a> Sync for ModelRequestBuilder<'a> {}
 unsafe impl<'a> Send for ModelRequestBuilder<'a> {}
 
+#[derive(Clone)]
 pub struct ModelRequest {
     pub system: Option<String>,
     pub messages: Option<Vec<Message>>,
     pub settings: Option<Settings>,
     pub tools: Option<Vec<Tool>>,
+    pub function_calling_mode: Option<FunctionCallingMode>,
+    /// JSON Schema the response must conform to. Honored by Gemini
+    /// (`responseSchema`) and OpenAI (`text.format`); Claude ignores it.
+    pub response_schema: Option<Value>,
+    /// The only answers the model may give, one of which it returns as plain
+    /// text. Honored by Gemini (`text/x.enum`); other providers ignore it.
+    pub enum_response: Option<Vec<String>>,
+    /// Number of top candidates to report per token, returned in
+    /// `Completion::logprobs`. Honored by Gemini; other providers ignore it.
+    pub logprobs: Option<i32>,
+    /// Kinds of output the model should produce, e.g. text and images.
+    /// Honored by Gemini (`responseModalities`); other providers ignore it.
+    pub response_modalities: Option<Vec<Modality>>,
+    /// Blocking thresholds per harm category. Honored by Gemini; other
+    /// providers ignore it.
+    pub safety_settings: Option<SafetySettings>,
+    /// JSON object merged into Gemini's `generationConfig`, for fields this
+    /// crate doesn't model yet. Fields set from the typed options win over
+    /// the same keys here. Other providers ignore it.
+    pub extra_generation_config: Option<Value>,
+    /// Runs on the serialized body right before it is sent. Honored by
+    /// Gemini; other providers ignore it.
+    pub before_send: Option<BeforeSendHook>,
+    /// Sends this request in place of the model's `client` and `transport`.
+    pub client: Option<reqwest::Client>,
+    /// Most bytes of a response body to read; larger bodies fail with
+    /// `LangrustError::ResponseTooLarge`. `None` reads any size.
+    pub max_response_bytes: Option<usize>,
+}
+
+impl ModelRequest {
+    /// Combines an app-level `base` request with a per-call `overrides`
+    /// request. Every field `overrides` sets wins, `Settings` field by field;
+    /// messages and tools are combined per `lists`.
+    pub fn merge(
+        base: ModelRequest,
+        overrides: ModelRequest,
+        lists: ListMergePolicy,
+    ) -> ModelRequest {
+        fn combine<T>(
+            base: Option<Vec<T>>,
+            other: Option<Vec<T>>,
+            lists: ListMergePolicy,
+        ) -> Option<Vec<T>> {
+            match (base, other, lists) {
+                (Some(mut base), Some(other), ListMergePolicy::Concatenate) => {
+                    base.extend(other);
+                    Some(base)
+                }
+                (base, other, _) => other.or(base),
+            }
+        }
+
+        ModelRequest {
+            system: overrides.system.or(base.system),
+            messages: combine(base.messages, overrides.messages, lists),
+            settings: match (base.settings, overrides.settings) {
+                (Some(base), Some(settings)) => Some(settings.or(base)),
+                (base, settings) => settings.or(base),
+            },
+            tools: combine(base.tools, overrides.tools, lists),
+            function_calling_mode: overrides
+                .function_calling_mode
+                .or(base.function_calling_mode),
+            response_schema: overrides.response_schema.or(base.response_schema),
+            enum_response: overrides.enum_response.or(base.enum_response),
+            logprobs: overrides.logprobs.or(base.logprobs),
+            response_modalities: overrides.response_modalities.or(base.response_modalities),
+            safety_settings: overrides.safety_settings.or(base.safety_settings),
+            extra_generation_config: overrides
+                .extra_generation_config
+                .or(base.extra_generation_config),
+            before_send: overrides.before_send.or(base.before_send),
+            client: overrides.client.or(base.client),
+            max_response_bytes: overrides.max_response_bytes.or(base.max_response_bytes),
+        }
+    }
+
+    /// A compact, human-readable description of the request for support
+    /// tickets and debugging, the same for every provider: the system prompt,
+    /// each message's role and a truncated excerpt, tool names and the
+    /// settings that are set. Message text is redacted per the request's
+    /// `privacy_mode`, and anything shaped like an API key or OAuth token is
+    /// always masked.
+    pub fn debug_summary(&self) -> String {
+        logging::summarize_request(self)
+    }
+
+    /// The request with `model`'s `defaults` filled in, as `model` would
+    /// have built it itself; for wrappers that hand a request on.
+    pub(crate) fn for_model(&self, model: &dyn Model) -> ModelRequest {
+        self.clone().with_defaults(model.defaults())
+    }
+
+    /// The request with `defaults` filled in where its settings leave them
+    /// unset. Every provider applies its model's defaults this way when it
+    /// builds the request body, so they hold for direct `Model` calls too.
+    pub(crate) fn with_defaults(mut self, defaults: Option<&ModelDefaults>) -> ModelRequest {
+        if let Some(defaults) = defaults {
+            self.settings = Some(self.settings.unwrap_or_default().or_defaults(defaults));
+        }
+        self
+    }
+
+    /// Checks the request for problems every provider would reject, so they
+    /// surface as a `LangrustError::InvalidRequest` instead of an HTTP error.
+    ///
+    /// A request needs at least one message; a system prompt alone is not
+    /// enough for any of the supported APIs. An enum response needs at least
+    /// one variant and no schema. Tool names must be unique, extra generation
+    /// config must be a JSON object, and a response schema must be an object
+    /// that constrains something (`type`, `enum`, `properties`, `$ref`, ...).
+    pub fn validate(&self) -> Result<(), LangrustError> {
+        if self.messages.as_ref().is_none_or(|ms| ms.is_empty()) {
+            return Err(LangrustError::InvalidRequest("no messages".to_string()));
+        }
+        if let Some(max_tokens) = self.settings.as_ref().and_then(|s| s.max_tokens)
+            && max_tokens <= 0
+        {
+            return Err(LangrustError::InvalidRequest(format!(
+                "max_tokens must be positive, got {}",
+                max_tokens
+            )));
+        }
+        if let Some(extra) = &self.extra_generation_config {
+            let Some(extra) = extra.as_object() else {
+                return Err(LangrustError::InvalidRequest(
+                    "extra generation config must be a JSON object".to_string(),
+                ));
+            };
+            for key in ["topK", "candidateCount"] {
+                if let Some(value) = extra.get(key)
+                    && value.as_i64().is_none_or(|v| v <= 0)
+                {
+                    return Err(LangrustError::InvalidRequest(format!(
+                        "{} must be a positive integer, got {}",
+                        key, value
+                    )));
+                }
+            }
+        }
+        for message in self.messages.iter().flatten() {
+            for part in &message.parts {
+                if let ContentPart::FileData { file_uri, .. } = part
+                    && !FILE_URI_SCHEMES.iter().any(|s| file_uri.starts_with(s))
+                {
+                    return Err(LangrustError::InvalidRequest(format!(
+                        "file URI must start with gs:// or https://, got `{}`",
+                        file_uri
+                    )));
+                }
+            }
+        }
+        if let Some(schema) = &self.response_schema
+            && !is_schema_object(schema)
+        {
+            return Err(LangrustError::InvalidRequest(
+                "response schema must be a JSON Schema object such as \
+                 `{\"type\": \"object\", \"properties\": {..}}`"
+                    .to_string(),
+            ));
+        }
+        let tools = self.tools.as_deref().unwrap_or_default();
+        for (i, tool) in tools.iter().enumerate() {
+            if tools[..i].iter().any(|t| t.name == tool.name) {
+                return Err(LangrustError::InvalidRequest(format!(
+                    "tool `{}` is defined more than once",
+                    tool.name
+                )));
+            }
+            tool.validate()?;
+        }
+        if let Some(variants) = &self.enum_response {
+            if variants.is_empty() {
+                return Err(LangrustError::InvalidRequest(
+                    "enum response needs at least one variant".to_string(),
+                ));
+            }
+            if self.response_schema.is_some() {
+                return Err(LangrustError::InvalidRequest(
+                    "an enum response cannot be combined with a response schema".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `validate`, also rejecting tools when `model` can't call
+    /// functions (`Model::supports_tools`).
+    pub fn validate_for(&self, model: &dyn Model) -> Result<(), LangrustError> {
+        self.validate()?;
+        self.check_tools_supported(model.supports_tools())
+    }
+
+    pub(crate) fn check_tools_supported(&self, supports_tools: bool) -> Result<(), LangrustError> {
+        let has_tools = self.tools.as_ref().is_some_and(|ts| !ts.is_empty());
+        if has_tools && !supports_tools {
+            return Err(LangrustError::InvalidRequest(
+                "this model does not support function calling; remove the tools".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Whether `schema` is an object carrying at least one keyword that constrains
+// the response; `{}` or a bare string would let anything through.
+fn is_schema_object(schema: &Value) -> bool {
+    const KEYWORDS: [&str; 9] = [
+        "type",
+        "enum",
+        "const",
+        "$ref",
+        "properties",
+        "items",
+        "anyOf",
+        "oneOf",
+        "allOf",
+    ];
+    schema
+        .as_object()
+        .is_some_and(|o| KEYWORDS.iter().any(|k| o.contains_key(*k)))
+}
+
+// Keeps one tool per name: the last one added, at the first one's position.
+fn dedupe_tools(tools: Vec<Tool>) -> Vec<Tool> {
+    let mut deduped: Vec<Tool> = Vec::with_capacity(tools.len());
+    for tool in tools {
+        match deduped.iter_mut().find(|t| t.name == tool.name) {
+            Some(existing) => *existing = tool,
+            None => deduped.push(tool),
+        }
+    }
+    deduped
 }
 
 impl<'a> ModelRequestBuilder<'a> {
@@ -216,8 +1144,29 @@ impl<'a> ModelRequestBuilder<'a> {
             model,
             system: None,
             messages: None,
+            pinned_messages: None,
             settings: None,
             tools: None,
+            duplicate_tool_policy: None,
+            function_calling_mode: None,
+            response_schema: None,
+            enum_response: None,
+            logprobs: None,
+            response_modalities: None,
+            safety_settings: None,
+            extra_generation_config: None,
+            retry: None,
+            rate_limiter: None,
+            observer: None,
+            max_stream_tokens: None,
+            stop_on: None,
+            first_token_timeout: None,
+            max_stream_duration: None,
+            stream_buffer: None,
+            before_send: None,
+            client: None,
+            max_response_bytes: None,
+            locale: None,
         }
     }
 
@@ -226,6 +1175,18 @@ impl<'a> ModelRequestBuilder<'a> {
         return self;
     }
 
+    /// Renders labeled sections as `## <label>\n<body>` blocks, in the order
+    /// given and separated by a blank line, and uses the result as the system
+    /// prompt.
+    pub fn with_system_sections(&mut self, sections: Vec<(String, String)>) -> &mut Self {
+        let rendered = sections
+            .iter()
+            .map(|(label, body)| format!("## {}\n{}", label, body))
+            .collect::<Vec<String>>()
+            .join("\n\n");
+        self.with_system(rendered)
+    }
+
     pub fn with_message(&mut self, message: Message) -> &mut Self {
         match &mut self.messages {
             None => self.messages = Some(vec![message]),
@@ -242,45 +1203,625 @@ impl<'a> ModelRequestBuilder<'a> {
         return self;
     }
 
+    /// Adds a message to the stable prefix of the conversation: pinned
+    /// messages are sent right after the system prompt, in the order they were
+    /// pinned, before every message added with `with_message`, whatever the
+    /// call order. Put large, unchanging content (documents, reference
+    /// material) here and the changing question last, so that requests share
+    /// a prefix and hit Gemini's implicit context cache.
+    ///
+    /// `clear_messages` keeps pinned messages; `reset` drops them.
+    pub fn with_pinned_message(&mut self, message: Message) -> &mut Self {
+        match &mut self.pinned_messages {
+            None => self.pinned_messages = Some(vec![message]),
+            Some(ms) => ms.push(message),
+        }
+        return self;
+    }
+
+    /// Appends a few-shot example: a user turn followed by the model's answer.
+    pub fn with_example(&mut self, user: impl Into<String>, model: impl Into<String>) -> &mut Self {
+        self.with_messages(vec![
+            Message::user(user.into()),
+            Message::model(model.into()),
+        ])
+    }
+
     pub fn with_settings(&mut self, settings: Settings) -> &mut Self {
         self.settings = Some(settings);
         return self;
     }
 
+    /// Sets a thinking budget together with a `max_tokens` that leaves
+    /// `answer_tokens` for the answer. Providers count thinking against the
+    /// output limit, so `max_tokens` becomes `answer_tokens + thinking_budget`
+    /// (saturating at `i16::MAX`); a budget of `0` or `-1` (dynamic) adds
+    /// nothing. Other settings are kept.
+    pub fn with_thinking_budget(&mut self, answer_tokens: i16, thinking_budget: i16) -> &mut Self {
+        let settings = self.settings.get_or_insert_default();
+        settings.thinking_budget = Some(thinking_budget);
+        settings.max_tokens = Some(answer_tokens.saturating_add(thinking_budget.max(0)));
+        return self;
+    }
+
+    /// Turns thinking off with a budget of `0`, for latency-sensitive calls
+    /// on thinking-capable models. Unlike leaving `thinking_budget` unset,
+    /// which lets the provider decide, this always sends the `0` (with
+    /// `includeThoughts: false` on Gemini). `max_tokens` is kept.
+    pub fn with_thinking_disabled(&mut self) -> &mut Self {
+        self.settings.get_or_insert_default().thinking_budget = Some(0);
+        return self;
+    }
+
     pub fn with_tool(&mut self, tool: Tool) -> &mut Self {
-        match self.tools {
+        match &mut self.tools {
             None => self.tools = Some(vec![tool]),
-            Some(_) => {
-                self.tools.clone().map(|mut ts| ts.push(tool));
-            }
+            Some(ts) => ts.push(tool),
         }
         return self;
     }
 
     pub fn with_tools(&mut self, tools: Vec<Tool>) -> &mut Self {
-        match self.tools {
+        match &mut self.tools {
             None => self.tools = Some(tools),
-            Some(_) => {
-                self.tools.clone().map(|mut ts| ts.extend(tools));
-            }
+            Some(ts) => ts.extend(tools),
         }
         return self;
     }
 
+    /// How tools added more than once under the same name are handled;
+    /// `DuplicateToolPolicy::LastWins` when unset.
+    pub fn with_duplicate_tool_policy(&mut self, policy: DuplicateToolPolicy) -> &mut Self {
+        self.duplicate_tool_policy = Some(policy);
+        return self;
+    }
+
+    pub fn with_function_calling_mode(&mut self, mode: FunctionCallingMode) -> &mut Self {
+        self.function_calling_mode = Some(mode);
+        return self;
+    }
+
+    /// Constrains the response to JSON matching the schema of `T`.
+    pub fn with_response_schema<T: JsonSchema>(&mut self) -> &mut Self {
+        self.response_schema = serde_json::to_value(schema_for!(T)).ok();
+        return self;
+    }
+
+    /// Constrains the response to JSON matching `schema`, a JSON Schema built
+    /// at runtime. The request fails validation if `schema` isn't a schema
+    /// object.
+    pub fn with_response_schema_value(&mut self, schema: Value) -> &mut Self {
+        self.response_schema = Some(schema);
+        return self;
+    }
+
+    /// Restricts the answer to one of `variants`, for classification. Read the
+    /// answer with `Completion::as_enum`.
+    pub fn with_enum_response(&mut self, variants: Vec<String>) -> &mut Self {
+        self.enum_response = Some(variants);
+        return self;
+    }
+
+    /// Requests log-probabilities for the generated tokens, with the `n` most
+    /// likely candidates at each position.
+    pub fn with_logprobs(&mut self, n: i32) -> &mut Self {
+        self.logprobs = Some(n);
+        return self;
+    }
+
+    /// Asks for these kinds of output, e.g. `vec![Modality::Text,
+    /// Modality::Image]` for a Gemini image model. Generated images come
+    /// back in `Completion::media`.
+    pub fn with_response_modalities(&mut self, modalities: Vec<Modality>) -> &mut Self {
+        self.response_modalities = Some(modalities);
+        return self;
+    }
+
+    /// Sets the temperature and top-p of `preset`, keeping the other
+    /// settings.
+    pub fn with_preset(&mut self, preset: GenerationPreset) -> &mut Self {
+        self.settings
+            .get_or_insert_with(Settings::default)
+            .apply_preset(preset);
+        return self;
+    }
+
+    /// Sets the safety thresholds, typically `SafetySettings::permissive()` or
+    /// `SafetySettings::strict()`.
+    pub fn with_safety_preset(&mut self, preset: SafetySettings) -> &mut Self {
+        self.safety_settings = Some(preset);
+        return self;
+    }
+
+    /// Adds raw fields to Gemini's `generationConfig`, e.g.
+    /// `json!({ "mediaResolution": "MEDIA_RESOLUTION_LOW" })`, for options
+    /// without a typed setter. Repeated calls merge, later keys replacing
+    /// earlier ones. Typed settings such as `max_tokens` take precedence over
+    /// the same key given here.
+    pub fn with_extra_generation_config(&mut self, config: Value) -> &mut Self {
+        match (&mut self.extra_generation_config, config) {
+            (Some(Value::Object(existing)), Value::Object(fields)) => existing.extend(fields),
+            (_, config) => self.extra_generation_config = Some(config),
+        }
+        return self;
+    }
+
+    /// Runs `hook` on the JSON body of every request right before it is sent,
+    /// after all typed options have been applied, so it can patch in fields
+    /// the typed API doesn't expose or sign the payload. Called again for each
+    /// retry. Honored by Gemini.
+    pub fn with_before_send(
+        &mut self,
+        hook: impl Fn(&mut Value) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.before_send = Some(Arc::new(hook));
+        return self;
+    }
+
+    /// Sends this request through `client` instead of the model's own client
+    /// (and its `transport`, if set), e.g. to route one tenant's requests
+    /// through its proxy. The request is still built by the model, with its
+    /// endpoint and credentials.
+    pub fn with_client(&mut self, client: reqwest::Client) -> &mut Self {
+        self.client = Some(client);
+        return self;
+    }
+
+    /// Caps the response body at `limit` bytes, failing with
+    /// `LangrustError::ResponseTooLarge` past it rather than buffering an
+    /// unbounded body, e.g. from an endpoint configured by an untrusted
+    /// party. Error bodies are cut off at the limit instead. Streams are not
+    /// capped.
+    pub fn with_max_response_bytes(&mut self, limit: usize) -> &mut Self {
+        self.max_response_bytes = Some(limit);
+        return self;
+    }
+
+    /// Sends each tool's descriptions in `locale`, e.g. `fr`, where it has a
+    /// translation for it (see `Tool::localized`).
+    pub fn with_locale(&mut self, locale: &str) -> &mut Self {
+        self.locale = Some(locale.to_string());
+        return self;
+    }
+
+    /// Retries transport failures (timeouts, connection errors) and
+    /// retryable API errors (see `LangrustError::is_retryable`) with
+    /// exponential backoff. Applies to `completion` and to opening a stream;
+    /// see `RetryConfig::retry_on_empty` for retrying empty completions.
+    pub fn with_retry(&mut self, retry: RetryConfig) -> &mut Self {
+        self.retry = Some(retry);
+        return self;
+    }
+
+    /// Waits on `limiter` before every attempt, retries included, instead of
+    /// the model's own (see `Model::rate_limiter`). Share one limiter between
+    /// all requests that draw from the same quota.
+    pub fn with_rate_limiter(&mut self, limiter: Arc<RateLimiter>) -> &mut Self {
+        self.rate_limiter = Some(limiter);
+        return self;
+    }
+
+    pub fn clear_messages(&mut self) -> &mut Self {
+        self.messages = None;
+        return self;
+    }
+
+    pub fn clear_tools(&mut self) -> &mut Self {
+        self.tools = None;
+        return self;
+    }
+
+    /// Drops everything set on the builder, keeping only the model.
+    pub fn reset(&mut self) -> &mut Self {
+        *self = ModelRequestBuilder::new(self.model);
+        return self;
+    }
+
+    /// Reports every attempt made through this builder to `observer`, instead
+    /// of the model's own (see `Model::observer`).
+    pub fn with_observer(&mut self, observer: Arc<dyn ModelObserver>) -> &mut Self {
+        self.observer = Some(observer);
+        return self;
+    }
+
+    /// Stops `stream()` once the completion text passes roughly `max_tokens`
+    /// tokens (by `estimate_text_tokens`), dropping the connection and ending with
+    /// `StreamEvent::Truncated`. The delta that crosses the limit is still
+    /// delivered.
+    pub fn with_max_stream_tokens(&mut self, max_tokens: u32) -> &mut Self {
+        self.max_stream_tokens = Some(max_tokens);
+        return self;
+    }
+
+    /// Stops `stream()` as soon as the text contains `sentinel`, e.g. an
+    /// agent protocol's end marker, dropping the connection and ending with
+    /// `StreamEvent::Stopped`. Only the text before the sentinel is
+    /// delivered; text that may be its start is held back until the next
+    /// delta settles it. `stream()` fails with `LangrustError::InvalidRequest`
+    /// if `sentinel` is empty.
+    pub fn with_stop_on(&mut self, sentinel: &str) -> &mut Self {
+        self.stop_on = Some(sentinel.to_string());
+        return self;
+    }
+
+    /// Fails `stream()` with `LangrustError::FirstTokenTimeout` if no event
+    /// arrives within `timeout`, counting from when the request is sent. A
+    /// model that is slow to start is caught early, without capping how long
+    /// a stream that is under way may run. Applies to each retry.
+    pub fn with_first_token_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.first_token_timeout = Some(timeout);
+        return self;
+    }
+
+    /// Ends `stream()` with a `StreamEvent::Error` for
+    /// `LangrustError::StreamTimeout` once `limit` has passed since it was
+    /// called, however steadily events are arriving.
+    pub fn with_max_stream_duration(&mut self, limit: Duration) -> &mut Self {
+        self.max_stream_duration = Some(limit);
+        return self;
+    }
+
+    /// How many events `stream_channel()` buffers ahead of its receiver; 32
+    /// when unset. See `CompletionStream::into_channel` for the tradeoff.
+    /// `stream_channel()` fails with `LangrustError::InvalidRequest` if `0`.
+    pub fn with_stream_buffer(&mut self, buffer: usize) -> &mut Self {
+        self.stream_buffer = Some(buffer);
+        return self;
+    }
+
     pub async fn completion(&self) -> Result<Completion, Box<dyn Error + Send + Sync>> {
-        self.model.completion(self.to_model_request()).await
+        let cache = self.model.cache().and_then(|cache| {
+            let key = CompletionCache::key(&self.model.model_name(), &self.to_model_request())?;
+            Some((cache, key))
+        });
+        if let Some((cache, key)) = &cache
+            && let Some(completion) = cache.get(key)
+        {
+            return Ok(completion);
+        }
+
+        let mut attempt = 0;
+        loop {
+            self.wait_for_rate_limit().await;
+            let started = self.notify_request(false);
+            let result = self.model.completion(self.to_model_request()).await;
+            self.notify_result(started, false, &result, |c| c.usage.clone());
+            match result {
+                Err(e) if self.should_retry(attempt, &*e) => {
+                    self.wait_before_retry(attempt).await;
+                    attempt += 1;
+                }
+                Ok(completion) if self.should_retry_empty(attempt, &completion) => {
+                    self.wait_before_retry(attempt).await;
+                    attempt += 1;
+                }
+                Ok(completion) => {
+                    if let Some((cache, key)) = cache {
+                        cache.insert(key, completion.clone());
+                    }
+                    return Ok(completion);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Asks for `n` candidate answers in one request (Gemini's
+    /// `candidateCount`) and returns them in order. The provider may return
+    /// fewer, e.g. when some are filtered. Every candidate carries the usage
+    /// of the whole request; see `Model::completions`.
+    pub async fn completion_n(
+        &self,
+        n: u32,
+    ) -> Result<Vec<Completion>, Box<dyn Error + Send + Sync>> {
+        let mut attempt = 0;
+        loop {
+            self.wait_for_rate_limit().await;
+            let started = self.notify_request(false);
+            let result = self.model.completions(self.to_model_request(), n).await;
+            self.notify_result(started, false, &result, |cs| {
+                cs.first().and_then(|c| c.usage.clone())
+            });
+            match result {
+                Err(e) if self.should_retry(attempt, &*e) => {
+                    self.wait_before_retry(attempt).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub async fn stream(&self) -> Result<CompletionStream, Box<dyn Error + Send + Sync>> {
+        if self.stop_on.as_deref() == Some("") {
+            return Err(LangrustError::InvalidRequest(
+                "stop sentinel must not be empty".to_string(),
+            )
+            .into());
+        }
+        let deadline = self
+            .max_stream_duration
+            .map(|limit| (tokio::time::Instant::now() + limit, limit));
+        let mut attempt = 0;
+        loop {
+            self.wait_for_rate_limit().await;
+            let started = self.notify_request(true);
+            let open = self.model.stream_completion(self.to_model_request());
+            let result = match self.first_token_timeout {
+                Some(timeout) => open_with_first_token_timeout(open, timeout).await,
+                None => open.await,
+            };
+            self.notify_result(started, true, &result, |_| None);
+            match result {
+                Err(e) if self.should_retry(attempt, &*e) => {
+                    self.wait_before_retry(attempt).await;
+                    attempt += 1;
+                }
+                result => {
+                    let mut stream = result.map(CompletionStream::new)?;
+                    if let Some((deadline, limit)) = deadline {
+                        stream = stream.limit_duration(deadline, limit);
+                    }
+                    if let Some(sentinel) = &self.stop_on {
+                        stream = stream.stop_on(sentinel.clone());
+                    }
+                    return Ok(match self.max_stream_tokens {
+                        Some(max_tokens) => stream.limit_tokens(max_tokens),
+                        None => stream,
+                    });
+                }
+            }
+        }
     }
 
-    pub async fn stream(&self) -> Result<StreamResult, Box<dyn Error + Send + Sync>> {
-        self.model.stream_completion(self.to_model_request()).await
+    /// Like `stream`, but also returns a `StreamHandle` whose `abort()` ends
+    /// the stream from another task with `StreamEvent::Cancelled`.
+    pub async fn stream_with_handle(
+        &self,
+    ) -> Result<(CompletionStream, StreamHandle), Box<dyn Error + Send + Sync>> {
+        Ok(self.stream().await?.abortable())
+    }
+
+    /// Streams the response into `writer`, writing and flushing each delta as
+    /// it arrives (e.g. to stdout in a CLI), and returns the whole
+    /// `Completion` once the stream ends. A mid-stream error is returned as
+    /// the error; the text before it has already been written.
+    pub async fn stream_to_writer<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+    ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
+        let mut stream = self.stream().await?;
+        let mut acc = CompletionAccumulator::default();
+        while let Some(event) = stream.next().await {
+            if let Some(delta) = acc.add(event)? {
+                writer.write_all(delta.as_bytes()).await?;
+                writer.flush().await?;
+            }
+        }
+        Ok(acc.finish(self.model.model_name(), self.enum_response.clone()))
+    }
+
+    /// Opens the stream and forwards its events to a channel from a spawned
+    /// task, buffering up to `with_stream_buffer` of them, so the consumer
+    /// can run anywhere without deadlocking the reader.
+    pub async fn stream_channel(
+        &self,
+    ) -> Result<mpsc::Receiver<StreamEvent>, Box<dyn Error + Send + Sync>> {
+        let buffer = self.stream_buffer.unwrap_or(DEFAULT_STREAM_BUFFER);
+        if buffer == 0 {
+            return Err(LangrustError::InvalidRequest(
+                "stream buffer must hold at least one event".to_string(),
+            )
+            .into());
+        }
+        Ok(self.stream().await?.into_channel(buffer)?)
+    }
+
+    /// Like `stream_to_writer`, but sends each delta to `tx`. Fails if the
+    /// receiver is dropped before the stream ends. Each send waits for room
+    /// in the channel, so the receiver must be drained concurrently unless
+    /// its buffer holds every delta; `stream_channel` needs no such care.
+    pub async fn stream_to_channel(
+        &self,
+        tx: mpsc::Sender<String>,
+    ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
+        let mut stream = self.stream().await?;
+        let mut acc = CompletionAccumulator::default();
+        while let Some(event) = stream.next().await {
+            if let Some(delta) = acc.add(event)? {
+                tx.send(delta)
+                    .await
+                    .map_err(|_| "stream receiver was dropped")?;
+            }
+        }
+        Ok(acc.finish(self.model.model_name(), self.enum_response.clone()))
+    }
+
+    /// Streams a response constrained to the schema of `T`, yielding the JSON
+    /// parsed so far as it grows and finally the typed value.
+    pub async fn json_stream<T: JsonSchema + DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> Result<JsonStream<T>, Box<dyn Error + Send + Sync>> {
+        let mut builder = self.clone();
+        builder.with_response_schema::<T>();
+        Ok(json_stream::json_events(builder.stream().await?))
+    }
+
+    /// Asks for a response constrained to the schema of `T` and parses it,
+    /// in one call. Fails with `LangrustError::InvalidResponse` if the answer
+    /// isn't valid JSON for `T`.
+    pub async fn completion_json<T: JsonSchema + DeserializeOwned>(
+        &self,
+    ) -> Result<T, LangrustError> {
+        let mut builder = self.clone();
+        builder.with_response_schema::<T>();
+        let completion = builder.completion().await?;
+        serde_json::from_str(&completion.completion).map_err(|e| {
+            LangrustError::InvalidResponse(format!(
+                "expected JSON matching the response schema: {}",
+                e
+            ))
+        })
+    }
+
+    /// Runs the tool loop: sends the request and, while the model answers with
+    /// function calls, passes each call to `handler` and sends its
+    /// `ToolResult` back, until the model answers with text. The handler is
+    /// async so that tools can await I/O; calls of one turn run one after
+    /// another. A handler error is reported to the model rather than aborting
+    /// the loop. Fails if the model is still calling tools after `max_steps`
+    /// turns of calls.
+    pub async fn run_tools<F>(
+        &self,
+        max_steps: usize,
+        handler: impl Fn(&FunctionCall) -> F + Send + Sync,
+    ) -> Result<Completion, Box<dyn Error + Send + Sync>>
+    where
+        F: Future<Output = ToolResult> + Send,
+    {
+        let mut builder = self.clone();
+        let mut steps = 0;
+        loop {
+            let completion = builder.completion().await?;
+            if completion.tool_calls().is_empty() {
+                return Ok(completion);
+            }
+            if steps == max_steps {
+                return Err(
+                    format!("model was still calling tools after {} turns", max_steps).into(),
+                );
+            }
+            steps += 1;
+            builder.with_message(Message::from_completion(&completion));
+            for call in completion.tool_calls() {
+                builder.with_message(handler(call).await.to_message(call));
+            }
+        }
+    }
+
+    fn notify_request(&self, streaming: bool) -> Instant {
+        if let Some(observer) = self.observer() {
+            observer.on_request(&RequestEvent {
+                model: self.model.model_name(),
+                streaming,
+            });
+        }
+        Instant::now()
+    }
+
+    fn notify_result<T>(
+        &self,
+        started: Instant,
+        streaming: bool,
+        result: &Result<T, Box<dyn Error + Send + Sync>>,
+        usage: impl Fn(&T) -> Option<Usage>,
+    ) {
+        let Some(observer) = self.observer() else {
+            return;
+        };
+        let model = self.model.model_name();
+        let latency = started.elapsed();
+        match result {
+            Ok(value) => observer.on_response(&ResponseEvent {
+                model,
+                streaming,
+                latency,
+                usage: usage(value),
+            }),
+            Err(e) => observer.on_error(&ErrorEvent {
+                model,
+                streaming,
+                latency,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    fn observer(&self) -> Option<&dyn ModelObserver> {
+        self.observer.as_deref().or(self.model.observer())
+    }
+
+    fn should_retry(&self, attempt: u32, error: &(dyn Error + Send + Sync + 'static)) -> bool {
+        match &self.retry {
+            Some(r) => attempt < r.max_retries && retry::is_retryable(error),
+            None => false,
+        }
+    }
+
+    fn should_retry_empty(&self, attempt: u32, completion: &Completion) -> bool {
+        match &self.retry {
+            Some(r) => {
+                r.retry_on_empty
+                    && attempt < r.max_retries
+                    && completion.function.is_none()
+                    && completion.completion.trim().is_empty()
+            }
+            None => false,
+        }
+    }
+
+    async fn wait_for_rate_limit(&self) {
+        let limiter = self.rate_limiter.as_deref().or(self.model.rate_limiter());
+        if let Some(limiter) = limiter {
+            let tokens = rate_limit::estimate_tokens(&self.to_model_request());
+            limiter.acquire(tokens).await;
+        }
+    }
+
+    async fn wait_before_retry(&self, attempt: u32) {
+        if let Some(r) = &self.retry {
+            tokio::time::sleep(r.delay_for_attempt(attempt)).await;
+        }
     }
 
     pub fn to_model_request(&self) -> ModelRequest {
         ModelRequest {
             system: self.system.clone(),
-            messages: self.messages.clone(),
-            settings: self.settings.clone(),
-            tools: self.tools.clone(),
+            messages: match (&self.pinned_messages, &self.messages) {
+                (Some(pinned), Some(messages)) => Some([pinned.clone(), messages.clone()].concat()),
+                (Some(pinned), None) => Some(pinned.clone()),
+                (None, messages) => messages.clone(),
+            },
+            settings: match self.model.defaults() {
+                Some(defaults) => Some(
+                    self.settings
+                        .clone()
+                        .unwrap_or_default()
+                        .or_defaults(defaults),
+                ),
+                None => self.settings.clone(),
+            },
+            tools: self.tools.as_ref().map(|ts| {
+                let ts = ts
+                    .iter()
+                    .map(|t| t.localized(self.locale.as_deref()))
+                    .collect();
+                match self.duplicate_tool_policy {
+                    Some(DuplicateToolPolicy::Error) => ts,
+                    _ => dedupe_tools(ts),
+                }
+            }),
+            function_calling_mode: self.function_calling_mode.clone(),
+            response_schema: self.response_schema.clone(),
+            enum_response: self.enum_response.clone(),
+            logprobs: self.logprobs,
+            response_modalities: self.response_modalities.clone(),
+            safety_settings: self.safety_settings.clone(),
+            extra_generation_config: self.extra_generation_config.clone(),
+            before_send: self.before_send.clone(),
+            client: self.client.clone(),
+            max_response_bytes: self.max_response_bytes,
         }
     }
 }
+
+/// Sends `prompt` to `model` as a single user message and returns the text of
+/// the answer: the shortest way to get a completion, for scripts. Use the
+/// builder from `Model::new_request` for anything more.
+pub async fn ask(model: &dyn Model, prompt: &str) -> Result<String, LangrustError> {
+    let completion = ModelRequestBuilder::new(model)
+        .with_message(Message::user(prompt.to_string()))
+        .completion()
+        .await?;
+    Ok(completion.completion)
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use super::Usage;
+
+/// Receives an event around every request sent through a
+/// `ModelRequestBuilder`, for feeding metrics systems. Attach one to a model
+/// with its `with_observer`. Retried attempts are
+/// reported individually. All methods default to doing nothing.
+///
+/// Callbacks run inline on the request's task, so they should be cheap.
+pub trait ModelObserver: Send + Sync {
+    fn on_request(&self, _event: &RequestEvent) {}
+    fn on_response(&self, _event: &ResponseEvent) {}
+    fn on_error(&self, _event: &ErrorEvent) {}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestEvent {
+    pub model: String,
+    pub streaming: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseEvent {
+    pub model: String,
+    pub streaming: bool,
+    /// Time until the completion arrived or, for a stream, until it opened.
+    pub latency: Duration,
+    /// Token usage of a completion. `None` for streams, which report usage in
+    /// their final event.
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorEvent {
+    pub model: String,
+    pub streaming: bool,
+    pub latency: Duration,
+    pub error: String,
+}
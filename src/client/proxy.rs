@@ -0,0 +1,68 @@
+/// Proxies for the `reqwest::Client` a model sends its requests through, for
+/// networks that only reach the internet through one. Set with the model's
+/// `with_proxy`, once, when the client is built.
+///
+/// A client built without one still honors `HTTP_PROXY`, `HTTPS_PROXY` and
+/// `NO_PROXY`, as reqwest reads them itself; setting a proxy here replaces
+/// them.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProxyConfig {
+    /// Proxy for `http://` URLs, e.g. `http://proxy.corp:3128`.
+    pub http_proxy: Option<String>,
+    /// Proxy for `https://` URLs, which every provider endpoint is. The
+    /// connection is tunnelled through it with `CONNECT`.
+    pub https_proxy: Option<String>,
+    /// Hosts reached directly rather than through either proxy, comma
+    /// separated as in `NO_PROXY`, e.g. `localhost,.internal`.
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    /// The proxies in `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`, or their
+    /// lowercase forms, for a config that starts from the environment.
+    pub fn from_env() -> ProxyConfig {
+        ProxyConfig::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    // Like `from_env`, reading variables through `lookup`.
+    pub(crate) fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> ProxyConfig {
+        let var = |name: &str| {
+            lookup(name)
+                .or_else(|| lookup(&name.to_ascii_lowercase()))
+                .filter(|v| !v.is_empty())
+        };
+        ProxyConfig {
+            http_proxy: var("HTTP_PROXY"),
+            https_proxy: var("HTTPS_PROXY"),
+            no_proxy: var("NO_PROXY"),
+        }
+    }
+
+    /// Applies the proxies that are set to `builder`. Fails if a proxy URL
+    /// doesn't parse. Combines with `HttpTimeouts::apply` when both are
+    /// wanted.
+    pub fn apply(
+        &self,
+        builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, reqwest::Error> {
+        let no_proxy = || {
+            self.no_proxy
+                .as_deref()
+                .and_then(reqwest::NoProxy::from_string)
+        };
+        let mut builder = builder;
+        if let Some(url) = &self.http_proxy {
+            builder = builder.proxy(reqwest::Proxy::http(url)?.no_proxy(no_proxy()));
+        }
+        if let Some(url) = &self.https_proxy {
+            builder = builder.proxy(reqwest::Proxy::https(url)?.no_proxy(no_proxy()));
+        }
+        Ok(builder)
+    }
+
+    /// A client with these proxies and reqwest's defaults otherwise, ready to
+    /// pass as a model's `client`.
+    pub fn client(&self) -> Result<reqwest::Client, reqwest::Error> {
+        self.apply(reqwest::Client::builder())?.build()
+    }
+}
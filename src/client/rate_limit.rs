@@ -0,0 +1,138 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use super::{LangrustError, ModelRequest, chunking::estimate_tokens_for_len};
+
+/// Token-bucket limiter shared by every request made against one quota.
+///
+/// Wrap it in an `Arc` and attach it to every model that draws from the same
+/// API key with the model's `with_rate_limiter`, or to a single request with
+/// `ModelRequestBuilder::with_rate_limiter`. Each bucket holds up to a
+/// minute's allowance and refills continuously; a request that would overdraw
+/// it waits until enough has refilled instead of failing.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    capacity: f64,
+    per_second: f64,
+}
+
+#[derive(Debug)]
+struct State {
+    requests: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// A limiter allowing `requests_per_minute` requests and
+    /// `tokens_per_minute` tokens; `None` leaves that dimension unlimited.
+    /// Fails with `LangrustError::InvalidRequest` if either limit is
+    /// `Some(0)`, which would never let a request through.
+    pub fn new(
+        requests_per_minute: Option<u32>,
+        tokens_per_minute: Option<u32>,
+    ) -> Result<RateLimiter, LangrustError> {
+        if requests_per_minute == Some(0) || tokens_per_minute == Some(0) {
+            return Err(LangrustError::InvalidRequest(
+                "rate limits must be non-zero".to_string(),
+            ));
+        }
+        let bucket = |per_minute: u32| Bucket {
+            capacity: per_minute as f64,
+            per_second: per_minute as f64 / 60.0,
+        };
+        let requests = requests_per_minute.map(bucket);
+        let tokens = tokens_per_minute.map(bucket);
+        Ok(RateLimiter {
+            requests,
+            tokens,
+            state: Mutex::new(State {
+                requests: requests.map_or(0.0, |b| b.capacity),
+                tokens: tokens.map_or(0.0, |b| b.capacity),
+                updated: Instant::now(),
+            }),
+        })
+    }
+
+    /// Waits until one request costing `tokens` tokens fits in the limits, then
+    /// takes it out of the buckets. A cost above the per-minute token limit is
+    /// capped to it, so an oversized request waits for a full bucket rather
+    /// than forever.
+    pub async fn acquire(&self, tokens: u32) {
+        loop {
+            let wait = self.try_acquire(tokens as f64);
+            if wait.is_zero() {
+                return;
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Takes the request from the buckets and returns zero, or returns how long
+    // to wait before the buckets can cover it.
+    fn try_acquire(&self, tokens: f64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.updated).as_secs_f64();
+        state.updated = now;
+        if let Some(b) = self.requests {
+            state.requests = (state.requests + elapsed * b.per_second).min(b.capacity);
+        }
+        if let Some(b) = self.tokens {
+            state.tokens = (state.tokens + elapsed * b.per_second).min(b.capacity);
+        }
+
+        let requests_wait = self
+            .requests
+            .map_or(0.0, |b| shortfall(state.requests, 1.0, b));
+        let tokens_wait = self
+            .tokens
+            .map_or(0.0, |b| shortfall(state.tokens, tokens.min(b.capacity), b));
+        let wait = requests_wait.max(tokens_wait);
+        if wait > 0.0 {
+            return Duration::from_secs_f64(wait);
+        }
+
+        if self.requests.is_some() {
+            state.requests -= 1.0;
+        }
+        if let Some(b) = self.tokens {
+            state.tokens -= tokens.min(b.capacity);
+        }
+        Duration::ZERO
+    }
+}
+
+// Seconds until `available` refills to `needed`.
+fn shortfall(available: f64, needed: f64, bucket: Bucket) -> f64 {
+    match available >= needed {
+        true => 0.0,
+        false => (needed - available) / bucket.per_second,
+    }
+}
+
+/// Rough token cost of a request, used before the provider reports usage: the
+/// prompt text's `estimate_text_tokens`, plus the `max_tokens` budget.
+pub(crate) fn estimate_tokens(request: &ModelRequest) -> u32 {
+    let len: usize = request.system.as_ref().map_or(0, |s| s.len())
+        + request
+            .messages
+            .iter()
+            .flatten()
+            .map(|m| m.content.len())
+            .sum::<usize>();
+    let completion = request
+        .settings
+        .as_ref()
+        .and_then(|s| s.max_tokens)
+        .map_or(0, |t| t.max(0) as u32);
+    estimate_tokens_for_len(len) as u32 + completion
+}
@@ -0,0 +1,123 @@
+use std::{collections::VecDeque, error::Error, sync::Mutex};
+
+use async_trait::async_trait;
+use futures::stream;
+
+use super::{
+    Completion, FunctionCall, Message, Model, ModelRequest, StreamEvent, StreamResult, Usage,
+};
+
+/// Offline `Model` that answers with scripted completions, in order, for
+/// testing code built on langrust without calling a provider. Available with
+/// the `testing` feature.
+///
+/// Panics if asked for more completions than were scripted. The messages of
+/// every request are recorded for assertions with `requests()`.
+///
+/// ```ignore
+/// let model = ReplayModel::new()
+///     .then_call(FunctionCall { name: "get_weather".into(), args, id: None })
+///     .then_text("It is sunny in Paris.");
+/// ```
+pub struct ReplayModel {
+    replies: Mutex<VecDeque<Completion>>,
+    requests: Mutex<Vec<Vec<Message>>>,
+}
+
+impl ReplayModel {
+    pub fn new() -> ReplayModel {
+        ReplayModel {
+            replies: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds `completion` to the script.
+    pub fn then(self, completion: Completion) -> ReplayModel {
+        self.replies.lock().unwrap().push_back(completion);
+        return self;
+    }
+
+    /// Adds a text answer to the script.
+    pub fn then_text(self, text: impl Into<String>) -> ReplayModel {
+        self.then(reply(text.into(), None))
+    }
+
+    /// Adds a function call to the script.
+    pub fn then_call(self, call: FunctionCall) -> ReplayModel {
+        self.then(reply(String::new(), Some(call)))
+    }
+
+    /// The messages of each request received so far, in order.
+    pub fn requests(&self) -> Vec<Vec<Message>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Number of scripted completions not yet returned.
+    pub fn remaining(&self) -> usize {
+        self.replies.lock().unwrap().len()
+    }
+
+    fn next_reply(&self, request: ModelRequest) -> Completion {
+        let mut requests = self.requests.lock().unwrap();
+        requests.push(request.messages.unwrap_or_default());
+        let call = requests.len();
+        self.replies.lock().unwrap().pop_front().unwrap_or_else(|| {
+            panic!(
+                "ReplayModel called {} times but only {} completions were scripted",
+                call,
+                call - 1
+            )
+        })
+    }
+}
+
+impl Default for ReplayModel {
+    fn default() -> ReplayModel {
+        ReplayModel::new()
+    }
+}
+
+#[async_trait]
+impl Model for ReplayModel {
+    async fn completion(
+        &self,
+        request: ModelRequest,
+    ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
+        Ok(self.next_reply(request))
+    }
+
+    /// Streams the next scripted completion as one delta, its function call
+    /// and its usage.
+    async fn stream_completion(
+        &self,
+        request: ModelRequest,
+    ) -> Result<StreamResult, Box<dyn Error + Send + Sync>> {
+        let completion = self.next_reply(request);
+        let mut events = Vec::new();
+        if !completion.completion.is_empty() {
+            events.push(StreamEvent::Delta(completion.completion));
+        }
+        events.extend(completion.function.map(StreamEvent::FunctionCall));
+        events.extend(completion.usage.map(StreamEvent::Usage));
+        Ok(Box::pin(stream::iter(events)))
+    }
+
+    fn model_name(&self) -> String {
+        "replay".to_string()
+    }
+}
+
+fn reply(text: String, function: Option<FunctionCall>) -> Completion {
+    Completion {
+        completion: text,
+        usage: Some(Usage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+        }),
+        function,
+        model: "replay".to_string(),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::hash_map::RandomState,
+    error::Error,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use super::LangrustError;
+
+/// How much randomness is applied to each backoff delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JitterMode {
+    /// Uniformly random delay in `[0, backoff]`. Spreads retries from many
+    /// clients the most; the default.
+    Full,
+    /// Half of the backoff plus a uniformly random delay in `[0, backoff / 2]`.
+    Equal,
+    /// Exactly the exponential backoff. Intended for tests that assert timing.
+    None,
+}
+
+/// Exponential backoff used by `ModelRequestBuilder::with_retry`.
+///
+/// The backoff for attempt `n` (starting at 0) is `base_delay * 2^n`, capped
+/// at `max_delay`, and then jittered according to `jitter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: JitterMode,
+    /// Also retry a successful completion with no text and no function call,
+    /// which Gemini occasionally returns under load. Off by default.
+    pub retry_on_empty: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: JitterMode::Full,
+            retry_on_empty: false,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Delay to wait before retry number `attempt` (0 for the first retry).
+    pub fn delay_for_attempt(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        match self.jitter {
+            JitterMode::None => backoff,
+            JitterMode::Full => backoff.mul_f64(random_fraction()),
+            JitterMode::Equal => backoff / 2 + (backoff / 2).mul_f64(random_fraction()),
+        }
+    }
+}
+
+/// Whether sending the request again, or to another model, could succeed: a
+/// timeout or failed connection, or a `LangrustError` whose `is_retryable()`
+/// is true. Shared by `with_retry` and `FallbackModel`.
+pub(crate) fn is_retryable(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+        return e.is_timeout() || e.is_connect();
+    }
+    error
+        .downcast_ref::<LangrustError>()
+        .is_some_and(LangrustError::is_retryable)
+}
+
+// Cheap uniform value in [0, 1) without pulling in a RNG crate; `RandomState`
+// is seeded randomly per instance.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
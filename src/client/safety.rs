@@ -0,0 +1,104 @@
+/// Harm category a safety rating applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HarmCategory {
+    Harassment,
+    HateSpeech,
+    SexuallyExplicit,
+    DangerousContent,
+    CivicIntegrity,
+    /// A category this version of the crate doesn't know, as sent.
+    Other(String),
+}
+
+/// How likely the content is to be harmful in a category, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HarmProbability {
+    Unspecified,
+    Negligible,
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyRating {
+    pub category: HarmCategory,
+    pub probability: HarmProbability,
+    /// Whether this rating is what caused the content to be blocked.
+    pub blocked: bool,
+}
+
+/// Per-category safety ratings reported by the provider for a prompt or a
+/// response.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SafetyRatings {
+    pub ratings: Vec<SafetyRating>,
+}
+
+impl SafetyRatings {
+    pub fn probability(&self, category: &HarmCategory) -> Option<HarmProbability> {
+        self.ratings
+            .iter()
+            .find(|r| &r.category == category)
+            .map(|r| r.probability)
+    }
+
+    /// The ratings that caused a block.
+    pub fn blocked(&self) -> Vec<&SafetyRating> {
+        self.ratings.iter().filter(|r| r.blocked).collect()
+    }
+}
+
+/// Probability at or above which Gemini blocks content in a category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HarmBlockThreshold {
+    BlockNone,
+    BlockOnlyHigh,
+    BlockMediumAndAbove,
+    BlockLowAndAbove,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetySetting {
+    pub category: HarmCategory,
+    pub threshold: HarmBlockThreshold,
+}
+
+/// Per-category blocking thresholds sent with a request. Honored by Gemini;
+/// categories left out keep the provider's default.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SafetySettings(pub Vec<SafetySetting>);
+
+impl SafetySettings {
+    /// The categories every Gemini model rates.
+    pub const STANDARD_CATEGORIES: [HarmCategory; 4] = [
+        HarmCategory::Harassment,
+        HarmCategory::HateSpeech,
+        HarmCategory::SexuallyExplicit,
+        HarmCategory::DangerousContent,
+    ];
+
+    /// The same threshold for every standard category.
+    pub fn all(threshold: HarmBlockThreshold) -> SafetySettings {
+        SafetySettings(
+            SafetySettings::STANDARD_CATEGORIES
+                .into_iter()
+                .map(|category| SafetySetting {
+                    category,
+                    threshold,
+                })
+                .collect(),
+        )
+    }
+
+    /// Blocks nothing in any standard category.
+    pub fn permissive() -> SafetySettings {
+        SafetySettings::all(HarmBlockThreshold::BlockNone)
+    }
+
+    /// Blocks anything rated low probability or above in every standard
+    /// category.
+    pub fn strict() -> SafetySettings {
+        SafetySettings::all(HarmBlockThreshold::BlockLowAndAbove)
+    }
+}
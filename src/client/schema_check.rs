@@ -0,0 +1,168 @@
+use serde_json::Value;
+
+// Where a value first departs from a schema, for
+// `LangrustError::SchemaMismatch`.
+pub(crate) struct Mismatch {
+    pub(crate) path: String,
+    pub(crate) expected: String,
+}
+
+// Checks `value` against `schema` for the keywords schemars emits: `type`,
+// `enum`, `const`, `properties`/`required`, `items`, `minimum`/`maximum`,
+// `anyOf`/`oneOf`/`allOf` and local `$ref`s. Anything else is accepted.
+pub(crate) fn check(value: &Value, schema: &Value) -> Result<(), Mismatch> {
+    check_at(value, schema, schema, "$")
+}
+
+fn check_at(value: &Value, schema: &Value, root: &Value, path: &str) -> Result<(), Mismatch> {
+    let schema = resolve(schema, root);
+    let Some(keywords) = schema.as_object() else {
+        // `true` or a malformed schema: nothing to check.
+        return Ok(());
+    };
+    let mismatch = || Mismatch {
+        path: path.to_string(),
+        expected: describe(schema, root),
+    };
+
+    if let Some(branches) = keywords
+        .get("anyOf")
+        .or_else(|| keywords.get("oneOf"))
+        .and_then(Value::as_array)
+        && !branches
+            .iter()
+            .any(|b| check_at(value, b, root, path).is_ok())
+    {
+        return Err(mismatch());
+    }
+    if let Some(branches) = keywords.get("allOf").and_then(Value::as_array) {
+        for branch in branches {
+            check_at(value, branch, root, path)?;
+        }
+    }
+    if let Some(types) = keywords.get("type")
+        && !type_names(types).iter().any(|t| has_type(value, t))
+    {
+        return Err(mismatch());
+    }
+    if let Some(allowed) = keywords.get("enum").and_then(Value::as_array)
+        && !allowed.contains(value)
+    {
+        return Err(mismatch());
+    }
+    if let Some(constant) = keywords.get("const")
+        && constant != value
+    {
+        return Err(mismatch());
+    }
+    if let Some(n) = value.as_f64() {
+        let below = keywords
+            .get("minimum")
+            .and_then(Value::as_f64)
+            .is_some_and(|min| n < min);
+        let above = keywords
+            .get("maximum")
+            .and_then(Value::as_f64)
+            .is_some_and(|max| n > max);
+        if below || above {
+            return Err(mismatch());
+        }
+    }
+
+    if let Value::Object(fields) = value {
+        let properties = keywords.get("properties").and_then(Value::as_object);
+        for name in keywords
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !fields.contains_key(name) {
+                let property = properties.and_then(|p| p.get(name));
+                return Err(Mismatch {
+                    path: format!("{}.{}", path, name),
+                    expected: match property {
+                        Some(property) => format!("required {}", describe(property, root)),
+                        None => "required field".to_string(),
+                    },
+                });
+            }
+        }
+        for (name, property) in properties.into_iter().flatten() {
+            if let Some(field) = fields.get(name) {
+                check_at(field, property, root, &format!("{}.{}", path, name))?;
+            }
+        }
+    }
+    if let (Value::Array(elements), Some(items)) = (value, keywords.get("items")) {
+        for (i, element) in elements.iter().enumerate() {
+            check_at(element, items, root, &format!("{}[{}]", path, i))?;
+        }
+    }
+    Ok(())
+}
+
+// Follows a local `$ref` such as `#/$defs/Address` to its definition.
+fn resolve<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    match schema.get("$ref").and_then(Value::as_str) {
+        Some(reference) => match reference.strip_prefix('#') {
+            Some(pointer) => root.pointer(pointer).unwrap_or(schema),
+            None => schema,
+        },
+        None => schema,
+    }
+}
+
+// What a value matching `schema` looks like, e.g. `integer` or
+// `string or null`.
+fn describe(schema: &Value, root: &Value) -> String {
+    let schema = resolve(schema, root);
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+        return format!("one of {}", allowed.join(", "));
+    }
+    if let Some(constant) = schema.get("const") {
+        return constant.to_string();
+    }
+    if let Some(branches) = schema
+        .get("anyOf")
+        .or_else(|| schema.get("oneOf"))
+        .and_then(Value::as_array)
+    {
+        let branches: Vec<String> = branches.iter().map(|b| describe(b, root)).collect();
+        return branches.join(" or ");
+    }
+    let mut description = match schema.get("type") {
+        Some(types) => type_names(types).join(" or "),
+        None => "any value".to_string(),
+    };
+    if let Some(min) = schema.get("minimum") {
+        description.push_str(&format!(" >= {}", min));
+    }
+    if let Some(max) = schema.get("maximum") {
+        description.push_str(&format!(" <= {}", max));
+    }
+    description
+}
+
+fn type_names(types: &Value) -> Vec<&str> {
+    match types {
+        Value::String(t) => vec![t.as_str()],
+        Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
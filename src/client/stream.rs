@@ -0,0 +1,346 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt, stream};
+use tokio::{
+    sync::{mpsc, watch},
+    time::Instant,
+};
+
+use super::{
+    Completion, CompletionPart, FunctionCall, LangrustError, StreamEvent, StreamResult, Usage,
+    chunking::estimate_tokens_for_len,
+};
+
+// Events `ModelRequestBuilder::stream_channel` buffers unless told otherwise:
+// enough to ride out a consumer's short pauses without holding much text.
+pub(crate) const DEFAULT_STREAM_BUFFER: usize = 32;
+
+/// Stream of events returned by `ModelRequestBuilder::stream`.
+///
+/// Implements `Stream`, and also has an inherent `next()` so a
+/// `while let Some(event) = stream.next().await` loop works without importing
+/// `futures::StreamExt`. Failures mid-stream arrive as `StreamEvent::Error`.
+///
+/// Dropping it before the end is a cancellation: the response body goes with
+/// it, closing the connection rather than leaving it open.
+pub struct CompletionStream {
+    inner: StreamResult,
+}
+
+impl CompletionStream {
+    pub fn new(inner: StreamResult) -> CompletionStream {
+        CompletionStream { inner }
+    }
+
+    /// The next event, or `None` once the stream is exhausted.
+    pub async fn next(&mut self) -> Option<StreamEvent> {
+        self.inner.next().await
+    }
+
+    pub fn into_inner(self) -> StreamResult {
+        self.inner
+    }
+
+    /// Re-encodes the events as Server-Sent Events, one per item, for a proxy
+    /// to pipe into its own response body (`Content-Type:
+    /// text/event-stream`). Each is framed as `event: <kind>` and a single
+    /// `data:` line holding the event as JSON, e.g.
+    /// `event: delta\ndata: {"delta":"Hi"}\n\n`, so a client can deserialize
+    /// `data` back into the same `StreamEvent`.
+    pub fn into_sse(self) -> impl Stream<Item = String> + Send {
+        self.inner.map(|event| {
+            let data = serde_json::to_string(&event).expect("stream events serialize to JSON");
+            format!("event: {}\ndata: {}\n\n", event.kind(), data)
+        })
+    }
+
+    /// Forwards the events, from a spawned task, to a channel holding up to
+    /// `buffer` of them, and returns its receiver.
+    ///
+    /// A full channel pauses reading the response until the receiver catches
+    /// up, so a small buffer keeps memory flat but lets a slow consumer hold
+    /// the connection idle; a large one absorbs bursts at the cost of
+    /// buffering that many events. Dropping the receiver ends the task and
+    /// closes the connection. Fails with `LangrustError::InvalidRequest` if
+    /// `buffer` is `0`.
+    pub fn into_channel(self, buffer: usize) -> Result<mpsc::Receiver<StreamEvent>, LangrustError> {
+        if buffer == 0 {
+            return Err(LangrustError::InvalidRequest(
+                "stream buffer must hold at least one event".to_string(),
+            ));
+        }
+        let (tx, rx) = mpsc::channel(buffer);
+        let mut inner = self.inner;
+        tokio::spawn(async move {
+            while let Some(event) = inner.next().await {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    // Ends the stream with `StreamEvent::Truncated` once the deltas pass
+    // `max_tokens` tokens by `estimate_text_tokens`. Dropping `inner` closes the
+    // connection.
+    pub(crate) fn limit_tokens(self, max_tokens: u32) -> CompletionStream {
+        let state = (Some(self.inner), 0usize, None::<u32>);
+        CompletionStream::new(Box::pin(stream::unfold(
+            state,
+            move |(inner, seen, truncated)| async move {
+                if let Some(tokens) = truncated {
+                    return Some((StreamEvent::Truncated(tokens), (None, seen, None)));
+                }
+                let mut inner = inner?;
+                let event = inner.next().await?;
+                let mut seen = seen;
+                if let StreamEvent::Delta(delta) = &event {
+                    seen += delta.len();
+                    let tokens = estimate_tokens_for_len(seen) as u32;
+                    if tokens > max_tokens {
+                        return Some((event, (None, seen, Some(tokens))));
+                    }
+                }
+                Some((event, (Some(inner), seen, None)))
+            },
+        )))
+    }
+
+    // Ends the stream with `StreamEvent::Stopped` once the text contains
+    // `sentinel`, delivering only the text before it. The end of the text
+    // that could be the start of `sentinel` is held back in `pending` until
+    // the next event shows whether it is.
+    pub(crate) fn stop_on(self, sentinel: String) -> CompletionStream {
+        let state = (Some(self.inner), String::new(), VecDeque::new());
+        CompletionStream::new(Box::pin(stream::unfold(
+            state,
+            move |(mut inner, mut pending, mut ready)| {
+                let sentinel = sentinel.clone();
+                async move {
+                    loop {
+                        if let Some(event) = ready.pop_front() {
+                            return Some((event, (inner, pending, ready)));
+                        }
+                        let event = inner.as_mut()?.next().await;
+                        match event {
+                            Some(StreamEvent::Delta(delta)) => {
+                                pending.push_str(&delta);
+                                if let Some(end) = pending.find(&sentinel) {
+                                    pending.truncate(end);
+                                    if !pending.is_empty() {
+                                        ready.push_back(StreamEvent::Delta(pending.clone()));
+                                    }
+                                    ready.push_back(StreamEvent::Stopped(sentinel.clone()));
+                                    inner = None;
+                                    continue;
+                                }
+                                let held = partial_match_len(&pending, &sentinel);
+                                let rest = pending.split_off(pending.len() - held);
+                                if !pending.is_empty() {
+                                    ready.push_back(StreamEvent::Delta(pending));
+                                }
+                                pending = rest;
+                            }
+                            Some(event) => {
+                                if !pending.is_empty() {
+                                    ready.push_back(StreamEvent::Delta(std::mem::take(
+                                        &mut pending,
+                                    )));
+                                }
+                                ready.push_back(event);
+                            }
+                            None => {
+                                if !pending.is_empty() {
+                                    ready.push_back(StreamEvent::Delta(std::mem::take(
+                                        &mut pending,
+                                    )));
+                                }
+                                inner = None;
+                            }
+                        }
+                    }
+                }
+            },
+        )))
+    }
+
+    // Ends the stream with `StreamEvent::Cancelled` once `handle` is aborted,
+    // dropping `inner` and with it the connection.
+    pub(crate) fn abortable(self) -> (CompletionStream, StreamHandle) {
+        let (tx, rx) = watch::channel(false);
+        let stream = stream::unfold((Some(self.inner), rx), |(inner, mut aborted)| async move {
+            let mut inner = inner?;
+            let event = tokio::select! {
+                biased;
+                Ok(_) = aborted.wait_for(|aborted| *aborted) => None,
+                event = inner.next() => Some(event?),
+            };
+            match event {
+                Some(event) => Some((event, (Some(inner), aborted))),
+                None => Some((StreamEvent::Cancelled, (None, aborted))),
+            }
+        });
+        let handle = StreamHandle {
+            aborted: Arc::new(tx),
+        };
+        (CompletionStream::new(Box::pin(stream)), handle)
+    }
+
+    // Ends the stream with a `StreamEvent::Error` for
+    // `LangrustError::StreamTimeout` if it is still running at `deadline`,
+    // which is `limit` after it was requested.
+    pub(crate) fn limit_duration(self, deadline: Instant, limit: Duration) -> CompletionStream {
+        CompletionStream::new(Box::pin(stream::unfold(
+            Some(self.inner),
+            move |inner| async move {
+                let mut inner = inner?;
+                match tokio::time::timeout_at(deadline, inner.next()).await {
+                    Ok(event) => Some((event?, Some(inner))),
+                    Err(_) => {
+                        let error = LangrustError::StreamTimeout { limit };
+                        Some((StreamEvent::Error(error.to_string()), None))
+                    }
+                }
+            },
+        )))
+    }
+}
+
+// Length of the longest end of `text` that is a proper start of `sentinel`.
+fn partial_match_len(text: &str, sentinel: &str) -> usize {
+    (1..sentinel.len().min(text.len() + 1))
+        .rev()
+        .find(|&n| sentinel.is_char_boundary(n) && text.ends_with(&sentinel[..n]))
+        .unwrap_or(0)
+}
+
+// Opens a stream with `open` and waits for its first event, failing with
+// `LangrustError::FirstTokenTimeout` if both together take longer than
+// `timeout`. The event is put back at the front of the stream.
+pub(crate) async fn open_with_first_token_timeout(
+    open: impl Future<Output = Result<StreamResult, Box<dyn Error + Send + Sync>>>,
+    timeout: Duration,
+) -> Result<StreamResult, Box<dyn Error + Send + Sync>> {
+    let first = tokio::time::timeout(timeout, async {
+        let mut inner = open.await?;
+        let first = inner.next().await;
+        Ok::<_, Box<dyn Error + Send + Sync>>((first, inner))
+    })
+    .await;
+    let (first, inner) = first.map_err(|_| LangrustError::FirstTokenTimeout { timeout })??;
+    Ok(Box::pin(stream::iter(first).chain(inner)))
+}
+
+/// Stops a stream from elsewhere, e.g. another task, returned alongside it
+/// by `ModelRequestBuilder::stream_with_handle`. Clones abort the same
+/// stream.
+#[derive(Debug, Clone)]
+pub struct StreamHandle {
+    aborted: Arc<watch::Sender<bool>>,
+}
+
+impl StreamHandle {
+    /// Ends the stream: it yields `StreamEvent::Cancelled` in place of its
+    /// next event and nothing after, and closes the connection. Does nothing
+    /// if the stream has already ended or been aborted.
+    pub fn abort(&self) {
+        self.aborted.send_replace(true);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        *self.aborted.borrow()
+    }
+}
+
+impl From<StreamResult> for CompletionStream {
+    fn from(inner: StreamResult) -> CompletionStream {
+        CompletionStream::new(inner)
+    }
+}
+
+impl Stream for CompletionStream {
+    type Item = StreamEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<StreamEvent>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+// Folds streamed events into the `Completion` they add up to, for the
+// `stream_to_*` helpers.
+#[derive(Default)]
+pub(crate) struct CompletionAccumulator {
+    text: String,
+    usage: Option<Usage>,
+    function: Option<FunctionCall>,
+    parts: Vec<CompletionPart>,
+    truncated: bool,
+}
+
+impl CompletionAccumulator {
+    // Records `event`, returning the text to pass on if it is a delta. A
+    // stream error ends the stream and is returned as is.
+    pub(crate) fn add(
+        &mut self,
+        event: StreamEvent,
+    ) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        match event {
+            StreamEvent::Delta(delta) => {
+                self.text.push_str(&delta);
+                CompletionPart::push_text(&mut self.parts, &delta);
+                Ok(Some(delta))
+            }
+            StreamEvent::Usage(usage) => {
+                self.usage = Some(usage);
+                Ok(None)
+            }
+            StreamEvent::FunctionCall(call) => {
+                self.parts.push(CompletionPart::FunctionCall(call.clone()));
+                self.function.get_or_insert(call);
+                Ok(None)
+            }
+            StreamEvent::FunctionCallStarted { .. } => Ok(None),
+            StreamEvent::Error(e) => Err(e.into()),
+            StreamEvent::Truncated(_) => {
+                self.truncated = true;
+                Ok(None)
+            }
+            StreamEvent::Stopped(_) => Ok(None),
+            StreamEvent::Cancelled => Err("stream was cancelled".into()),
+            StreamEvent::Filtered { finish_reason, .. } => {
+                Err(format!("stream stopped by the provider: {}", finish_reason).into())
+            }
+        }
+    }
+
+    pub(crate) fn finish(self, model: String, enum_variants: Option<Vec<String>>) -> Completion {
+        Completion {
+            completion: self.text,
+            usage: self.usage,
+            function: self.function,
+            function_calls: CompletionPart::calls(&self.parts),
+            model,
+            model_version: None,
+            response_id: None,
+            logprobs: None,
+            avg_logprobs: None,
+            safety_ratings: None,
+            enum_variants,
+            ordered_parts: Some(self.parts),
+            media: vec![],
+            truncated: self.truncated,
+        }
+    }
+}
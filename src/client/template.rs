@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use super::LangrustError;
+
+/// A prompt with `{name}` placeholders, filled in by `render`, e.g. a system
+/// prompt kept in a file and passed to `with_system` once rendered.
+///
+/// A placeholder is a name of ASCII letters, digits and underscores between
+/// braces. Any other brace is kept as written, so JSON examples in a prompt
+/// need no escaping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    template: String,
+}
+
+impl PromptTemplate {
+    pub fn new(template: impl Into<String>) -> PromptTemplate {
+        PromptTemplate {
+            template: template.into(),
+        }
+    }
+
+    /// The template with every placeholder replaced by its value in `vars`.
+    /// Fails with `LangrustError::InvalidRequest` naming the first
+    /// placeholder `vars` has no value for.
+    pub fn render(&self, vars: &HashMap<String, String>) -> Result<String, LangrustError> {
+        let mut rendered = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(open) = rest.find('{') {
+            rendered.push_str(&rest[..open]);
+            let after = &rest[open + 1..];
+            match placeholder(after) {
+                Some(name) => {
+                    let value = vars.get(name).ok_or_else(|| {
+                        LangrustError::InvalidRequest(format!(
+                            "missing value for template variable `{}`",
+                            name
+                        ))
+                    })?;
+                    rendered.push_str(value);
+                    rest = &after[name.len() + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = after;
+                }
+            }
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+}
+
+// The name of the placeholder `text` starts with, just after its `{`.
+fn placeholder(text: &str) -> Option<&str> {
+    let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))?;
+    match end > 0 && text[end..].starts_with('}') {
+        true => Some(&text[..end]),
+        false => None,
+    }
+}
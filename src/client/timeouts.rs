@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+/// Connection-level timeouts for the `reqwest::Client` a model sends its
+/// requests through, set with the model's `with_timeouts`. They are set once,
+/// when the client is built, and are distinct from the overall per-request
+/// `Settings::timeout`.
+///
+/// A long-running stream typically wants a short `connect_timeout` and a
+/// generous `read_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HttpTimeouts {
+    /// Time allowed to establish the connection, TLS handshake included.
+    pub connect_timeout: Option<Duration>,
+    /// Time allowed for each read of the response. On a stream this bounds
+    /// the gap between two chunks, not the length of the whole stream.
+    pub read_timeout: Option<Duration>,
+}
+
+impl HttpTimeouts {
+    /// Applies the timeouts that are set to `builder`.
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        let builder = match self.connect_timeout {
+            Some(t) => builder.connect_timeout(t),
+            None => builder,
+        };
+        match self.read_timeout {
+            Some(t) => builder.read_timeout(t),
+            None => builder,
+        }
+    }
+
+    /// A client with these timeouts and reqwest's defaults otherwise, ready to
+    /// pass as a model's `client`.
+    pub fn client(&self) -> Result<reqwest::Client, reqwest::Error> {
+        self.apply(reqwest::Client::builder()).build()
+    }
+}
@@ -0,0 +1,56 @@
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use super::{FunctionCall, Message};
+
+/// What a tool handler passed to `ModelRequestBuilder::run_tools` returns.
+///
+/// An error is not fatal: it is sent back to the model as the function's
+/// response, `{"error": "..."}`, so the model can correct its arguments or
+/// answer without the tool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolResult {
+    Success(Value),
+    Error(String),
+}
+
+impl ToolResult {
+    /// A successful result holding `value` serialized to JSON. A value that
+    /// fails to serialize becomes an error result.
+    pub fn success<T: Serialize>(value: T) -> ToolResult {
+        match serde_json::to_value(value) {
+            Ok(value) => ToolResult::Success(value),
+            Err(e) => ToolResult::Error(format!("failed to serialize tool result: {}", e)),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> ToolResult {
+        ToolResult::Error(message.into())
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, ToolResult::Error(_))
+    }
+
+    /// The `functionResponse` payload for this result.
+    pub fn to_response(&self) -> Value {
+        match self {
+            ToolResult::Success(value) => value.clone(),
+            ToolResult::Error(message) => json!({ "error": message }),
+        }
+    }
+
+    /// The message reporting this result of `call`.
+    pub fn to_message(&self, call: &FunctionCall) -> Message {
+        Message::function_result_for(call, self.to_response())
+    }
+}
+
+impl<T: Serialize, E: ToString> From<Result<T, E>> for ToolResult {
+    fn from(result: Result<T, E>) -> ToolResult {
+        match result {
+            Ok(value) => ToolResult::success(value),
+            Err(e) => ToolResult::Error(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use reqwest::{Request, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+
+use super::{HttpTimeouts, HttpVersionPref, LangrustError, ProxyConfig};
+
+/// Sends the requests a model builds, in place of the model's own
+/// `reqwest::Client`.
+///
+/// Set as a model's `transport` to route its requests through another HTTP
+/// stack. With the `middleware` feature a
+/// `reqwest_middleware::ClientWithMiddleware` is one, so its tracing, retry
+/// and caching layers see every request langrust makes.
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    async fn execute(&self, request: Request) -> Result<Response, Box<dyn Error + Send + Sync>>;
+}
+
+/// A plain client as a transport, e.g. one with its own proxy, passed for a
+/// single request with `ModelRequestBuilder::with_client`.
+#[async_trait]
+impl HttpTransport for reqwest::Client {
+    async fn execute(&self, request: Request) -> Result<Response, Box<dyn Error + Send + Sync>> {
+        Ok(reqwest::Client::execute(self, request).await?)
+    }
+}
+
+#[cfg(feature = "middleware")]
+#[async_trait]
+impl HttpTransport for reqwest_middleware::ClientWithMiddleware {
+    async fn execute(&self, request: Request) -> Result<Response, Box<dyn Error + Send + Sync>> {
+        match reqwest_middleware::ClientWithMiddleware::execute(self, request).await {
+            Ok(response) => Ok(response),
+            // Unwrapped so that `with_retry` still recognizes transport
+            // failures.
+            Err(reqwest_middleware::Error::Reqwest(e)) => Err(e.into()),
+            Err(reqwest_middleware::Error::Middleware(e)) => Err(e.into()),
+        }
+    }
+}
+
+// What a model's `reqwest::Client` is built from, kept on the model so that
+// setting one option rebuilds the client without losing the others.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientOptions {
+    pub(crate) timeouts: HttpTimeouts,
+    pub(crate) http_version: HttpVersionPref,
+    pub(crate) proxy: ProxyConfig,
+}
+
+impl ClientOptions {
+    pub(crate) fn build(&self) -> Result<reqwest::Client, reqwest::Error> {
+        let builder = self.timeouts.apply(reqwest::Client::builder());
+        let builder = self.http_version.apply(builder);
+        self.proxy.apply(builder)?.build()
+    }
+}
+
+/// Sends `builder` through `transport` if there is one, otherwise through the
+/// client it was built from.
+pub(crate) async fn send(
+    builder: RequestBuilder,
+    transport: Option<&dyn HttpTransport>,
+) -> Result<Response, Box<dyn Error + Send + Sync>> {
+    match transport {
+        Some(transport) => transport.execute(builder.build()?).await,
+        None => Ok(builder.send().await?),
+    }
+}
+
+/// Where a request goes: its own client if it was given one with
+/// `with_client`, otherwise the model's transport.
+pub(crate) fn route<'a>(
+    client: Option<&'a reqwest::Client>,
+    transport: Option<&'a dyn HttpTransport>,
+) -> Option<&'a dyn HttpTransport> {
+    match client {
+        Some(client) => Some(client),
+        None => transport,
+    }
+}
+
+/// Reads the body of `response`, failing with
+/// `LangrustError::ResponseTooLarge` as soon as it passes `limit` bytes
+/// instead of buffering it all.
+pub(crate) async fn read_body(
+    response: Response,
+    limit: Option<usize>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    match read_capped(response, limit).await? {
+        (body, false) => Ok(body),
+        (_, true) => Err(LangrustError::ResponseTooLarge {
+            limit: limit.unwrap_or_default(),
+        }
+        .into()),
+    }
+}
+
+/// `read_body` parsed as JSON.
+pub(crate) async fn read_json<T: DeserializeOwned>(
+    response: Response,
+    limit: Option<usize>,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    Ok(serde_json::from_slice(&read_body(response, limit).await?)?)
+}
+
+/// Reads at most `limit` bytes of the body of `response`, and whether there
+/// was more. Error bodies are read this way, so that a cut-off one still
+/// reports its status.
+pub(crate) async fn read_capped(
+    mut response: Response,
+    limit: Option<usize>,
+) -> Result<(Vec<u8>, bool), Box<dyn Error + Send + Sync>> {
+    let Some(limit) = limit else {
+        return Ok((response.bytes().await?.to_vec(), false));
+    };
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        let room = limit - body.len();
+        if chunk.len() > room {
+            body.extend_from_slice(&chunk[..room]);
+            return Ok((body, true));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok((body, false))
+}
@@ -1,37 +1,124 @@
 use eventsource_stream::Eventsource;
-use futures::{StreamExt, TryFutureExt, stream};
+use futures::{StreamExt, stream};
 use std::error::Error;
 
 use reqwest::RequestBuilder;
+use serde_json::{Map, Value, json};
 
 use crate::{
     client::{
-        Completion, FunctionCall, MessageType, Model, ModelRequest, Role, StreamEvent,
-        StreamResult, Usage,
+        BeforeSendHook, Completion, ContentPart, FunctionCall, HttpTransport, LangrustError,
+        MessageType, Model, ModelRequest, Role, StreamEvent, StreamResult, Usage, api_error,
+        encode_base64, log_request, read_json, route, send,
     },
     gemini::types::{
-        Content, FunctionCallPart, FunctionResponsePart, GeminiRequest, GeminiResponse, GeminiTool,
-        GeminiTools, GenerationConfig, Part, SystemInstructionContent, ThinkingConfig,
+        Content, FileDataPart, FunctionCallPart, FunctionResponsePart, GeminiMethod, GeminiRequest,
+        GeminiResponse, GeminiSafetySetting, GeminiTool, GeminiTools, GenerationConfig,
+        InlineDataPart, Part, SystemInstructionContent, ThinkingConfig, ToolConfig,
+        convert_property_to_gemini, modality_name,
     },
 };
 
+/// Gemini rejects requests above about 20MB, and inline data is nearly all of
+/// it in practice.
+pub(crate) const INLINE_DATA_LIMIT: usize = 20 * 1024 * 1024;
+
+fn check_inline_data_size(request: &ModelRequest) -> Result<(), LangrustError> {
+    let bytes: usize = request
+        .messages
+        .iter()
+        .flatten()
+        .flat_map(|m| m.parts.iter())
+        .map(|p| p.inline_data_size())
+        .sum();
+    if bytes > INLINE_DATA_LIMIT {
+        return Err(LangrustError::RequestTooLarge {
+            bytes,
+            limit: INLINE_DATA_LIMIT,
+        });
+    }
+    Ok(())
+}
+
+/// Gemini refuses function calling when the response is constrained by a
+/// schema or an enum.
+fn check_tools_without_response_schema(request: &ModelRequest) -> Result<(), LangrustError> {
+    let has_tools = request.tools.as_ref().is_some_and(|ts| !ts.is_empty());
+    if has_tools && (request.response_schema.is_some() || request.enum_response.is_some()) {
+        return Err(LangrustError::InvalidRequest(
+            "tools cannot be combined with a response schema on Gemini; make a separate request \
+             for the structured output once the tool calls are done"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Folds runs of same-role contents into a single turn, keeping the parts in
+/// order. Gemini rejects `contents` whose roles don't alternate.
+fn merge_consecutive_roles_into_turns(contents: Vec<Content>) -> Vec<Content> {
+    let mut merged: Vec<Content> = Vec::with_capacity(contents.len());
+    for content in contents {
+        match merged.last_mut() {
+            Some(last) if last.role == content.role => last.parts.extend(content.parts),
+            _ => merged.push(content),
+        }
+    }
+    merged
+}
+
 pub trait GeminiClient: Model {
     fn create_request_body(&self, request: ModelRequest) -> GeminiRequest {
+        let request = request.with_defaults(self.defaults());
         let thinking_config = request
             .settings
             .as_ref()
             .and_then(|s| s.thinking_budget)
-            .map(|thinking_budget| ThinkingConfig { thinking_budget });
+            .map(|thinking_budget| ThinkingConfig {
+                thinking_budget,
+                include_thoughts: (thinking_budget == 0).then_some(false),
+            });
 
-        let generation_config = GenerationConfig {
+        let mut generation_config = GenerationConfig {
             max_output_tokens: request.settings.clone().and_then(|s| s.max_tokens),
-            temperature: request
-                .settings
-                .clone()
-                .map(|s| s.temperature.unwrap_or_default())
-                .unwrap_or_default(),
+            // Sent as 0 when unset, rather than left to Gemini's default.
+            temperature: Some(
+                request
+                    .settings
+                    .as_ref()
+                    .and_then(|s| s.temperature)
+                    .unwrap_or(0),
+            ),
+            top_p: request.settings.as_ref().and_then(|s| s.top_p),
             thinking_config,
+            response_mime_type: match (&request.enum_response, &request.response_schema) {
+                (Some(_), _) => Some("text/x.enum".to_string()),
+                (None, Some(_)) => Some("application/json".to_string()),
+                (None, None) => None,
+            },
+            response_schema: match &request.enum_response {
+                Some(variants) => Some(json!({ "type": "STRING", "enum": variants })),
+                None => request
+                    .response_schema
+                    .as_ref()
+                    .map(convert_property_to_gemini),
+            },
+            response_logprobs: request.logprobs.map(|_| true),
+            logprobs: request.logprobs,
+            response_modalities: request
+                .response_modalities
+                .as_ref()
+                .map(|ms| ms.iter().map(modality_name).collect()),
+            extra: Map::new(),
         };
+        if let Some(Value::Object(extra)) = &request.extra_generation_config {
+            let typed = serde_json::to_value(&generation_config).unwrap_or_default();
+            generation_config.extra = extra
+                .iter()
+                .filter(|(key, _)| typed.get(key.as_str()).is_none())
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+        }
 
         let contents: Vec<Content> = request
             .messages
@@ -39,24 +126,52 @@ pub trait GeminiClient: Model {
             .unwrap_or(vec![])
             .iter()
             .map(|message| match &message.message_type {
-                MessageType::Text => Content {
-                    parts: vec![Part::Text {
-                        text: message.content.clone(),
-                    }],
-                    role: message.role.clone().unwrap_or_else(|| Role::User),
-                },
-                MessageType::FunctionCall(fc) => Content {
+                MessageType::FunctionCall(fc) if message.parts.is_empty() => Content {
                     parts: vec![Part::FunctionCall {
                         function_call: FunctionCallPart {
+                            id: fc.id.clone(),
                             name: fc.name.clone(),
                             args: fc.args.clone(),
                         },
                     }],
                     role: Role::Model,
                 },
-                MessageType::FunctionResponse { name, response } => Content {
+                MessageType::Text | MessageType::FunctionCall(_) => Content {
+                    parts: message
+                        .content_parts()
+                        .into_iter()
+                        .map(|part| match part {
+                            ContentPart::Text(text) => Part::Text { text },
+                            ContentPart::InlineData { mime_type, data } => Part::InlineData {
+                                inline_data: InlineDataPart {
+                                    mime_type,
+                                    data: encode_base64(&data),
+                                },
+                            },
+                            ContentPart::FileData {
+                                mime_type,
+                                file_uri,
+                            } => Part::FileData {
+                                file_data: FileDataPart {
+                                    mime_type,
+                                    file_uri,
+                                },
+                            },
+                            ContentPart::FunctionCall(fc) => Part::FunctionCall {
+                                function_call: FunctionCallPart {
+                                    id: fc.id,
+                                    name: fc.name,
+                                    args: fc.args,
+                                },
+                            },
+                        })
+                        .collect(),
+                    role: message.role.clone().unwrap_or_else(|| Role::User),
+                },
+                MessageType::FunctionResponse { name, response, id } => Content {
                     parts: vec![Part::FunctionResponse {
                         function_response: FunctionResponsePart {
+                            id: id.clone(),
                             name: name.clone(),
                             response: response.clone().unwrap_or(serde_json::Value::Null),
                         },
@@ -66,6 +181,16 @@ pub trait GeminiClient: Model {
             })
             .collect();
 
+        let merge_consecutive_roles = request
+            .settings
+            .as_ref()
+            .and_then(|s| s.merge_consecutive_roles)
+            .unwrap_or(true);
+        let contents = match merge_consecutive_roles {
+            true => merge_consecutive_roles_into_turns(contents),
+            false => contents,
+        };
+
         let system_instruction = request.system.clone().map(|m| SystemInstructionContent {
             parts: vec![Part::Text { text: m }],
         });
@@ -74,6 +199,10 @@ pub trait GeminiClient: Model {
             system_instruction,
             contents,
             generation_config,
+            safety_settings: request
+                .safety_settings
+                .as_ref()
+                .map(|s| s.0.iter().map(GeminiSafetySetting::from_setting).collect()),
             tools: request.tools.clone().map(|ts| {
                 vec![GeminiTools {
                     function_declarations: ts
@@ -83,6 +212,10 @@ pub trait GeminiClient: Model {
                         .collect(),
                 }]
             }),
+            tool_config: request
+                .function_calling_mode
+                .as_ref()
+                .map(ToolConfig::from_mode),
         };
         req
     }
@@ -91,48 +224,103 @@ pub trait GeminiClient: Model {
         &self,
         request: ModelRequest,
     ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
-        let endpoint = self.get_endpoint(&self.model_name(), String::from("generateContent"));
+        let enum_variants = request.enum_response.clone();
+        let response_body = self.send_generate_content(request).await?;
+        self.completion_at(&response_body, 0, &enum_variants)
+            .ok_or_else(|| "Missing completion from response".into())
+    }
+
+    /// Asks for `n` candidates and returns one `Completion` per candidate
+    /// received, in order. Each carries the usage of the whole response.
+    async fn generate_contents(
+        &self,
+        mut request: ModelRequest,
+        n: u32,
+    ) -> Result<Vec<Completion>, Box<dyn Error + Send + Sync>> {
+        let mut extra = request
+            .extra_generation_config
+            .take()
+            .unwrap_or_else(|| json!({}));
+        if let Some(extra) = extra.as_object_mut() {
+            extra.insert("candidateCount".to_string(), json!(n));
+        }
+        request.extra_generation_config = Some(extra);
+
+        let enum_variants = request.enum_response.clone();
+        let response_body = self.send_generate_content(request).await?;
+        Ok((0..response_body.candidates.len())
+            .filter_map(|i| self.completion_at(&response_body, i, &enum_variants))
+            .collect())
+    }
+
+    async fn send_generate_content(
+        &self,
+        request: ModelRequest,
+    ) -> Result<GeminiResponse, Box<dyn Error + Send + Sync>> {
+        request.validate()?;
+        request.check_tools_supported(self.supports_tools())?;
+        check_inline_data_size(&request)?;
+        check_tools_without_response_schema(&request)?;
+        log_request("gemini", &self.model_name(), &request);
+        let endpoint = self.get_endpoint(&self.model_name(), GeminiMethod::GenerateContent);
+        let before_send = request.before_send.clone();
+        let client = request.client.clone();
+        let max_response_bytes = request.max_response_bytes;
         let request_body = self.create_request_body(request);
-        let response = self
-            .build_request(&endpoint, &request_body)
-            .await?
-            .send()
+        let builder = self
+            .build_request_with_hook(&endpoint, &request_body, before_send)
             .await?;
+        let response = send(builder, route(client.as_ref(), self.transport())).await?;
 
         let status = response.status();
         if !status.is_success() {
-            let error_text = response.text().map_err(|e| e.to_string()).await?;
-            return Err(format!(
-                "Gemini request failed with status {}: {}",
-                status, error_text
-            )
-            .into());
+            return Err(api_error(response, max_response_bytes).await?.into());
         }
 
-        let response_body: GeminiResponse = response.json().await?;
-
-        let content: String =
-            response_body
-                .get_text()
-                .ok_or_else(|| -> Box<dyn Error + Send + Sync> {
-                    "Missing completion from response".into()
-                })?;
+        let response_body: GeminiResponse = read_json(response, max_response_bytes).await?;
+        if let Some(blocked) = response_body.get_prompt_block() {
+            return Err(blocked.into());
+        }
+        Ok(response_body)
+    }
 
-        let prompt_tokens = response_body.get_prompt_tokens().unwrap_or(0);
-        let completion_tokens = response_body.get_completion_tokens().unwrap_or(0);
-        let total_tokens = response_body.get_total_tokens().unwrap_or(0);
+    /// The candidate at `index` as a `Completion` of a request allowing
+    /// `enum_variants`, or `None` if there is no such candidate.
+    fn completion_at(
+        &self,
+        response_body: &GeminiResponse,
+        index: usize,
+        enum_variants: &Option<Vec<String>>,
+    ) -> Option<Completion> {
+        let content = response_body.get_text_at(index)?;
 
-        return Ok(Completion {
+        return Some(Completion {
             completion: content,
-            usage: Usage {
-                prompt_tokens,
-                completion_tokens,
-                total_tokens,
-            },
-            function: response_body.get_function().map(|gf| FunctionCall {
+            usage: response_body.get_usage(),
+            function: response_body.get_function_at(index).map(|gf| FunctionCall {
                 name: gf.name,
                 args: gf.args,
+                id: gf.id,
             }),
+            function_calls: response_body
+                .get_functions_at(index)
+                .into_iter()
+                .map(|gf| FunctionCall {
+                    name: gf.name,
+                    args: gf.args,
+                    id: gf.id,
+                })
+                .collect(),
+            model: self.model_name(),
+            logprobs: response_body.get_logprobs_at(index),
+            avg_logprobs: response_body.get_avg_logprobs_at(index),
+            safety_ratings: response_body.get_safety_ratings_at(index),
+            enum_variants: enum_variants.clone(),
+            ordered_parts: response_body.get_parts_at(index),
+            media: response_body.get_media_at(index),
+            truncated: false,
+            model_version: response_body.model_version.clone(),
+            response_id: response_body.response_id.clone(),
         });
     }
 
@@ -140,27 +328,30 @@ pub trait GeminiClient: Model {
         &self,
         request: ModelRequest,
     ) -> Result<StreamResult, Box<dyn Error + Send + Sync>> {
-        let endpoint = self.get_endpoint(
-            &self.model_name(),
-            String::from("streamGenerateContent?alt=sse"),
-        );
+        request.validate()?;
+        request.check_tools_supported(self.supports_tools())?;
+        check_inline_data_size(&request)?;
+        check_tools_without_response_schema(&request)?;
+        log_request("gemini", &self.model_name(), &request);
+        let endpoint = self.get_endpoint(&self.model_name(), GeminiMethod::StreamGenerateContent);
+        let before_send = request.before_send.clone();
+        let client = request.client.clone();
+        let max_response_bytes = request.max_response_bytes;
         let request_body = self.create_request_body(request);
-        let response = self
-            .build_request(&endpoint, &request_body)
-            .await?
-            .send()
+        let builder = self
+            .build_request_with_hook(&endpoint, &request_body, before_send)
             .await?;
+        let response = send(builder, route(client.as_ref(), self.transport())).await?;
 
         let status = response.status();
         if !status.is_success() {
-            let error_text = response.text().map_err(|e| e.to_string()).await?;
-            return Err(format!(
-                "Gemini streaming request failed with status {}: {}",
-                status, error_text
-            )
-            .into());
+            return Err(api_error(response, max_response_bytes).await?.into());
         }
 
+        // `eventsource` drops SSE comments, such as `: keepalive`
+        // heartbeats, so only `data:` payloads reach the parser. It also
+        // decodes UTF-8 across reads, holding back a character split between
+        // two chunks until its remaining bytes arrive.
         let event_stream = response
             .bytes_stream()
             .eventsource()
@@ -175,20 +366,31 @@ pub trait GeminiClient: Model {
                             Ok(gemini_response) => {
                                 let mut events = Vec::new();
 
+                                if let Some(blocked) = gemini_response.get_prompt_block() {
+                                    events.push(StreamEvent::Error(blocked.to_string()));
+                                }
+
                                 if let Some(text) = gemini_response.get_text() {
                                     if !text.is_empty() {
                                         events.push(StreamEvent::Delta(text));
                                     }
                                 }
 
-                                if let Some(gf) = gemini_response.get_function() {
+                                for gf in gemini_response.get_functions_at(0) {
                                     events.push(StreamEvent::FunctionCall(FunctionCall {
                                         name: gf.name,
                                         args: gf.args,
+                                        id: gf.id,
                                     }));
                                 }
 
-                                if let Some(usage) = &gemini_response.usage_metadata {
+                                // Every chunk carries running usage counts; only
+                                // report them once, with the chunk that finishes
+                                // the candidate.
+                                let usage = gemini_response
+                                    .get_finish_reason()
+                                    .and(gemini_response.usage_metadata.as_ref());
+                                if let Some(usage) = usage {
                                     if let (Some(pt), Some(ct), Some(tt)) = (
                                         usage.prompt_token_count,
                                         usage.candidates_token_count,
@@ -202,6 +404,15 @@ pub trait GeminiClient: Model {
                                     }
                                 }
 
+                                // The partial text is filled in below, once
+                                // every delta has been seen.
+                                if let Some(reason) = gemini_response.get_filtered_finish_reason() {
+                                    events.push(StreamEvent::Filtered {
+                                        finish_reason: reason.to_string(),
+                                        partial_text: String::new(),
+                                    });
+                                }
+
                                 if events.is_empty() {
                                     None
                                 } else {
@@ -214,12 +425,49 @@ pub trait GeminiClient: Model {
                     Err(e) => Some(stream::iter(vec![StreamEvent::Error(e.to_string())])),
                 }
             })
-            .flat_map(|s| s);
+            .flat_map(|s| s)
+            .scan(String::new(), |text, event| {
+                let event = match event {
+                    StreamEvent::Delta(delta) => {
+                        text.push_str(&delta);
+                        StreamEvent::Delta(delta)
+                    }
+                    StreamEvent::Filtered { finish_reason, .. } => StreamEvent::Filtered {
+                        finish_reason,
+                        partial_text: text.clone(),
+                    },
+                    event => event,
+                };
+                futures::future::ready(Some(event))
+            });
 
         Ok(Box::pin(event_stream))
     }
 
-    fn get_endpoint(&self, model: &String, method: String) -> String;
+    /// `build_request`, with the body replaced by the output of `before_send`
+    /// when one is set.
+    async fn build_request_with_hook(
+        &self,
+        endpoint: &String,
+        request_body: &GeminiRequest,
+        before_send: Option<BeforeSendHook>,
+    ) -> Result<RequestBuilder, Box<dyn Error + Send + Sync>> {
+        let builder = self.build_request(endpoint, request_body).await?;
+        let Some(hook) = before_send else {
+            return Ok(builder);
+        };
+        let mut body = serde_json::to_value(request_body)?;
+        hook(&mut body);
+        Ok(builder.json(&body))
+    }
+
+    /// Where built requests are sent instead of the model's client, if
+    /// anywhere.
+    fn transport(&self) -> Option<&dyn HttpTransport> {
+        None
+    }
+
+    fn get_endpoint(&self, model: &String, method: GeminiMethod) -> String;
     async fn build_request(
         &self,
         endpoint: &String,
@@ -1,19 +1,214 @@
-use std::error::Error;
+use std::{collections::HashSet, error::Error, sync::Arc};
 
 use crate::{
-    client::{Completion, Model, ModelRequest, StreamResult},
+    client::{
+        ClientOptions, Completion, CompletionCache, CompletionsFuture, HttpTimeouts, HttpTransport,
+        HttpVersionPref, KeyProvider, LangrustError, Model, ModelDefaults, ModelObserver,
+        ModelRequest, ProxyConfig, RateLimiter, StreamResult, api_error, send,
+    },
     gemini::{
         base::GeminiClient,
-        types::{GeminiModel, GeminiRequest},
+        types::{GeminiMethod, GeminiModel, GeminiRequest, ListModelsResponse, ModelInfo},
     },
 };
 use async_trait::async_trait;
 use reqwest::RequestBuilder;
 
+const API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+
+/// A Gemini model on the Gemini Developer API, authenticated with an API key.
+///
+/// Build one with `GeminiApiModel::new` and the `with_*` methods; fields may
+/// be added in future versions.
+#[non_exhaustive]
 pub struct GeminiApiModel {
     pub api_key: String,
     pub client: reqwest::Client,
     pub model: GeminiModel, // TODO Replace this with a type
+    /// When set, asked for the key before every request and used instead of
+    /// `api_key`.
+    pub key_provider: Option<Arc<dyn KeyProvider>>,
+    /// When set, every request is sent through it instead of `client`, e.g. a
+    /// `reqwest_middleware::ClientWithMiddleware` with the `middleware`
+    /// feature.
+    pub transport: Option<Arc<dyn HttpTransport>>,
+    /// See `ModelDefaults`.
+    pub defaults: Option<ModelDefaults>,
+    /// See `CompletionCache`.
+    pub cache: Option<Arc<CompletionCache>>,
+    /// See `Model::rate_limiter`.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// See `Model::observer`.
+    pub observer: Option<Arc<dyn ModelObserver>>,
+    // What `client` was last built from by a `with_*` method.
+    pub(crate) client_options: ClientOptions,
+}
+
+impl GeminiApiModel {
+    /// `model` on the Gemini API with `api_key`, sent through a default
+    /// `reqwest::Client`. Logs a warning, once, if `api_key` doesn't look
+    /// like a Google API key.
+    pub fn new(api_key: impl Into<String>, model: GeminiModel) -> GeminiApiModel {
+        let api_key = api_key.into();
+        // Google API keys start with `AIza`; anything else is probably a
+        // mix-up, but proxies may accept other keys, so only warn. An empty
+        // key is rejected when a request is sent instead.
+        if !api_key.is_empty() && !api_key.starts_with("AIza") {
+            tracing::warn!("Gemini API key does not start with `AIza`; it may be malformed");
+        }
+        GeminiApiModel {
+            api_key,
+            client: reqwest::Client::new(),
+            model,
+            key_provider: None,
+            transport: None,
+            defaults: None,
+            cache: None,
+            rate_limiter: None,
+            observer: None,
+            client_options: ClientOptions::default(),
+        }
+    }
+
+    /// Asks `provider` for the key before every request, instead of using
+    /// `api_key`.
+    pub fn with_key_provider(mut self, provider: Arc<dyn KeyProvider>) -> Self {
+        self.key_provider = Some(provider);
+        self
+    }
+
+    /// Sends requests through `client`, e.g. one with custom TLS settings,
+    /// instead of a default `reqwest::Client`.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Rebuilds the client with `timeouts`, replacing one set with
+    /// `with_client`. Fails if the client can't be built.
+    pub fn with_timeouts(mut self, timeouts: HttpTimeouts) -> Result<Self, reqwest::Error> {
+        self.client_options.timeouts = timeouts;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Rebuilds the client to speak `version`, replacing one set with
+    /// `with_client`. Fails if the client can't be built.
+    pub fn with_http_version(mut self, version: HttpVersionPref) -> Result<Self, reqwest::Error> {
+        self.client_options.http_version = version;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Rebuilds the client to send requests through the proxies in `proxy`,
+    /// replacing one set with `with_client`. Fails if a proxy URL doesn't
+    /// parse.
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Result<Self, reqwest::Error> {
+        self.client_options.proxy = proxy;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Sends every request through `transport` instead of `client`.
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Settings used where a request leaves them unset.
+    pub fn with_defaults(mut self, defaults: ModelDefaults) -> Self {
+        self.defaults = Some(defaults);
+        self
+    }
+
+    /// Caches completions in `cache`; see `CompletionCache`.
+    pub fn with_cache(mut self, cache: Arc<CompletionCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Waits on `limiter` before every request built with `new_request`.
+    /// Share one limiter between all models that draw from the same quota.
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Reports every request built with `new_request` to `observer`.
+    pub fn with_observer(mut self, observer: Arc<dyn ModelObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    async fn current_api_key(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let key = match &self.key_provider {
+            Some(provider) => provider.api_key().await?,
+            None => self.api_key.clone(),
+        };
+        if key.trim().is_empty() {
+            return Err(LangrustError::Auth("empty API key".to_string()).into());
+        }
+        Ok(key)
+    }
+
+    /// Lists the models available to this API key, as reported by the
+    /// `models` endpoint, following its pages to the end. Fails if the
+    /// endpoint hands back a page token it already gave, rather than
+    /// fetching the same pages forever.
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>, LangrustError> {
+        self.list_models_at(API_BASE).await
+    }
+
+    pub(crate) async fn list_models_at(
+        &self,
+        endpoint: &str,
+    ) -> Result<Vec<ModelInfo>, LangrustError> {
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+        let mut seen_tokens = HashSet::new();
+        loop {
+            let page = self
+                .list_models_page_at(endpoint, page_token.as_deref())
+                .await?;
+            models.extend(page.models);
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => {
+                    if !seen_tokens.insert(token.clone()) {
+                        return Err(LangrustError::InvalidResponse(format!(
+                            "models endpoint repeated page token `{}`",
+                            token
+                        )));
+                    }
+                    page_token = Some(token);
+                }
+                _ => return Ok(models),
+            }
+        }
+    }
+
+    async fn list_models_page_at(
+        &self,
+        endpoint: &str,
+        page_token: Option<&str>,
+    ) -> Result<ListModelsResponse, LangrustError> {
+        let mut builder = self
+            .client
+            .get(endpoint)
+            .header("x-goog-api-key", self.current_api_key().await?);
+        if let Some(token) = page_token {
+            builder = builder.query(&[("pageToken", token)]);
+        }
+        let response = send(builder, self.transport.as_deref()).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(api_error(response, None).await?);
+        }
+
+        response.json().await.map_err(|e| {
+            LangrustError::InvalidResponse(format!("failed to parse model list: {}", e))
+        })
+    }
 }
 
 #[async_trait]
@@ -33,17 +228,42 @@ impl Model for GeminiApiModel {
         self.stream_generate_content(request).await
     }
 
+    fn completions(&self, request: ModelRequest, n: u32) -> CompletionsFuture<'_> {
+        Box::pin(self.generate_contents(request, n))
+    }
+
     fn model_name(&self) -> String {
         self.model.to_string()
     }
+
+    fn defaults(&self) -> Option<&ModelDefaults> {
+        self.defaults.as_ref()
+    }
+
+    fn cache(&self) -> Option<&CompletionCache> {
+        self.cache.as_deref()
+    }
+
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_deref()
+    }
+
+    fn observer(&self) -> Option<&dyn ModelObserver> {
+        self.observer.as_deref()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.model.supports_tools()
+    }
 }
 
 impl GeminiClient for GeminiApiModel {
-    fn get_endpoint(&self, model: &String, method: String) -> String {
-        return format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:{}",
-            model, method
-        );
+    fn transport(&self) -> Option<&dyn HttpTransport> {
+        self.transport.as_deref()
+    }
+
+    fn get_endpoint(&self, model: &String, method: GeminiMethod) -> String {
+        return format!("{}/{}:{}", API_BASE, model, method.as_str());
     }
 
     async fn build_request(
@@ -54,7 +274,7 @@ impl GeminiClient for GeminiApiModel {
         return Ok(self
             .client
             .post(endpoint.clone())
-            .header("x-goog-api-key", self.api_key.clone())
+            .header("x-goog-api-key", self.current_api_key().await?)
             .header("Content-Type", "application/json")
             .json(request_body));
     }
@@ -1,31 +1,75 @@
+use std::{error::Error, time::Duration};
+
 use reqwest::Client;
 use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::client::{LangrustError, TokenCache};
+
+/// How long `gcloud auth print-access-token` may run before it is killed. It
+/// can hang indefinitely when it prompts for reauthentication.
+const GCLOUD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a fetched access token is reused. Tokens live for an hour, but
+/// `gcloud` may hand out one that is close to expiry.
+const ACCESS_TOKEN_TTL: Duration = Duration::from_secs(60);
+
+/// Host of the metadata server, unless `GCE_METADATA_HOST` names another one
+/// (as it does for emulators and some non-GCE runtimes).
+const DEFAULT_METADATA_HOST: &str = "metadata.google.internal";
+
+// Shared by every Vertex model, so a burst of requests fetches one token.
+static ACCESS_TOKEN: TokenCache = TokenCache::new();
 
 #[derive(Debug, Deserialize)]
 struct MetadataTokenResponse {
     access_token: String,
 }
 
-pub async fn get_access_token() -> Result<String, String> {
+pub async fn get_access_token() -> Result<String, Box<dyn Error + Send + Sync>> {
+    ACCESS_TOKEN
+        .get_or_fetch(ACCESS_TOKEN_TTL, fetch_access_token)
+        .await
+}
+
+async fn fetch_access_token() -> Result<String, Box<dyn Error + Send + Sync>> {
     let remote_token = get_access_token_server().await;
-    if remote_token.is_ok() {
-        return remote_token;
+    if let Ok(token) = remote_token {
+        return Ok(token);
     }
 
     return get_access_token_local().await;
 }
 
-async fn get_access_token_local() -> Result<String, String> {
-    use std::process::Command;
+async fn get_access_token_local() -> Result<String, Box<dyn Error + Send + Sync>> {
+    run_gcloud("gcloud", &["auth", "print-access-token"], GCLOUD_TIMEOUT).await
+}
 
-    let output = Command::new("gcloud")
-        .args(&["auth", "print-access-token"])
-        .output()
+// Runs `program` and returns its trimmed stdout. The child is killed if it
+// outlives `timeout`, since dropping the wait future drops it with
+// `kill_on_drop` set.
+async fn run_gcloud(
+    program: &str,
+    args: &[&str],
+    timeout: Duration,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let child = Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
         .map_err(|e| format!("Failed to run gcloud command: {}", e))?;
 
+    let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => output?,
+        Err(_) => return Err(LangrustError::Auth("gcloud timed out".to_string()).into()),
+    };
+
     if !output.status.success() {
         let error = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("gcloud auth failed: {}", error));
+        return Err(format!("gcloud auth failed: {}", error).into());
     }
 
     let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
@@ -38,11 +82,8 @@ async fn get_access_token_server() -> Result<String, String> {
         .build()
         .map_err(|e| e.to_string())?;
 
-    // Google Cloud metadata server endpoint
-    let metadata_url = "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
-
     let response = client
-        .get(metadata_url)
+        .get(metadata_token_url())
         .header("Metadata-Flavor", "Google")
         .send()
         .await
@@ -68,6 +109,23 @@ async fn get_access_token_server() -> Result<String, String> {
     Ok(token_response.access_token)
 }
 
+// Google Cloud metadata server token endpoint, on the host named by
+// `GCE_METADATA_HOST` if it is set, like other Google client libraries.
+fn metadata_token_url() -> String {
+    metadata_token_url_for(std::env::var("GCE_METADATA_HOST").ok().as_deref())
+}
+
+// `metadata_token_url` with `GCE_METADATA_HOST` set to `host`.
+fn metadata_token_url_for(host: Option<&str>) -> String {
+    let host = host
+        .filter(|h| !h.is_empty())
+        .unwrap_or(DEFAULT_METADATA_HOST);
+    format!(
+        "http://{}/computeMetadata/v1/instance/service-accounts/default/token",
+        host
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -80,4 +138,40 @@ mod tests {
         let access_token = response.unwrap();
         assert!(!access_token.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_gcloud_times_out_on_slow_command() {
+        let started = std::time::Instant::now();
+        let err = run_gcloud("sleep", &["10"], Duration::from_millis(100))
+            .await
+            .expect_err("a hung command should time out");
+
+        assert_eq!(
+            err.downcast_ref::<LangrustError>(),
+            Some(&LangrustError::Auth("gcloud timed out".to_string()))
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_gcloud_output_is_trimmed() {
+        let token = run_gcloud("echo", &["ya29.token"], Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(token, "ya29.token");
+    }
+
+    #[test]
+    fn test_metadata_token_url_honors_gce_metadata_host() {
+        assert_eq!(
+            metadata_token_url_for(Some("127.0.0.1:8989")),
+            "http://127.0.0.1:8989/computeMetadata/v1/instance/service-accounts/default/token"
+        );
+        for unset in [None, Some("")] {
+            assert_eq!(
+                metadata_token_url_for(unset),
+                "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token"
+            );
+        }
+    }
 }
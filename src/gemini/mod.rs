@@ -1,6 +1,7 @@
 mod base;
 mod direct_api_client;
 mod gcloud_helpers;
+mod pricing;
 mod types;
 mod vertex_client;
 
@@ -8,5 +9,6 @@ mod vertex_client;
 mod tests;
 
 pub use direct_api_client::GeminiApiModel;
-pub use types::GeminiModel;
+pub use pricing::{CostEstimate, PricingTable};
+pub use types::{GeminiMethod, GeminiModel, ModelCapabilities, ModelInfo};
 pub use vertex_client::GeminiVertexModel;
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use crate::{
+    client::{ContentPart, ModelRequest, estimate_text_tokens},
+    gemini::types::GeminiModel,
+};
+
+/// Input price of each `GeminiModel`, in US dollars per million tokens.
+///
+/// `PricingTable::default()` holds the list prices of prompts up to 200k
+/// tokens at the time of writing; prices change, so override them with
+/// `set_input_price` rather than relying on the built-in figures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricingTable {
+    input_per_million: HashMap<GeminiModel, f64>,
+}
+
+impl Default for PricingTable {
+    fn default() -> PricingTable {
+        PricingTable {
+            input_per_million: HashMap::from([
+                (GeminiModel::Gemini25Flash, 0.30),
+                (GeminiModel::Gemini31Pro, 2.00),
+                (GeminiModel::Gemini3Flash, 0.50),
+                (GeminiModel::Gemini31FlashLite, 0.25),
+            ]),
+        }
+    }
+}
+
+impl PricingTable {
+    /// Sets `model`'s input price, in US dollars per million tokens.
+    pub fn set_input_price(&mut self, model: GeminiModel, usd_per_million: f64) -> &mut Self {
+        self.input_per_million.insert(model, usd_per_million);
+        return self;
+    }
+
+    /// `model`'s input price in US dollars per million tokens, `0.0` if the
+    /// table has none.
+    pub fn input_price(&self, model: &GeminiModel) -> f64 {
+        self.input_per_million.get(model).copied().unwrap_or(0.0)
+    }
+
+    /// Estimates the input cost of sending `request` to `model` at these
+    /// prices, e.g. to show "~$0.003" before the user hits send. Only text is
+    /// counted: the system prompt and every message's text parts. Media and
+    /// the output are not.
+    pub fn estimate(&self, request: &ModelRequest, model: &GeminiModel) -> CostEstimate {
+        let mut text = request.system.clone().unwrap_or_default();
+        for message in request.messages.iter().flatten() {
+            for part in message.content_parts() {
+                if let ContentPart::Text(t) = part {
+                    text.push_str(&t);
+                }
+            }
+        }
+        let input_tokens = estimate_text_tokens(&text);
+        CostEstimate {
+            input_tokens,
+            estimated_input_cost: input_tokens as f64 * self.input_price(model) / 1_000_000.0,
+        }
+    }
+}
+
+/// What a request is expected to cost before it is sent, from
+/// `PricingTable::estimate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostEstimate {
+    /// Estimated prompt tokens, by `estimate_text_tokens`.
+    pub input_tokens: usize,
+    /// `input_tokens` at the model's input price, in US dollars.
+    pub estimated_input_cost: f64,
+}
@@ -9,25 +9,23 @@ use crate::{
     gemini::{
         base::GeminiClient,
         direct_api_client::GeminiApiModel,
-        types::{GeminiModel, GeminiTool},
+        types::{GeminiMethod, GeminiModel, GeminiTool},
         vertex_client::GeminiVertexModel,
     },
 };
 
 fn make_direct(model: GeminiModel) -> GeminiApiModel {
-    GeminiApiModel {
-        client: reqwest::Client::new(),
-        api_key: env::var("GEMINI_KEY").expect("GEMINI_KEY env var must be set"),
+    GeminiApiModel::new(
+        env::var("GEMINI_KEY").expect("GEMINI_KEY env var must be set"),
         model,
-    }
+    )
 }
 
 fn make_vertex(model: GeminiModel) -> GeminiVertexModel {
-    GeminiVertexModel {
-        project_name: env::var("VERTEX_PROJECT").expect("VERTEX_PROJECT env var must be set"),
-        client: reqwest::Client::new(),
+    GeminiVertexModel::new(
+        env::var("VERTEX_PROJECT").expect("VERTEX_PROJECT env var must be set"),
         model,
-    }
+    )
 }
 
 fn default_settings() -> Settings {
@@ -35,9 +33,12 @@ fn default_settings() -> Settings {
         max_tokens: Some(8000),
         timeout: None,
         temperature: None,
+        top_p: None,
         // Use dynamic thinking (-1) so thinking-only models like Gemini 3.1 Pro
         // actually emit a completion. Non-thinking models ignore this.
         thinking_budget: Some(-1),
+        privacy_mode: None,
+        merge_consecutive_roles: None,
     }
 }
 
@@ -72,6 +73,7 @@ async fn run_generate_content<M: Model>(m: &M) {
         .completion()
         .await;
     assert!(response.is_ok(), "completion failed: {:?}", response.err());
+    assert_eq!(response.unwrap().model, m.model_name());
 }
 
 async fn run_with_messages<M: Model>(m: &M) {
@@ -171,7 +173,14 @@ async fn run_stream_generate_content<M: Model>(m: &M) {
                 assert!(total_tokens > 0);
             }
             StreamEvent::FunctionCall(_) => {}
+            StreamEvent::FunctionCallStarted { .. } => {}
             StreamEvent::Error(e) => panic!("stream event should not be an error: {}", e),
+            StreamEvent::Truncated(_) => panic!("stream should not be truncated"),
+            StreamEvent::Stopped(_) => panic!("stream should not be stopped"),
+            StreamEvent::Cancelled => panic!("stream should not be cancelled"),
+            StreamEvent::Filtered { finish_reason, .. } => {
+                panic!("stream should not be filtered: {}", finish_reason)
+            }
         }
     }
 
@@ -206,6 +215,12 @@ async fn run_stream_function_call<M: Model>(m: &M) {
                 assert_eq!(fc.name, "get_weather");
             }
             StreamEvent::Error(e) => panic!("stream event should not be an error: {}", e),
+            StreamEvent::Truncated(_) => panic!("stream should not be truncated"),
+            StreamEvent::Stopped(_) => panic!("stream should not be stopped"),
+            StreamEvent::Cancelled => panic!("stream should not be cancelled"),
+            StreamEvent::Filtered { finish_reason, .. } => {
+                panic!("stream should not be filtered: {}", finish_reason)
+            }
             _ => {}
         }
     }
@@ -375,7 +390,7 @@ fn response_deserializes_when_content_has_no_parts() {
     let resp: GeminiResponse =
         serde_json::from_str(raw).expect("response with no parts should still decode");
     assert_eq!(resp.get_text().as_deref(), Some(""));
-    assert!(resp.get_function().is_none());
+    assert!(resp.get_function_at(0).is_none());
     assert_eq!(resp.get_prompt_tokens(), Some(5));
 }
 
@@ -410,7 +425,9 @@ fn response_with_partial_usage_metadata_reports_missing_counts_as_none() {
     assert_eq!(resp.get_prompt_tokens(), Some(12));
     assert_eq!(resp.get_completion_tokens(), None);
     assert_eq!(resp.get_total_tokens(), None);
-    let func = resp.get_function().expect("function call should be parsed");
+    let func = resp
+        .get_function_at(0)
+        .expect("function call should be parsed");
     assert_eq!(func.name, "get_weather");
 }
 
@@ -427,15 +444,11 @@ fn response_deserializes_when_candidate_has_no_content() {
     let resp: GeminiResponse =
         serde_json::from_str(raw).expect("response with no content should still decode");
     assert_eq!(resp.get_text().as_deref(), Some(""));
-    assert!(resp.get_function().is_none());
+    assert!(resp.get_function_at(0).is_none());
 }
 
 fn make_direct_dummy(model: GeminiModel) -> GeminiApiModel {
-    GeminiApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy".to_string(),
-        model,
-    }
+    GeminiApiModel::new("dummy", model)
 }
 
 fn request_with_thinking(thinking_budget: Option<i16>) -> crate::client::ModelRequest {
@@ -446,9 +459,22 @@ fn request_with_thinking(thinking_budget: Option<i16>) -> crate::client::ModelRe
             max_tokens: Some(100),
             timeout: None,
             temperature: None,
+            top_p: None,
             thinking_budget,
+            privacy_mode: None,
+            merge_consecutive_roles: None,
         }),
         tools: None,
+        function_calling_mode: None,
+        response_schema: None,
+        enum_response: None,
+        logprobs: None,
+        response_modalities: None,
+        safety_settings: None,
+        extra_generation_config: None,
+        before_send: None,
+        client: None,
+        max_response_bytes: None,
     }
 }
 
@@ -479,6 +505,16 @@ fn thinking_config_omitted_when_settings_is_none() {
         messages: Some(vec![Message::user("hi".to_string())]),
         settings: None,
         tools: None,
+        function_calling_mode: None,
+        response_schema: None,
+        enum_response: None,
+        logprobs: None,
+        response_modalities: None,
+        safety_settings: None,
+        extra_generation_config: None,
+        before_send: None,
+        client: None,
+        max_response_bytes: None,
     };
     let body = m.create_request_body(req);
     assert!(body.generation_config.thinking_config.is_none());
@@ -505,6 +541,68 @@ fn thinking_config_set_when_budget_is_some() {
     );
 }
 
+#[test]
+fn thinking_budget_is_added_to_max_output_tokens() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("hi".to_string()))
+        .with_settings(Settings {
+            temperature: Some(1),
+            top_p: None,
+            ..Default::default()
+        })
+        .with_thinking_budget(512, 1024);
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+    let gen_cfg = &json["generationConfig"];
+    assert_eq!(gen_cfg["maxOutputTokens"], 1536);
+    assert_eq!(gen_cfg["thinkingConfig"]["thinkingBudget"], 1024);
+    assert_eq!(gen_cfg["temperature"], 1);
+}
+
+#[test]
+fn dynamic_thinking_budget_leaves_max_output_tokens_at_answer() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder.with_thinking_budget(512, -1);
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+    assert_eq!(json["generationConfig"]["maxOutputTokens"], 512);
+    assert_eq!(
+        json["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+        -1
+    );
+}
+
+#[test]
+fn thinking_config_passes_zero_through_to_disable_thinking() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let json = serde_json::to_value(m.create_request_body(request_with_thinking(Some(0)))).unwrap();
+    assert_eq!(
+        json["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+        0
+    );
+}
+
+#[test]
+fn with_thinking_disabled_sends_zero_budget() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("hi".to_string()))
+        .with_settings(Settings {
+            max_tokens: Some(256),
+            ..Default::default()
+        })
+        .with_thinking_disabled();
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+    let gen_cfg = &json["generationConfig"];
+    assert_eq!(
+        gen_cfg["thinkingConfig"],
+        serde_json::json!({ "thinkingBudget": 0, "includeThoughts": false })
+    );
+    assert_eq!(gen_cfg["maxOutputTokens"], 256);
+}
+
 #[test]
 fn thinking_config_supports_dynamic_budget() {
     // Gemini uses -1 to signal "dynamic thinking". Make sure we pass it through.
@@ -519,6 +617,199 @@ fn thinking_config_supports_dynamic_budget() {
     );
 }
 
+#[test]
+fn function_call_completion_round_trips_as_function_call_part() {
+    use crate::client::{Completion, FunctionCall};
+
+    let completion = Completion {
+        completion: String::new(),
+        usage: Some(Usage {
+            prompt_tokens: 5,
+            completion_tokens: 3,
+            total_tokens: 8,
+        }),
+        function: Some(FunctionCall {
+            name: "get_weather".to_string(),
+            args: std::collections::HashMap::from([(
+                "city".to_string(),
+                serde_json::json!("Paris"),
+            )]),
+            id: None,
+        }),
+        model: "gemini-2.5-flash".to_string(),
+        ..Default::default()
+    };
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut req = request_with_thinking(None);
+    req.messages = Some(vec![
+        Message::user("weather in Paris?".to_string()),
+        Message::from_completion(&completion),
+        Message::function_result("get_weather".to_string(), "sunny"),
+    ]);
+    let json = serde_json::to_value(m.create_request_body(req)).unwrap();
+
+    assert_eq!(
+        json["contents"][1],
+        serde_json::json!({
+            "role": "model",
+            "parts": [{ "functionCall": { "name": "get_weather", "args": { "city": "Paris" } } }]
+        })
+    );
+}
+
+#[test]
+fn text_and_function_call_turn_round_trips_as_text_and_function_call_parts() {
+    use crate::client::{Completion, FunctionCall};
+
+    let completion = Completion {
+        completion: "Let me check.".to_string(),
+        function: Some(FunctionCall {
+            name: "get_weather".to_string(),
+            args: std::collections::HashMap::from([(
+                "city".to_string(),
+                serde_json::json!("Paris"),
+            )]),
+            id: None,
+        }),
+        ..Default::default()
+    };
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut req = request_with_thinking(None);
+    req.messages = Some(vec![
+        Message::user("weather in Paris?".to_string()),
+        Message::from_completion(&completion),
+    ]);
+    let json = serde_json::to_value(m.create_request_body(req)).unwrap();
+
+    assert_eq!(
+        json["contents"][1],
+        serde_json::json!({
+            "role": "model",
+            "parts": [
+                { "text": "Let me check." },
+                { "functionCall": { "name": "get_weather", "args": { "city": "Paris" } } }
+            ]
+        })
+    );
+}
+
+#[test]
+fn parallel_function_results_are_matched_to_calls_by_id() {
+    use crate::client::FunctionCall;
+
+    let call = |id: &str, city: &str| FunctionCall {
+        name: "get_weather".to_string(),
+        args: std::collections::HashMap::from([("city".to_string(), serde_json::json!(city))]),
+        id: Some(id.to_string()),
+    };
+    let paris = call("call-1", "Paris");
+    let rome = call("call-2", "Rome");
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut req = request_with_thinking(None);
+    req.messages = Some(vec![
+        Message::user("weather in Paris and Rome?".to_string()),
+        Message::function_call(paris.clone()),
+        Message::function_call(rome.clone()),
+        Message::function_result_for(&rome, serde_json::json!({ "forecast": "rainy" })),
+        Message::function_result_for(&paris, serde_json::json!({ "forecast": "sunny" })),
+    ]);
+    let json = serde_json::to_value(m.create_request_body(req)).unwrap();
+
+    assert_eq!(
+        json["contents"][1]["parts"],
+        serde_json::json!([
+            { "functionCall": { "id": "call-1", "name": "get_weather", "args": { "city": "Paris" } } },
+            { "functionCall": { "id": "call-2", "name": "get_weather", "args": { "city": "Rome" } } }
+        ])
+    );
+    assert_eq!(
+        json["contents"][2]["parts"],
+        serde_json::json!([
+            { "functionResponse": { "id": "call-2", "name": "get_weather", "response": { "forecast": "rainy" } } },
+            { "functionResponse": { "id": "call-1", "name": "get_weather", "response": { "forecast": "sunny" } } }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn completion_reports_function_call_id() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{
+            "candidates": [
+                {
+                    "content": {
+                        "role": "model",
+                        "parts": [
+                            { "functionCall": { "id": "call-7", "name": "get_weather", "args": { "city": "Paris" } } }
+                        ]
+                    },
+                    "finishReason": "STOP",
+                    "index": 0
+                }
+            ]
+        }"#,
+    )])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let completion = m
+        .new_request()
+        .with_message(Message::user("weather in Paris?".to_string()))
+        .completion()
+        .await
+        .expect("completion should succeed");
+
+    let call = completion.function.expect("expected a function call");
+    assert_eq!(call.id.as_deref(), Some("call-7"));
+}
+
+#[test]
+fn consecutive_user_messages_merge_into_one_content() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut req = request_with_thinking(None);
+    req.messages = Some(vec![
+        Message::user("first".to_string()),
+        Message::user("second".to_string()),
+        Message::user("third".to_string()),
+        Message::model("reply".to_string()),
+    ]);
+    let json = serde_json::to_value(m.create_request_body(req)).unwrap();
+
+    assert_eq!(
+        json["contents"],
+        serde_json::json!([
+            {
+                "role": "user",
+                "parts": [{ "text": "first" }, { "text": "second" }, { "text": "third" }]
+            },
+            { "role": "model", "parts": [{ "text": "reply" }] }
+        ])
+    );
+}
+
+#[test]
+fn consecutive_roles_kept_apart_when_merging_disabled() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut req = request_with_thinking(None);
+    req.settings = Some(Settings {
+        merge_consecutive_roles: Some(false),
+        ..Settings::default()
+    });
+    req.messages = Some(vec![
+        Message::user("first".to_string()),
+        Message::user("second".to_string()),
+    ]);
+    let body = m.create_request_body(req);
+
+    assert_eq!(body.contents.len(), 2);
+}
+
 #[test]
 fn test_model_name_gemini_api() {
     let m = make_direct_dummy(GeminiModel::Gemini25Flash);
@@ -534,19 +825,2616 @@ fn test_model_name_gemini_api() {
     assert_eq!(m.model_name(), "gemini-3.1-flash-lite-preview");
 }
 
+#[test]
+fn capabilities_for_each_model() {
+    use crate::gemini::ModelCapabilities;
+
+    let text_model = ModelCapabilities {
+        context_window: 1_048_576,
+        output_limit: 65_536,
+        multimodal: true,
+        thinking: true,
+        tools: true,
+        json_mode: true,
+    };
+    for model in [
+        GeminiModel::Gemini25Flash,
+        GeminiModel::Gemini31Pro,
+        GeminiModel::Gemini3Flash,
+        GeminiModel::Gemini31FlashLite,
+    ] {
+        assert_eq!(model.capabilities(), text_model, "{}", model.to_string());
+        assert!(model.supports_tools());
+    }
+}
+
 #[test]
 fn test_model_name_gemini_vertex() {
+    let m = GeminiVertexModel::new("dummy-project", GeminiModel::Gemini25Flash);
+    assert_eq!(m.model_name(), "gemini-2.5-flash");
+
+    let m = GeminiVertexModel::new("dummy-project", GeminiModel::Gemini31Pro);
+    assert_eq!(m.model_name(), "gemini-3.1-pro-preview");
+}
+
+// Hands out a fixed Vertex access token, so no gcloud is needed.
+struct StaticToken;
+
+#[async_trait::async_trait]
+impl crate::client::KeyProvider for StaticToken {
+    async fn api_key(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        Ok("vertex-token".to_string())
+    }
+}
+
+fn vertex_dummy(endpoint_override: Option<&str>) -> GeminiVertexModel {
+    GeminiVertexModel {
+        endpoint_override: endpoint_override.map(str::to_string),
+        ..GeminiVertexModel::new("dummy-project", GeminiModel::Gemini25Flash)
+    }
+}
+
+#[test]
+fn vertex_endpoint_defaults_to_public_host() {
+    let m = vertex_dummy(None);
+    assert_eq!(
+        m.get_endpoint(&m.model_name(), GeminiMethod::GenerateContent),
+        "https://aiplatform.googleapis.com/v1/projects/dummy-project/locations/global/publishers/google/models/gemini-2.5-flash:generateContent"
+    );
+}
+
+#[test]
+fn vertex_endpoint_override_uses_psc_host() {
+    let m = vertex_dummy(Some(
+        "https://us-central1-aiplatform-mypsc.p.googleapis.com/",
+    ));
+    assert_eq!(
+        m.get_endpoint(&m.model_name(), GeminiMethod::GenerateContent),
+        "https://us-central1-aiplatform-mypsc.p.googleapis.com/v1/projects/dummy-project/locations/global/publishers/google/models/gemini-2.5-flash:generateContent"
+    );
+
+    let m = vertex_dummy(Some("aiplatform-mypsc.p.googleapis.com"));
+    assert!(
+        m.get_endpoint(&m.model_name(), GeminiMethod::StreamGenerateContent)
+            .starts_with("https://aiplatform-mypsc.p.googleapis.com/v1/projects/dummy-project/")
+    );
+}
+
+#[test]
+fn vertex_endpoint_uses_publisher_override() {
     let m = GeminiVertexModel {
-        client: reqwest::Client::new(),
-        project_name: "dummy-project".to_string(),
-        model: GeminiModel::Gemini25Flash,
+        publisher: Some("anthropic".to_string()),
+        ..vertex_dummy(None)
     };
-    assert_eq!(m.model_name(), "gemini-2.5-flash");
+    assert_eq!(
+        m.get_endpoint(&m.model_name(), GeminiMethod::GenerateContent),
+        "https://aiplatform.googleapis.com/v1/projects/dummy-project/locations/global/publishers/anthropic/models/gemini-2.5-flash:generateContent"
+    );
+}
+
+#[test]
+fn vertex_endpoint_for_each_method() {
+    let m = vertex_dummy(None);
+    let base = "https://aiplatform.googleapis.com/v1/projects/dummy-project/locations/global/publishers/google/models/gemini-2.5-flash";
+    for (method, suffix) in [
+        (GeminiMethod::GenerateContent, ":generateContent"),
+        (
+            GeminiMethod::StreamGenerateContent,
+            ":streamGenerateContent?alt=sse",
+        ),
+        (GeminiMethod::CountTokens, ":countTokens"),
+        (GeminiMethod::EmbedContent, ":embedContent"),
+    ] {
+        assert_eq!(
+            m.get_endpoint(&m.model_name(), method),
+            format!("{}{}", base, suffix)
+        );
+    }
+}
+
+#[test]
+fn direct_endpoint_for_each_method() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let base = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash";
+    for (method, suffix) in [
+        (GeminiMethod::GenerateContent, ":generateContent"),
+        (
+            GeminiMethod::StreamGenerateContent,
+            ":streamGenerateContent?alt=sse",
+        ),
+        (GeminiMethod::CountTokens, ":countTokens"),
+        (GeminiMethod::EmbedContent, ":embedContent"),
+    ] {
+        assert_eq!(
+            m.get_endpoint(&m.model_name(), method),
+            format!("{}{}", base, suffix)
+        );
+    }
+}
+
+#[tokio::test]
+async fn html_error_page_is_reported_as_html() {
+    use crate::client::{ErrorBodyKind, LangrustError};
+    use crate::test_server::{MockResponse, MockServer};
+
+    let page = "<!DOCTYPE html>\n<html><head><title>502 Bad Gateway</title></head>\
+                <body><h1>Bad Gateway</h1></body></html>";
+    let server = MockServer::start(vec![MockResponse {
+        status: 502,
+        content_type: "text/html; charset=utf-8",
+        body: page.as_bytes().to_vec(),
+        delay: None,
+        hold_open: false,
+        split_at: None,
+    }])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let err = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .expect_err("a 502 should fail");
+
+    let Some(LangrustError::ApiError {
+        status,
+        content_type,
+        body_kind,
+        body,
+    }) = err.downcast_ref::<LangrustError>()
+    else {
+        panic!("expected ApiError, got {}", err);
+    };
+    assert_eq!(*status, 502);
+    assert_eq!(content_type.as_deref(), Some("text/html; charset=utf-8"));
+    assert_eq!(*body_kind, ErrorBodyKind::Html);
+    assert_eq!(body, page.as_bytes());
+    let message = err.to_string();
+    assert!(message.contains("status 502 (HTML body"), "{}", message);
+    assert!(message.contains("<h1>Bad Gateway</h1>"), "{}", message);
+}
+
+#[tokio::test]
+async fn binary_error_body_is_previewed_as_hex() {
+    use crate::client::{ErrorBodyKind, LangrustError};
+    use crate::test_server::{MockResponse, MockServer};
+
+    // The start of a gzip stream, which isn't valid UTF-8.
+    let gzipped = vec![0x1f, 0x8b, 0x08, 0x00, 0xff, 0xfe];
+    let server = MockServer::start(vec![MockResponse {
+        status: 503,
+        content_type: "application/octet-stream",
+        body: gzipped.clone(),
+        delay: None,
+        hold_open: false,
+        split_at: None,
+    }])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let err = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .stream()
+        .await
+        .err()
+        .expect("a 503 should fail");
+
+    let Some(LangrustError::ApiError {
+        body_kind, body, ..
+    }) = err.downcast_ref::<LangrustError>()
+    else {
+        panic!("expected ApiError, got {}", err);
+    };
+    assert_eq!(*body_kind, ErrorBodyKind::Binary);
+    assert_eq!(*body, gzipped);
+    assert!(
+        err.to_string().ends_with("6 bytes: 1f 8b 08 00 ff fe"),
+        "{}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn json_error_body_is_kept_as_text() {
+    use crate::client::{ErrorBodyKind, LangrustError};
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        400,
+        r#"{"error":{"code":400,"message":"API key not valid.","status":"INVALID_ARGUMENT"}}"#,
+    )])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let err = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .expect_err("a 400 should fail");
+
+    assert!(matches!(
+        err.downcast_ref::<LangrustError>(),
+        Some(LangrustError::ApiError {
+            status: 400,
+            body_kind: ErrorBodyKind::Json,
+            ..
+        })
+    ));
+    assert!(err.to_string().contains("API key not valid."), "{}", err);
+}
+
+#[tokio::test]
+async fn empty_api_key_fails_before_sending() {
+    use crate::client::LangrustError;
+
+    for api_key in ["", "   "] {
+        let m = GeminiApiModel {
+            api_key: api_key.to_string(),
+            ..make_direct_dummy(GeminiModel::Gemini25Flash)
+        };
+        let err = m
+            .new_request()
+            .with_message(Message::user("hello".to_string()))
+            .completion()
+            .await
+            .expect_err("an empty key should be rejected");
+        assert_eq!(
+            err.downcast_ref::<LangrustError>(),
+            Some(&LangrustError::Auth("empty API key".to_string()))
+        );
+    }
+}
+
+#[tokio::test]
+async fn vertex_stream_uses_stream_endpoint_and_bearer_token() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
 
+    let server = MockServer::start(vec![MockResponse::sse(&[
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Hel"}]},"index":0}]}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"lo"}]},"finishReason":"STOP","index":0}],"usageMetadata":{"promptTokenCount":4,"candidatesTokenCount":2,"totalTokenCount":6}}"#,
+    ])])
+    .await;
     let m = GeminiVertexModel {
-        client: reqwest::Client::new(),
-        project_name: "dummy-project".to_string(),
-        model: GeminiModel::Gemini31Pro,
+        token_provider: Some(Arc::new(StaticToken)),
+        ..vertex_dummy(Some(&server.url))
     };
-    assert_eq!(m.model_name(), "gemini-3.1-pro-preview");
+
+    let events: Vec<StreamEvent> = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .stream()
+        .await
+        .expect("stream should open")
+        .collect()
+        .await;
+
+    let text: String = events
+        .iter()
+        .filter_map(|e| match e {
+            StreamEvent::Delta(d) => Some(d.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(text, "Hello");
+    assert_eq!(events.last().unwrap().usage().unwrap().total_tokens, 6);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].path,
+        "/v1/projects/dummy-project/locations/global/publishers/google/models/gemini-2.5-flash:streamGenerateContent?alt=sse"
+    );
+    assert_eq!(
+        requests[0].header("authorization"),
+        Some("Bearer vertex-token")
+    );
+}
+
+#[tokio::test]
+async fn concurrent_vertex_requests_share_one_token_fetch() {
+    use crate::client::{CachedKeyProvider, KeyProvider};
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    struct CountingToken(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl KeyProvider for CountingToken {
+        async fn api_key(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            Ok("vertex-token".to_string())
+        }
+    }
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"hi"}]},"finishReason":"STOP","index":0}]}"#,
+    )])
+    .await;
+    let counter = Arc::new(CountingToken(AtomicUsize::new(0)));
+    let m = Arc::new(GeminiVertexModel {
+        token_provider: Some(Arc::new(CachedKeyProvider::new(
+            counter.clone(),
+            std::time::Duration::from_secs(60),
+        ))),
+        ..vertex_dummy(Some(&server.url))
+    });
+
+    let tasks: Vec<_> = (0..20)
+        .map(|_| {
+            let m = m.clone();
+            tokio::spawn(async move {
+                m.new_request()
+                    .with_message(Message::user("hello".to_string()))
+                    .completion()
+                    .await
+                    .map(|c| c.completion)
+                    .map_err(|e| e.to_string())
+            })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), Ok("hi".to_string()));
+    }
+
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    assert_eq!(server.requests().len(), 20);
+}
+
+#[tokio::test]
+async fn list_models_parses_model_list() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{
+            "models": [
+                {
+                    "name": "models/gemini-2.5-flash",
+                    "displayName": "Gemini 2.5 Flash",
+                    "inputTokenLimit": 1048576,
+                    "outputTokenLimit": 65536,
+                    "supportedGenerationMethods": ["generateContent", "countTokens"]
+                },
+                {
+                    "name": "models/text-embedding-004",
+                    "displayName": "Text Embedding 004",
+                    "inputTokenLimit": 2048,
+                    "outputTokenLimit": 1,
+                    "supportedGenerationMethods": ["embedContent"]
+                }
+            ]
+        }"#,
+    )])
+    .await;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let models = m
+        .list_models_at(&format!("{}/v1beta/models", server.url))
+        .await
+        .expect("list models should succeed");
+
+    assert_eq!(models.len(), 2);
+    assert_eq!(models[0].name, "models/gemini-2.5-flash");
+    assert_eq!(models[0].display_name.as_deref(), Some("Gemini 2.5 Flash"));
+    assert_eq!(models[0].input_token_limit, Some(1048576));
+    assert_eq!(models[0].output_token_limit, Some(65536));
+    assert_eq!(
+        models[0].supported_generation_methods,
+        vec!["generateContent", "countTokens"]
+    );
+    assert_eq!(models[1].supported_generation_methods, vec!["embedContent"]);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(requests[0].path, "/v1beta/models");
+    assert_eq!(requests[0].header("x-goog-api-key"), Some("dummy"));
+}
+
+#[tokio::test]
+async fn list_models_follows_next_page_token() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![
+        MockResponse::json(
+            200,
+            r#"{
+                "models": [{ "name": "models/gemini-2.5-flash" }, { "name": "models/gemini-2.5-pro" }],
+                "nextPageToken": "page-2"
+            }"#,
+        ),
+        MockResponse::json(
+            200,
+            r#"{ "models": [{ "name": "models/text-embedding-004" }] }"#,
+        ),
+    ])
+    .await;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let models = m
+        .list_models_at(&format!("{}/v1beta/models", server.url))
+        .await
+        .unwrap();
+
+    let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "models/gemini-2.5-flash",
+            "models/gemini-2.5-pro",
+            "models/text-embedding-004"
+        ]
+    );
+    let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+    assert_eq!(
+        paths,
+        vec!["/v1beta/models", "/v1beta/models?pageToken=page-2"]
+    );
+}
+
+#[tokio::test]
+async fn list_models_stops_on_a_repeated_page_token() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let page = |token: &str| {
+        MockResponse::json(
+            200,
+            &format!(
+                r#"{{ "models": [{{ "name": "models/gemini-2.5-flash" }}], "nextPageToken": "{}" }}"#,
+                token
+            ),
+        )
+    };
+    let server = MockServer::start(vec![page("page-2"), page("page-3"), page("page-2")]).await;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let err = m
+        .list_models_at(&format!("{}/v1beta/models", server.url))
+        .await
+        .expect_err("a repeated page token should fail");
+
+    assert_eq!(
+        err,
+        crate::client::LangrustError::InvalidResponse(
+            "models endpoint repeated page token `page-2`".to_string()
+        )
+    );
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn list_models_returns_an_api_error_on_failure() {
+    use crate::client::LangrustError;
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        403,
+        r#"{ "error": { "code": 403, "message": "API key not valid", "status": "PERMISSION_DENIED" } }"#,
+    )])
+    .await;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let err = m
+        .list_models_at(&format!("{}/v1beta/models", server.url))
+        .await
+        .expect_err("a 403 should fail");
+
+    assert!(
+        matches!(err, LangrustError::ApiError { status: 403, .. }),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn per_request_client_is_used_instead_of_models() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let tenant_client = |tenant: &str| {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-tenant", tenant.parse().unwrap());
+        reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap()
+    };
+
+    let server = MockServer::start(vec![MockResponse::json(200, HELLO_RESPONSE)]).await;
+    let m = MockGeminiModel {
+        client: tenant_client("model"),
+        ..MockGeminiModel::new(&server)
+    };
+    let mut builder = m.new_request();
+    builder.with_message(Message::user("hi".to_string()));
+
+    builder
+        .clone()
+        .with_client(tenant_client("acme"))
+        .completion()
+        .await
+        .unwrap();
+    builder.completion().await.unwrap();
+
+    let tenants: Vec<Option<String>> = server
+        .requests()
+        .iter()
+        .map(|r| r.header("x-tenant").map(str::to_string))
+        .collect();
+    assert_eq!(
+        tenants,
+        vec![Some("acme".to_string()), Some("model".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn key_provider_is_consulted_per_request() {
+    use crate::client::KeyProvider;
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    struct RotatingKeys(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl KeyProvider for RotatingKeys {
+        async fn api_key(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(format!("key-{}", self.0.fetch_add(1, Ordering::SeqCst)))
+        }
+    }
+
+    let server = MockServer::start(vec![MockResponse::json(200, r#"{"models": []}"#)]).await;
+    let m = GeminiApiModel {
+        key_provider: Some(Arc::new(RotatingKeys(AtomicUsize::new(1)))),
+        ..make_direct_dummy(GeminiModel::Gemini25Flash)
+    };
+    let endpoint = format!("{}/v1beta/models", server.url);
+    m.list_models_at(&endpoint).await.unwrap();
+    m.list_models_at(&endpoint).await.unwrap();
+
+    let keys: Vec<Option<String>> = server
+        .requests()
+        .iter()
+        .map(|r| r.header("x-goog-api-key").map(str::to_string))
+        .collect();
+    assert_eq!(
+        keys,
+        vec![Some("key-1".to_string()), Some("key-2".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn completion_with_only_system_prompt_fails_locally() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let err = m
+        .new_request()
+        .with_system("you are a helpful assistant".to_string())
+        .completion()
+        .await
+        .expect_err("a request without messages should be rejected");
+
+    assert_eq!(
+        err.downcast_ref::<crate::client::LangrustError>(),
+        Some(&crate::client::LangrustError::InvalidRequest(
+            "no messages".to_string()
+        ))
+    );
+}
+
+// GeminiClient pointed at a local mock server, for end-to-end tests of the
+// request/response handling without network access.
+struct MockGeminiModel {
+    client: reqwest::Client,
+    base_url: String,
+    model: GeminiModel,
+}
+
+impl MockGeminiModel {
+    fn new(server: &crate::test_server::MockServer) -> MockGeminiModel {
+        MockGeminiModel {
+            client: reqwest::Client::new(),
+            base_url: server.url.clone(),
+            model: GeminiModel::Gemini25Flash,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Model for MockGeminiModel {
+    async fn completion(
+        &self,
+        request: crate::client::ModelRequest,
+    ) -> Result<crate::client::Completion, Box<dyn std::error::Error + Send + Sync>> {
+        self.generate_content(request).await
+    }
+
+    async fn stream_completion(
+        &self,
+        request: crate::client::ModelRequest,
+    ) -> Result<crate::client::StreamResult, Box<dyn std::error::Error + Send + Sync>> {
+        self.stream_generate_content(request).await
+    }
+
+    fn completions(
+        &self,
+        request: crate::client::ModelRequest,
+        n: u32,
+    ) -> crate::client::CompletionsFuture<'_> {
+        Box::pin(self.generate_contents(request, n))
+    }
+
+    fn model_name(&self) -> String {
+        self.model.to_string()
+    }
+}
+
+impl GeminiClient for MockGeminiModel {
+    fn get_endpoint(&self, model: &String, method: GeminiMethod) -> String {
+        format!("{}/models/{}:{}", self.base_url, model, method.as_str())
+    }
+
+    async fn build_request(
+        &self,
+        endpoint: &String,
+        request_body: &crate::gemini::types::GeminiRequest,
+    ) -> Result<reqwest::RequestBuilder, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.client.post(endpoint).json(request_body))
+    }
+}
+
+#[tokio::test]
+async fn completion_reports_model_version_and_response_id() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{
+            "candidates": [
+                {
+                    "content": { "role": "model", "parts": [{ "text": "Hi!" }] },
+                    "finishReason": "STOP",
+                    "index": 0
+                }
+            ],
+            "usageMetadata": {
+                "promptTokenCount": 3,
+                "candidatesTokenCount": 2,
+                "totalTokenCount": 5
+            },
+            "modelVersion": "gemini-2.5-flash-001",
+            "responseId": "resp-abc123"
+        }"#,
+    )])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let completion = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .expect("completion should succeed");
+
+    assert_eq!(completion.completion, "Hi!");
+    assert_eq!(completion.model, "gemini-2.5-flash");
+    assert_eq!(
+        completion.model_version.as_deref(),
+        Some("gemini-2.5-flash-001")
+    );
+    assert_eq!(completion.response_id.as_deref(), Some("resp-abc123"));
+}
+
+#[tokio::test]
+async fn completion_parses_logprobs_result() {
+    use crate::client::{TokenLogprob, TokenLogprobs};
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{
+            "candidates": [
+                {
+                    "content": { "role": "model", "parts": [{ "text": "Yes." }] },
+                    "finishReason": "STOP",
+                    "index": 0,
+                    "logprobsResult": {
+                        "topCandidates": [
+                            { "candidates": [
+                                { "token": "Yes", "tokenId": 1, "logProbability": -0.1 },
+                                { "token": "No", "tokenId": 2, "logProbability": -2.5 }
+                            ] },
+                            { "candidates": [
+                                { "token": ".", "tokenId": 3, "logProbability": -0.01 },
+                                { "token": "!", "tokenId": 4, "logProbability": -4.75 }
+                            ] }
+                        ],
+                        "chosenCandidates": [
+                            { "token": "Yes", "tokenId": 1, "logProbability": -0.1 },
+                            { "token": ".", "tokenId": 3, "logProbability": -0.01 }
+                        ]
+                    }
+                }
+            ]
+        }"#,
+    )])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let completion = m
+        .new_request()
+        .with_message(Message::user("is it?".to_string()))
+        .with_logprobs(2)
+        .completion()
+        .await
+        .expect("completion should succeed");
+
+    let token = |token: &str, logprob: f64| TokenLogprob {
+        token: token.to_string(),
+        logprob,
+    };
+    assert_eq!(
+        completion.logprobs,
+        Some(vec![
+            TokenLogprobs {
+                chosen: token("Yes", -0.1),
+                top: vec![token("Yes", -0.1), token("No", -2.5)],
+            },
+            TokenLogprobs {
+                chosen: token(".", -0.01),
+                top: vec![token(".", -0.01), token("!", -4.75)],
+            },
+        ])
+    );
+
+    let body: serde_json::Value = serde_json::from_slice(&server.requests()[0].body).unwrap();
+    assert_eq!(body["generationConfig"]["responseLogprobs"], true);
+    assert_eq!(body["generationConfig"]["logprobs"], 2);
+}
+
+#[tokio::test]
+async fn completion_surfaces_avg_logprobs() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![
+        MockResponse::json(
+            200,
+            r#"{
+                "candidates": [
+                    {
+                        "content": { "role": "model", "parts": [{ "text": "Paris." }] },
+                        "finishReason": "STOP",
+                        "avgLogprobs": -0.0421
+                    }
+                ]
+            }"#,
+        ),
+        MockResponse::json(200, HELLO_RESPONSE),
+    ])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let mut builder = m.new_request();
+    builder.with_message(Message::user("Capital of France?".to_string()));
+
+    let completion = builder.completion().await.unwrap();
+    assert_eq!(completion.avg_logprobs, Some(-0.0421));
+
+    let completion = builder.completion().await.unwrap();
+    assert_eq!(completion.avg_logprobs, None);
+}
+
+#[test]
+fn response_modalities_serialize_into_generation_config() {
+    use crate::client::Modality;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder.with_message(Message::user("hi".to_string()));
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+    assert!(json["generationConfig"].get("responseModalities").is_none());
+
+    builder.with_response_modalities(vec![Modality::Text, Modality::Image, Modality::Audio]);
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+    assert_eq!(
+        json["generationConfig"]["responseModalities"],
+        serde_json::json!(["TEXT", "IMAGE", "AUDIO"])
+    );
+}
+
+#[tokio::test]
+async fn completion_decodes_generated_images() {
+    use crate::client::{MediaPart, Modality};
+    use crate::test_server::{MockResponse, MockServer};
+
+    // An 8-byte PNG signature, base64-encoded.
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [
+                        { "text": "Here is your cat." },
+                        { "inlineData": { "mimeType": "image/png", "data": "iVBORw0KGgo=" } }
+                    ]
+                },
+                "finishReason": "STOP"
+            }]
+        }"#,
+    )])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let completion = m
+        .new_request()
+        .with_message(Message::user("Draw a cat".to_string()))
+        .with_response_modalities(vec![Modality::Text, Modality::Image])
+        .completion()
+        .await
+        .unwrap();
+
+    let body: serde_json::Value = serde_json::from_slice(&server.requests()[0].body).unwrap();
+    assert_eq!(
+        body["generationConfig"]["responseModalities"],
+        serde_json::json!(["TEXT", "IMAGE"])
+    );
+    assert_eq!(completion.completion, "Here is your cat.");
+    assert_eq!(
+        completion.media,
+        vec![MediaPart {
+            mime_type: "image/png".to_string(),
+            data: vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'],
+        }]
+    );
+}
+
+#[tokio::test]
+async fn completion_reports_safety_ratings() {
+    use crate::client::{HarmCategory, HarmProbability};
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{
+            "candidates": [
+                {
+                    "content": { "role": "model", "parts": [{ "text": "Sure." }] },
+                    "finishReason": "STOP",
+                    "index": 0,
+                    "safetyRatings": [
+                        { "category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE" },
+                        { "category": "HARM_CATEGORY_HATE_SPEECH", "probability": "LOW" },
+                        { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "MEDIUM" },
+                        { "category": "HARM_CATEGORY_IMAGE_HATE", "probability": "HARM_PROBABILITY_UNSPECIFIED" }
+                    ]
+                }
+            ]
+        }"#,
+    )])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let completion = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .expect("completion should succeed");
+
+    let ratings = completion.safety_ratings.expect("ratings should be parsed");
+    assert_eq!(ratings.ratings.len(), 4);
+    assert_eq!(
+        ratings.probability(&HarmCategory::Harassment),
+        Some(HarmProbability::Negligible)
+    );
+    assert_eq!(
+        ratings.probability(&HarmCategory::HateSpeech),
+        Some(HarmProbability::Low)
+    );
+    assert_eq!(
+        ratings.probability(&HarmCategory::DangerousContent),
+        Some(HarmProbability::Medium)
+    );
+    assert_eq!(
+        ratings.probability(&HarmCategory::Other("HARM_CATEGORY_IMAGE_HATE".to_string())),
+        Some(HarmProbability::Unspecified)
+    );
+    assert_eq!(ratings.probability(&HarmCategory::SexuallyExplicit), None);
+    assert!(ratings.blocked().is_empty());
+}
+
+#[tokio::test]
+async fn blocked_prompt_returns_error_with_safety_ratings() {
+    use crate::client::{HarmCategory, HarmProbability, LangrustError};
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{
+            "promptFeedback": {
+                "blockReason": "SAFETY",
+                "safetyRatings": [
+                    { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "probability": "NEGLIGIBLE" },
+                    { "category": "HARM_CATEGORY_HARASSMENT", "probability": "HIGH", "blocked": true }
+                ]
+            },
+            "usageMetadata": { "promptTokenCount": 12, "totalTokenCount": 12 }
+        }"#,
+    )])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let err = m
+        .new_request()
+        .with_message(Message::user("something rude".to_string()))
+        .completion()
+        .await
+        .expect_err("a blocked prompt should fail");
+
+    let Some(LangrustError::PromptBlocked {
+        reason,
+        safety_ratings,
+        usage,
+    }) = err.downcast_ref::<LangrustError>()
+    else {
+        panic!("expected PromptBlocked, got {}", err);
+    };
+    assert_eq!(reason, "SAFETY");
+    let blocked = safety_ratings.blocked();
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0].category, HarmCategory::Harassment);
+    assert_eq!(blocked[0].probability, HarmProbability::High);
+    assert_eq!(
+        safety_ratings.probability(&HarmCategory::SexuallyExplicit),
+        Some(HarmProbability::Negligible)
+    );
+    assert_eq!(
+        *usage,
+        Some(Usage {
+            prompt_tokens: 12,
+            completion_tokens: 0,
+            total_tokens: 12,
+        })
+    );
+}
+
+fn safety_settings_json(preset: Option<crate::client::SafetySettings>) -> serde_json::Value {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut req = request_with_thinking(None);
+    req.safety_settings = preset;
+    serde_json::to_value(m.create_request_body(req)).unwrap()
+}
+
+#[test]
+fn permissive_safety_preset_blocks_none_in_every_category() {
+    let json = safety_settings_json(Some(crate::client::SafetySettings::permissive()));
+    assert_eq!(
+        json["safetySettings"],
+        serde_json::json!([
+            { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_NONE" },
+            { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "BLOCK_NONE" },
+            { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": "BLOCK_NONE" },
+            { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "BLOCK_NONE" }
+        ])
+    );
+}
+
+#[test]
+fn strict_safety_preset_blocks_low_and_above() {
+    let json = safety_settings_json(Some(crate::client::SafetySettings::strict()));
+    let settings = json["safetySettings"].as_array().unwrap();
+    assert_eq!(settings.len(), 4);
+    assert!(
+        settings
+            .iter()
+            .all(|s| s["threshold"] == "BLOCK_LOW_AND_ABOVE")
+    );
+}
+
+#[test]
+fn safety_settings_omitted_by_default() {
+    assert!(safety_settings_json(None).get("safetySettings").is_none());
+}
+
+#[test]
+fn duplicate_tools_serialize_one_function_declaration() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("hi".to_string()))
+        .with_tool(Tool::new("get_weather", "Old weather tool"))
+        .with_tool(Tool::new("get_weather", "Current weather for a city"));
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+
+    let declarations = json["tools"][0]["functionDeclarations"].as_array().unwrap();
+    assert_eq!(declarations.len(), 1);
+    assert_eq!(declarations[0]["name"], "get_weather");
+    assert_eq!(declarations[0]["description"], "Current weather for a city");
+}
+
+#[test]
+fn pinned_messages_come_before_volatile_contents() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_system("Answer from the handbook.".to_string())
+        .with_message(Message::user("How many vacation days?".to_string()))
+        .with_message(Message::model("25.".to_string()))
+        .with_pinned_message(Message::user("HANDBOOK PART 1".to_string()))
+        .with_message(Message::user("And sick days?".to_string()))
+        .with_pinned_message(Message::user("HANDBOOK PART 2".to_string()));
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+
+    let texts: Vec<Vec<&str>> = json["contents"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            c["parts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["text"].as_str().unwrap())
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        texts,
+        vec![
+            vec![
+                "HANDBOOK PART 1",
+                "HANDBOOK PART 2",
+                "How many vacation days?"
+            ],
+            vec!["25."],
+            vec!["And sick days?"],
+        ]
+    );
+}
+
+#[test]
+fn enum_response_sets_enum_mime_type_and_schema() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("Is this review positive?".to_string()))
+        .with_enum_response(vec!["positive".to_string(), "negative".to_string()]);
+    let request = builder.to_model_request();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+
+    let gen_cfg = &json["generationConfig"];
+    assert_eq!(gen_cfg["responseMimeType"], "text/x.enum");
+    assert_eq!(
+        gen_cfg["responseSchema"],
+        serde_json::json!({ "type": "STRING", "enum": ["positive", "negative"] })
+    );
+}
+
+#[tokio::test]
+async fn enum_response_flags_answers_outside_the_set() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let answer = |text: &str| {
+        MockResponse::json(
+            200,
+            &format!(
+                r#"{{"candidates":[{{"content":{{"role":"model","parts":[{{"text":"{}"}}]}},"finishReason":"STOP","index":0}}]}}"#,
+                text
+            ),
+        )
+    };
+    let server = MockServer::start(vec![answer(r"negative\n"), answer("neutral")]).await;
+    let m = MockGeminiModel::new(&server);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("Is this review positive?".to_string()))
+        .with_enum_response(vec!["positive".to_string(), "negative".to_string()]);
+
+    let in_set = builder.completion().await.unwrap();
+    assert_eq!(in_set.as_enum(), Some("negative"));
+    let out_of_set = builder.completion().await.unwrap();
+    assert_eq!(out_of_set.completion, "neutral");
+    assert_eq!(out_of_set.as_enum(), None);
+}
+
+#[tokio::test]
+async fn enum_response_is_checked_on_direct_model_calls() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"negative"}]},"finishReason":"STOP","index":0}]}"#,
+    )])
+    .await;
+    let m = MockGeminiModel::new(&server);
+    let request = m
+        .new_request()
+        .with_message(Message::user("Is this review positive?".to_string()))
+        .with_enum_response(vec!["positive".to_string(), "negative".to_string()])
+        .to_model_request();
+
+    let completion = m.completion(request).await.unwrap();
+    assert_eq!(completion.as_enum(), Some("negative"));
+}
+
+#[test]
+fn response_schema_value_is_converted_for_gemini() {
+    use serde_json::json;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("classify this".to_string()))
+        .with_response_schema_value(json!({
+            "type": "object",
+            "properties": {
+                "label": { "type": "string" },
+                "score": { "type": ["number", "null"] }
+            },
+            "required": ["label"]
+        }));
+    let request = builder.to_model_request();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+
+    let gen_cfg = &json["generationConfig"];
+    assert_eq!(gen_cfg["responseMimeType"], "application/json");
+    let schema = &gen_cfg["responseSchema"];
+    assert_eq!(schema["type"], "OBJECT");
+    assert_eq!(schema["properties"]["label"]["type"], "STRING");
+    assert_eq!(schema["properties"]["score"]["type"], "NUMBER");
+    assert_eq!(schema["properties"]["score"]["nullable"], true);
+    assert_eq!(schema["required"], json!(["label"]));
+}
+
+#[test]
+fn response_schema_value_must_be_a_schema_object() {
+    use serde_json::json;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    for schema in [
+        json!("object"),
+        json!({}),
+        json!({ "description": "a label" }),
+    ] {
+        let mut builder = m.new_request();
+        builder
+            .with_message(Message::user("hi".to_string()))
+            .with_response_schema_value(schema);
+        assert!(builder.to_model_request().validate().is_err());
+    }
+}
+
+#[test]
+fn extra_generation_config_is_merged_under_typed_fields() {
+    use serde_json::json;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("hi".to_string()))
+        .with_settings(Settings {
+            max_tokens: Some(256),
+            ..Default::default()
+        })
+        .with_extra_generation_config(json!({
+            "mediaResolution": "MEDIA_RESOLUTION_LOW",
+            "maxOutputTokens": 9999,
+        }))
+        .with_extra_generation_config(json!({ "audioTimestamp": true }));
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+
+    let gen_cfg = &json["generationConfig"];
+    assert_eq!(gen_cfg["mediaResolution"], "MEDIA_RESOLUTION_LOW");
+    assert_eq!(gen_cfg["audioTimestamp"], true);
+    assert_eq!(gen_cfg["maxOutputTokens"], 256);
+}
+
+#[test]
+fn extra_generation_config_must_be_an_object() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("hi".to_string()))
+        .with_extra_generation_config(serde_json::json!(["mediaResolution"]));
+    assert!(builder.to_model_request().validate().is_err());
+}
+
+#[test]
+fn extra_generation_config_rejects_non_positive_top_k_and_candidate_count() {
+    use crate::client::LangrustError;
+
+    let validate = |extra: serde_json::Value| {
+        let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+        let mut builder = m.new_request();
+        builder
+            .with_message(Message::user("hi".to_string()))
+            .with_extra_generation_config(extra);
+        builder.to_model_request().validate()
+    };
+
+    assert_eq!(
+        validate(serde_json::json!({ "topK": 0 })),
+        Err(LangrustError::InvalidRequest(
+            "topK must be a positive integer, got 0".to_string()
+        ))
+    );
+    assert_eq!(
+        validate(serde_json::json!({ "candidateCount": -1 })),
+        Err(LangrustError::InvalidRequest(
+            "candidateCount must be a positive integer, got -1".to_string()
+        ))
+    );
+    assert_eq!(
+        validate(serde_json::json!({ "topK": 40, "candidateCount": 1 })),
+        Ok(())
+    );
+}
+
+#[test]
+fn top_p_is_sent_in_generation_config() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("hi".to_string()))
+        .with_settings(Settings::creative());
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+
+    assert_eq!(json["generationConfig"]["temperature"], 1);
+    assert_eq!(json["generationConfig"]["topP"], 0.95f32 as f64);
+}
+
+fn generation_config_with_defaults(
+    defaults: Option<crate::client::ModelDefaults>,
+    settings: Option<Settings>,
+) -> serde_json::Value {
+    let m = GeminiApiModel {
+        defaults,
+        ..make_direct_dummy(GeminiModel::Gemini25Flash)
+    };
+    let mut builder = m.new_request();
+    builder.with_message(Message::user("hi".to_string()));
+    if let Some(settings) = settings {
+        builder.with_settings(settings);
+    }
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+    json["generationConfig"].clone()
+}
+
+#[test]
+fn request_settings_override_model_defaults() {
+    let defaults = crate::client::ModelDefaults {
+        temperature: Some(1),
+        max_tokens: Some(500),
+        ..Default::default()
+    };
+    let settings = Settings {
+        temperature: Some(0),
+        ..Default::default()
+    };
+    let config = generation_config_with_defaults(Some(defaults), Some(settings));
+
+    assert_eq!(config["temperature"], 0);
+    // Fields the request leaves unset still come from the model.
+    assert_eq!(config["maxOutputTokens"], 500);
+}
+
+#[test]
+fn model_defaults_apply_when_request_has_no_settings() {
+    let defaults = crate::client::ModelDefaults {
+        temperature: Some(1),
+        top_p: Some(0.5),
+        ..Default::default()
+    };
+    let config = generation_config_with_defaults(Some(defaults), None);
+
+    assert_eq!(config["temperature"], 1);
+    assert_eq!(config["topP"], 0.5);
+}
+
+#[test]
+fn crate_defaults_send_temperature_zero_and_leave_the_rest_to_the_provider() {
+    let config = generation_config_with_defaults(None, None);
+
+    assert_eq!(config["temperature"], 0);
+    assert!(config.get("topP").is_none());
+    assert!(config.get("maxOutputTokens").is_none());
+}
+
+#[tokio::test]
+async fn model_defaults_apply_to_direct_model_calls() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![MockResponse::json(200, HELLO_RESPONSE)]).await;
+    let m = GeminiVertexModel::new("dummy-project", GeminiModel::Gemini25Flash)
+        .with_endpoint_override(&server.url)
+        .with_token_provider(Arc::new(StaticToken))
+        .with_defaults(crate::client::ModelDefaults {
+            temperature: Some(1),
+            ..Default::default()
+        });
+    let request = GeminiVertexModel::new("dummy-project", GeminiModel::Gemini25Flash)
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .to_model_request();
+
+    m.completion(request).await.unwrap();
+
+    let sent: serde_json::Value = serde_json::from_slice(&server.requests()[0].body).unwrap();
+    assert_eq!(sent["generationConfig"]["temperature"], 1);
+}
+
+#[test]
+fn logprobs_omitted_from_generation_config_by_default() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let json = serde_json::to_value(m.create_request_body(request_with_thinking(None))).unwrap();
+    assert!(json["generationConfig"].get("responseLogprobs").is_none());
+    assert!(json["generationConfig"].get("logprobs").is_none());
+}
+
+#[tokio::test]
+async fn tools_with_response_schema_are_rejected_before_sending() {
+    use crate::client::{LangrustError, Tool};
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let err = m
+        .new_request()
+        .with_message(Message::user("How do I feel?".to_string()))
+        .with_tool(Tool::new("lookup", "Look something up"))
+        .with_enum_response(vec!["happy".to_string(), "sad".to_string()])
+        .completion()
+        .await
+        .expect_err("Gemini refuses tools with a constrained response");
+
+    match err.downcast_ref::<LangrustError>() {
+        Some(LangrustError::InvalidRequest(msg)) => {
+            assert!(
+                msg.contains("response schema"),
+                "unexpected message: {}",
+                msg
+            )
+        }
+        other => panic!("expected InvalidRequest, got {:?}", other),
+    }
+}
+
+#[test]
+fn response_schema_sets_json_mime_type_and_gemini_schema() {
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct Sentiment {
+        label: String,
+        score: Option<f32>,
+    }
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let request = m
+        .new_request()
+        .with_message(Message::user("I love it".to_string()))
+        .with_response_schema::<Sentiment>()
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+
+    let gen_cfg = json.get("generationConfig").unwrap();
+    assert_eq!(gen_cfg.get("responseMimeType").unwrap(), "application/json");
+    let schema = gen_cfg.get("responseSchema").unwrap();
+    assert_eq!(schema.get("type").unwrap(), "OBJECT");
+    assert!(schema.get("$schema").is_none());
+    let props = schema.get("properties").unwrap();
+    assert_eq!(props["label"]["type"], "STRING");
+    assert_eq!(props["score"]["type"], "NUMBER");
+    assert_eq!(props["score"]["nullable"], true);
+}
+
+#[tokio::test]
+async fn missing_usage_metadata_leaves_usage_unknown() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![
+        MockResponse::json(
+            200,
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"hi"}]},"finishReason":"STOP","index":0}]}"#,
+        ),
+        MockResponse::json(
+            200,
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":""}]},"finishReason":"STOP","index":0}],"usageMetadata":{"promptTokenCount":4,"totalTokenCount":4}}"#,
+        ),
+    ])
+    .await;
+    let m = MockGeminiModel::new(&server);
+    let mut builder = m.new_request();
+    builder.with_message(Message::user("hello".to_string()));
+
+    let without_usage = builder.completion().await.unwrap();
+    assert_eq!(without_usage.usage, None);
+
+    let with_usage = builder.completion().await.unwrap();
+    assert_eq!(
+        with_usage.usage,
+        Some(Usage {
+            prompt_tokens: 4,
+            completion_tokens: 0,
+            total_tokens: 4,
+        })
+    );
+}
+
+#[tokio::test]
+async fn before_send_hook_mutation_reaches_the_wire() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"hi"}]},"finishReason":"STOP","index":0}]}"#,
+    )])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    m.new_request()
+        .with_message(Message::user("hello".to_string()))
+        .with_before_send(|body| {
+            body["generationConfig"]["candidateCount"] = serde_json::json!(1);
+            body["labels"] = serde_json::json!({ "team": "search" });
+        })
+        .completion()
+        .await
+        .expect("completion should succeed");
+
+    let requests = server.requests();
+    let sent: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(sent["generationConfig"]["candidateCount"], 1);
+    assert_eq!(sent["labels"]["team"], "search");
+    assert_eq!(sent["contents"][0]["parts"][0]["text"], "hello");
+    assert_eq!(requests[0].header("content-type"), Some("application/json"));
+}
+
+#[tokio::test]
+async fn stream_reports_usage_only_on_final_chunk() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::sse(&[
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Hel"}]},"index":0}],"usageMetadata":{"promptTokenCount":4,"candidatesTokenCount":1,"totalTokenCount":5}}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"lo"}]},"index":0}],"usageMetadata":{"promptTokenCount":4,"candidatesTokenCount":2,"totalTokenCount":6}}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"!"}]},"finishReason":"STOP","index":0}],"usageMetadata":{"promptTokenCount":4,"candidatesTokenCount":3,"totalTokenCount":7}}"#,
+    ])])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let events: Vec<StreamEvent> = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .stream()
+        .await
+        .expect("stream should open")
+        .collect()
+        .await;
+
+    let usage_positions: Vec<usize> = events
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.usage().is_some())
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(usage_positions, vec![events.len() - 1]);
+
+    let last = events.last().unwrap();
+    assert!(last.is_final());
+    let usage = last.usage().unwrap();
+    assert_eq!(usage.completion_tokens, 3);
+    assert_eq!(usage.total_tokens, 7);
+    assert!(events[..events.len() - 1].iter().all(|e| !e.is_final()));
+}
+
+#[tokio::test]
+async fn stream_ignores_heartbeat_comments_and_blank_lines() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let body = concat!(
+        ": keepalive\n\n",
+        "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"Hel\"}]},\"index\":0}]}\n\n",
+        ": keepalive\n",
+        ":\n\n\n",
+        "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"lo\"}]},\"finishReason\":\"STOP\",\"index\":0}],\"usageMetadata\":{\"promptTokenCount\":4,\"candidatesTokenCount\":2,\"totalTokenCount\":6}}\n",
+        ": heartbeat between the payload and its terminating blank line\n\n",
+        ": keepalive\n\n",
+    );
+    let server = MockServer::start(vec![MockResponse::sse_raw(body)]).await;
+
+    let m = MockGeminiModel::new(&server);
+    let events: Vec<StreamEvent> = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .stream()
+        .await
+        .expect("stream should open")
+        .collect()
+        .await;
+
+    assert_eq!(events.len(), 3, "events: {:?}", events);
+    assert!(matches!(&events[0], StreamEvent::Delta(t) if t == "Hel"));
+    assert!(matches!(&events[1], StreamEvent::Delta(t) if t == "lo"));
+    assert!(matches!(&events[2], StreamEvent::Usage(u) if u.total_tokens == 6));
+}
+
+#[tokio::test]
+async fn stream_keeps_multi_byte_character_split_across_reads() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let body = "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"café 😀\"}]},\"finishReason\":\"STOP\",\"index\":0}]}\n\n";
+    // Split inside the emoji's four-byte encoding, so the first read ends
+    // mid-character.
+    let split = body.find('😀').unwrap() + 2;
+    let server = MockServer::start(vec![MockResponse::sse_raw(body).split_at(split)]).await;
+
+    let m = MockGeminiModel::new(&server);
+    let events: Vec<StreamEvent> = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .stream()
+        .await
+        .expect("stream should open")
+        .collect()
+        .await;
+
+    assert!(
+        matches!(&events[0], StreamEvent::Delta(t) if t == "café 😀"),
+        "events: {:?}",
+        events
+    );
+}
+
+#[tokio::test]
+async fn stream_cut_off_for_safety_ends_with_filtered_event() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::sse(&[
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Step one: "}]},"index":0}]}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"mix the"}]},"index":0}]}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[]},"finishReason":"SAFETY","index":0,"safetyRatings":[{"category":"HARM_CATEGORY_DANGEROUS_CONTENT","probability":"HIGH"}]}],"usageMetadata":{"promptTokenCount":4,"candidatesTokenCount":5,"totalTokenCount":9}}"#,
+    ])])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let events: Vec<StreamEvent> = m
+        .new_request()
+        .with_message(Message::user("how do I make ...".to_string()))
+        .stream()
+        .await
+        .expect("stream should open")
+        .collect()
+        .await;
+
+    assert_eq!(events.len(), 4);
+    assert!(matches!(&events[0], StreamEvent::Delta(t) if t == "Step one: "));
+    assert!(matches!(&events[1], StreamEvent::Delta(t) if t == "mix the"));
+    assert!(matches!(&events[2], StreamEvent::Usage(u) if u.total_tokens == 9));
+    match &events[3] {
+        StreamEvent::Filtered {
+            finish_reason,
+            partial_text,
+        } => {
+            assert_eq!(finish_reason, "SAFETY");
+            assert_eq!(partial_text, "Step one: mix the");
+        }
+        other => panic!("expected a Filtered event, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn dropping_a_stream_early_closes_its_connection() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![
+        MockResponse::sse(&[
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Once upon"}]},"index":0}]}"#,
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":" a time"}]},"index":0}]}"#,
+        ])
+        .held_open(),
+    ])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let mut stream = m
+        .new_request()
+        .with_message(Message::user("Tell me a story".to_string()))
+        .stream()
+        .await
+        .unwrap();
+    assert!(matches!(stream.next().await, Some(StreamEvent::Delta(_))));
+    assert_eq!(server.open_connections(), 1);
+
+    drop(stream);
+
+    // The server sees the close asynchronously.
+    for _ in 0..100 {
+        if server.open_connections() == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.open_connections(), 0);
+}
+
+#[tokio::test]
+async fn stream_without_first_event_in_time_fails_with_first_token_timeout() {
+    use crate::client::LangrustError;
+    use crate::test_server::{MockResponse, MockServer};
+    use std::time::Duration;
+
+    let server = MockServer::start(vec![
+        MockResponse::sse(&[
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Hi!"}]},"finishReason":"STOP","index":0}]}"#,
+        ])
+        .with_delay(Duration::from_millis(500)),
+    ])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let result = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .with_first_token_timeout(Duration::from_millis(100))
+        .stream()
+        .await;
+
+    let error = result.err().expect("stream should time out");
+    assert_eq!(
+        error.downcast_ref::<LangrustError>(),
+        Some(&LangrustError::FirstTokenTimeout {
+            timeout: Duration::from_millis(100)
+        })
+    );
+}
+
+#[tokio::test]
+async fn stream_past_max_duration_ends_with_timeout_error() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::time::Duration;
+
+    let server = MockServer::start(vec![
+        MockResponse::sse(&[
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Once upon"}]},"index":0}]}"#,
+        ])
+        .held_open(),
+    ])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let events: Vec<StreamEvent> = m
+        .new_request()
+        .with_message(Message::user("Tell me a story".to_string()))
+        .with_first_token_timeout(Duration::from_secs(5))
+        .with_max_stream_duration(Duration::from_millis(200))
+        .stream()
+        .await
+        .expect("the first event arrives in time")
+        .collect()
+        .await;
+
+    assert_eq!(events.len(), 2, "events: {:?}", events);
+    assert!(matches!(&events[0], StreamEvent::Delta(t) if t == "Once upon"));
+    assert!(
+        matches!(&events[1], StreamEvent::Error(e) if e.contains("still running after 200ms")),
+        "events: {:?}",
+        events
+    );
+}
+
+#[tokio::test]
+async fn stream_stops_at_sentinel_split_across_deltas() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::sse(&[
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Result: 4"}]},"index":0}]}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"2<EN"}]},"index":0}]}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"D> and then more"}]},"index":0}]}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":" text"}]},"finishReason":"STOP","index":0}],"usageMetadata":{"promptTokenCount":4,"candidatesTokenCount":9,"totalTokenCount":13}}"#,
+    ])])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let events: Vec<StreamEvent> = m
+        .new_request()
+        .with_message(Message::user("compute".to_string()))
+        .with_stop_on("<END>")
+        .stream()
+        .await
+        .expect("stream should open")
+        .collect()
+        .await;
+
+    assert_eq!(
+        events,
+        vec![
+            StreamEvent::Delta("Result: 4".to_string()),
+            StreamEvent::Delta("2".to_string()),
+            StreamEvent::Stopped("<END>".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn aborting_a_stream_handle_ends_it_with_cancelled() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::time::Duration;
+
+    let server = MockServer::start(vec![
+        MockResponse::sse(&[
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Once upon"}]},"index":0}]}"#,
+        ])
+        .held_open(),
+    ])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let (stream, handle) = m
+        .new_request()
+        .with_message(Message::user("Tell me a story".to_string()))
+        .stream_with_handle()
+        .await
+        .expect("stream should open");
+    let consumer = tokio::spawn(stream.collect::<Vec<StreamEvent>>());
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    handle.abort();
+    let events = tokio::time::timeout(Duration::from_secs(5), consumer)
+        .await
+        .expect("the aborted stream should end")
+        .unwrap();
+
+    assert!(handle.is_aborted());
+    assert_eq!(
+        events,
+        vec![
+            StreamEvent::Delta("Once upon".to_string()),
+            StreamEvent::Cancelled,
+        ]
+    );
+    for _ in 0..100 {
+        if server.open_connections() == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.open_connections(), 0);
+}
+
+#[tokio::test]
+async fn dropping_a_stream_handle_does_not_cancel_the_stream() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::sse(&[
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Hi!"}]},"finishReason":"STOP","index":0}],"usageMetadata":{"promptTokenCount":1,"candidatesTokenCount":1,"totalTokenCount":2}}"#,
+    ])])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let (stream, handle) = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .stream_with_handle()
+        .await
+        .expect("stream should open");
+    drop(handle);
+    let events: Vec<StreamEvent> = stream.collect().await;
+
+    assert_eq!(events.len(), 2, "events: {:?}", events);
+    assert!(matches!(&events[0], StreamEvent::Delta(t) if t == "Hi!"));
+    assert!(events[1].is_final());
+}
+
+const TEXT_AND_CALLS_RESPONSE: &str = r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Let me check both cities."},{"functionCall":{"name":"get_weather","args":{"city":"Paris"}}},{"functionCall":{"name":"get_weather","args":{"city":"Rome"}}}]},"finishReason":"STOP","index":0}]}"#;
+
+fn assert_text_and_calls(completion: &crate::client::Completion) {
+    assert_eq!(completion.completion, "Let me check both cities.");
+    let function = completion
+        .function
+        .as_ref()
+        .expect("function should be set");
+    assert_eq!(function.args["city"], "Paris");
+    let cities: Vec<serde_json::Value> = completion
+        .tool_calls()
+        .iter()
+        .map(|call| call.args["city"].clone())
+        .collect();
+    assert_eq!(cities, vec!["Paris", "Rome"]);
+}
+
+#[tokio::test]
+async fn text_and_function_calls_are_both_returned_by_each_client() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![
+        MockResponse::json(200, TEXT_AND_CALLS_RESPONSE),
+        MockResponse::json(200, TEXT_AND_CALLS_RESPONSE),
+    ])
+    .await;
+
+    let direct = MockGeminiModel::new(&server);
+    let completion = direct
+        .new_request()
+        .with_message(Message::user("Weather in Paris and Rome?".to_string()))
+        .completion()
+        .await
+        .unwrap();
+    assert_text_and_calls(&completion);
+
+    let vertex = GeminiVertexModel {
+        token_provider: Some(Arc::new(StaticToken)),
+        ..vertex_dummy(Some(&server.url))
+    };
+    let completion = vertex
+        .new_request()
+        .with_message(Message::user("Weather in Paris and Rome?".to_string()))
+        .completion()
+        .await
+        .unwrap();
+    assert_text_and_calls(&completion);
+}
+
+#[tokio::test]
+async fn vertex_completion_returns_function_call() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    // As Vertex sends it: `createTime`, `avgLogprobs` and the thought
+    // signature next to the call are extra to the direct API's shape.
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"functionCall":{"name":"get_weather","args":{"city":"Paris"}},"thoughtSignature":"c2ln"}]},"finishReason":"STOP","avgLogprobs":-0.12}],"usageMetadata":{"promptTokenCount":20,"candidatesTokenCount":5,"totalTokenCount":25,"trafficType":"ON_DEMAND"},"modelVersion":"gemini-2.5-flash","createTime":"2026-01-01T00:00:00.000000Z","responseId":"vertex-1"}"#,
+    )])
+    .await;
+    let m = GeminiVertexModel {
+        token_provider: Some(Arc::new(StaticToken)),
+        ..vertex_dummy(Some(&server.url))
+    };
+
+    let completion = m
+        .new_request()
+        .with_message(Message::user("Weather in Paris?".to_string()))
+        .with_tool(Tool::new("get_weather", "Get the weather"))
+        .completion()
+        .await
+        .unwrap();
+
+    let function = completion.function.expect("Vertex should return the call");
+    assert_eq!(function.name, "get_weather");
+    assert_eq!(function.args["city"], "Paris");
+    assert!(completion.completion.is_empty());
+    assert_eq!(completion.response_id.as_deref(), Some("vertex-1"));
+}
+
+#[tokio::test]
+async fn stream_emits_every_function_call_of_a_chunk() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::sse(&[TEXT_AND_CALLS_RESPONSE])]).await;
+
+    let m = MockGeminiModel::new(&server);
+    let events: Vec<StreamEvent> = m
+        .new_request()
+        .with_message(Message::user("Weather in Paris and Rome?".to_string()))
+        .stream()
+        .await
+        .expect("stream should open")
+        .collect()
+        .await;
+
+    let calls: Vec<serde_json::Value> = events
+        .iter()
+        .filter_map(|event| match event {
+            StreamEvent::FunctionCall(call) => Some(call.args["city"].clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(calls, vec!["Paris", "Rome"]);
+    assert!(matches!(&events[0], StreamEvent::Delta(t) if t == "Let me check both cities."));
+}
+
+#[derive(JsonSchema, Deserialize)]
+#[allow(dead_code)]
+struct WeatherArgs {
+    /// City to get the weather for
+    city: String,
+}
+
+fn localized_declarations(locale: Option<&str>) -> serde_json::Value {
+    let tool = Tool::new("get_weather", "Get the weather")
+        .with_parameter::<WeatherArgs>()
+        .unwrap()
+        .with_translation("fr", "Obtenir la météo")
+        .with_parameter_translation("fr", "city", "Ville dont on veut la météo");
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let mut builder = m.new_request();
+    builder
+        .with_message(Message::user("hi".to_string()))
+        .with_tool(tool);
+    if let Some(locale) = locale {
+        builder.with_locale(locale);
+    }
+    let json = serde_json::to_value(m.create_request_body(builder.to_model_request())).unwrap();
+    json["tools"][0]["functionDeclarations"][0].clone()
+}
+
+#[test]
+fn tool_descriptions_follow_request_locale() {
+    let french = localized_declarations(Some("fr"));
+    assert_eq!(french["description"], "Obtenir la météo");
+    assert_eq!(
+        french["parameters"]["properties"]["city"]["description"],
+        "Ville dont on veut la météo"
+    );
+    assert!(!french.to_string().contains("Get the weather"));
+    assert!(!french.to_string().contains("translations"));
+
+    assert_eq!(localized_declarations(Some("fr-CA")), french);
+    for locale in [None, Some("de")] {
+        let english = localized_declarations(locale);
+        assert_eq!(english["description"], "Get the weather");
+        assert_eq!(
+            english["parameters"]["properties"]["city"]["description"],
+            "City to get the weather for"
+        );
+    }
+}
+
+fn tool_config_for(mode: crate::client::FunctionCallingMode) -> serde_json::Value {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let request = m
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .with_tool(Tool::new("get_weather", "Get the weather"))
+        .with_function_calling_mode(mode)
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+    json.get("toolConfig").cloned().unwrap()
+}
+
+#[test]
+fn function_calling_mode_maps_to_gemini_tool_config() {
+    use crate::client::FunctionCallingMode;
+
+    assert_eq!(
+        tool_config_for(FunctionCallingMode::Auto),
+        serde_json::json!({ "functionCallingConfig": { "mode": "AUTO" } })
+    );
+    assert_eq!(
+        tool_config_for(FunctionCallingMode::Any),
+        serde_json::json!({ "functionCallingConfig": { "mode": "ANY" } })
+    );
+    assert_eq!(
+        tool_config_for(FunctionCallingMode::None),
+        serde_json::json!({ "functionCallingConfig": { "mode": "NONE" } })
+    );
+    assert_eq!(
+        tool_config_for(FunctionCallingMode::Function("get_weather".to_string())),
+        serde_json::json!({
+            "functionCallingConfig": {
+                "mode": "ANY",
+                "allowedFunctionNames": ["get_weather"]
+            }
+        })
+    );
+}
+
+#[test]
+fn tools_and_tool_config_are_top_level_siblings_in_any_builder_order() {
+    use crate::client::FunctionCallingMode;
+
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let request = m
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .with_function_calling_mode(FunctionCallingMode::Any)
+        .with_tool(Tool::new("get_weather", "Get the weather"))
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+
+    let tools = json["tools"].as_array().expect("tools should be a list");
+    assert_eq!(tools.len(), 1);
+    assert_eq!(tools[0]["functionDeclarations"][0]["name"], "get_weather");
+    assert!(tools[0].get("toolConfig").is_none());
+    assert_eq!(
+        json["toolConfig"],
+        serde_json::json!({ "functionCallingConfig": { "mode": "ANY" } })
+    );
+    assert!(json["generationConfig"].get("toolConfig").is_none());
+}
+
+#[test]
+fn tool_config_omitted_without_function_calling_mode() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let request = m
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .with_tool(Tool::new("get_weather", "Get the weather"))
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+    assert!(json.get("toolConfig").is_none());
+}
+
+#[test]
+fn inline_data_serializes_as_base64_part_after_text() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let request = m
+        .new_request()
+        .with_message(
+            Message::user("What is in this image?".to_string())
+                .with_inline_data("image/png", vec![0x89, b'P', b'N', b'G']),
+        )
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+
+    let parts = &json["contents"][0]["parts"];
+    assert_eq!(parts[0]["text"], "What is in this image?");
+    assert_eq!(parts[1]["inlineData"]["mimeType"], "image/png");
+    assert_eq!(parts[1]["inlineData"]["data"], "iVBORw==");
+}
+
+#[test]
+fn interleaved_text_and_image_stay_in_order_in_one_content() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let request = m
+        .new_request()
+        .with_message(
+            Message::user("Here's an image:".to_string())
+                .with_inline_data("image/png", vec![0x89, b'P', b'N', b'G'])
+                .with_text("What's in it?"),
+        )
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+
+    let contents = json["contents"].as_array().unwrap();
+    assert_eq!(contents.len(), 1);
+    assert_eq!(
+        contents[0]["parts"],
+        serde_json::json!([
+            { "text": "Here's an image:" },
+            { "inlineData": { "mimeType": "image/png", "data": "iVBORw==" } },
+            { "text": "What's in it?" }
+        ])
+    );
+}
+
+#[test]
+fn file_uri_serializes_as_file_data_part() {
+    let m = make_direct_dummy(GeminiModel::Gemini25Flash);
+    let request = m
+        .new_request()
+        .with_message(
+            Message::user("Summarize this report.".to_string())
+                .with_file_uri("gs://my-bucket/reports/q3.pdf", "application/pdf"),
+        )
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request)).unwrap();
+
+    let parts = &json["contents"][0]["parts"];
+    assert_eq!(parts[0]["text"], "Summarize this report.");
+    assert_eq!(
+        parts[1],
+        serde_json::json!({
+            "fileData": {
+                "mimeType": "application/pdf",
+                "fileUri": "gs://my-bucket/reports/q3.pdf"
+            }
+        })
+    );
+}
+
+#[tokio::test]
+async fn oversized_inline_data_is_rejected_before_sending() {
+    use crate::client::LangrustError;
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(200, "{}")]).await;
+    let m = MockGeminiModel::new(&server);
+    let image = vec![0u8; 16 * 1024 * 1024];
+    let err = m
+        .new_request()
+        .with_message(Message::user("describe".to_string()).with_inline_data("image/png", image))
+        .completion()
+        .await
+        .expect_err("21MB of base64 should exceed the inline data limit");
+
+    match err.downcast_ref::<LangrustError>() {
+        Some(LangrustError::RequestTooLarge { bytes, limit }) => {
+            assert_eq!(*bytes, 16 * 1024 * 1024 / 3 * 4 + 4);
+            assert_eq!(*limit, 20 * 1024 * 1024);
+        }
+        other => panic!("expected RequestTooLarge, got {:?}", other),
+    }
+    assert!(err.to_string().contains("File API"));
+    assert!(server.requests().is_empty());
+}
+
+const THREE_CANDIDATES: &str = r#"{
+    "candidates": [
+        { "content": { "role": "model", "parts": [{ "text": "Bonjour" }] }, "index": 0 },
+        {
+            "content": {
+                "role": "model",
+                "parts": [{ "functionCall": { "name": "translate", "args": { "to": "fr" } } }]
+            },
+            "index": 1
+        },
+        { "content": { "role": "model", "parts": [{ "text": "Salut" }] }, "index": 2 }
+    ],
+    "usageMetadata": { "promptTokenCount": 4, "candidatesTokenCount": 6, "totalTokenCount": 10 }
+}"#;
+
+#[test]
+fn candidate_accessors_are_bounds_checked() {
+    use crate::gemini::types::GeminiResponse;
+
+    let resp: GeminiResponse = serde_json::from_str(THREE_CANDIDATES).unwrap();
+    assert_eq!(resp.get_text_at(0).as_deref(), Some("Bonjour"));
+    assert_eq!(resp.get_text_at(1).as_deref(), Some(""));
+    assert_eq!(resp.get_text_at(2).as_deref(), Some("Salut"));
+    assert!(resp.get_text_at(3).is_none());
+    assert!(resp.get_function_at(0).is_none());
+    assert_eq!(resp.get_function_at(1).unwrap().name, "translate");
+    assert!(resp.get_function_at(3).is_none());
+}
+
+#[tokio::test]
+async fn completion_n_returns_one_completion_per_candidate() {
+    use crate::test_server::{MockResponse, MockServer};
+
+    let server = MockServer::start(vec![MockResponse::json(200, THREE_CANDIDATES)]).await;
+
+    let m = MockGeminiModel::new(&server);
+    let completions = m
+        .new_request()
+        .with_message(Message::user("say hello in French".to_string()))
+        .completion_n(3)
+        .await
+        .expect("completion_n should succeed");
+
+    let texts: Vec<&str> = completions.iter().map(|c| c.completion.as_str()).collect();
+    assert_eq!(texts, vec!["Bonjour", "", "Salut"]);
+    assert!(completions[0].function.is_none());
+    assert_eq!(completions[1].function.as_ref().unwrap().name, "translate");
+    assert!(completions[2].function.is_none());
+
+    let requests = server.requests();
+    let sent: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(sent["generationConfig"]["candidateCount"], 3);
+}
+
+#[tokio::test]
+async fn completion_parts_keep_text_and_calls_in_order() {
+    use crate::client::CompletionPart;
+    use crate::test_server::{MockResponse, MockServer};
+
+    let body = r#"{
+        "candidates": [{
+            "content": {
+                "role": "model",
+                "parts": [
+                    { "text": "Let me check " },
+                    { "text": "the weather." },
+                    { "functionCall": { "name": "get_weather", "args": { "city": "Paris" } } },
+                    { "text": "Then I will answer." }
+                ]
+            },
+            "index": 0
+        }]
+    }"#;
+    let server = MockServer::start(vec![MockResponse::json(200, body)]).await;
+
+    let m = MockGeminiModel::new(&server);
+    let completion = m
+        .new_request()
+        .with_message(Message::user("Weather in Paris?".to_string()))
+        .completion()
+        .await
+        .unwrap();
+
+    let parts = completion.parts();
+    assert_eq!(parts.len(), 3);
+    assert_eq!(
+        parts[0],
+        CompletionPart::Text("Let me check the weather.".to_string())
+    );
+    assert!(matches!(&parts[1], CompletionPart::FunctionCall(call) if call.name == "get_weather"));
+    assert_eq!(
+        parts[2],
+        CompletionPart::Text("Then I will answer.".to_string())
+    );
+    assert_eq!(
+        completion.completion,
+        "Let me check the weather.Then I will answer."
+    );
+}
+
+const HELLO_RESPONSE: &str = r#"{
+    "candidates": [
+        { "content": { "role": "model", "parts": [{ "text": "Hi!" }] }, "index": 0 }
+    ]
+}"#;
+
+// Points `request` at `base_url`, keeping its path and query.
+fn redirect(request: &mut reqwest::Request, base_url: &str) {
+    let url = request.url();
+    let target = format!(
+        "{}{}?{}",
+        base_url,
+        url.path(),
+        url.query().unwrap_or_default()
+    );
+    *request.url_mut() = reqwest::Url::parse(&target).unwrap();
+}
+
+// Records the URL of every request and forwards it to a mock server.
+struct RedirectingTransport {
+    base_url: String,
+    seen: std::sync::Mutex<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+impl crate::client::HttpTransport for RedirectingTransport {
+    async fn execute(
+        &self,
+        mut request: reqwest::Request,
+    ) -> Result<reqwest::Response, Box<dyn std::error::Error + Send + Sync>> {
+        self.seen.lock().unwrap().push(request.url().to_string());
+        redirect(&mut request, &self.base_url);
+        Ok(reqwest::Client::new().execute(request).await?)
+    }
+}
+
+#[tokio::test]
+async fn requests_go_through_the_transport_when_set() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![MockResponse::json(200, HELLO_RESPONSE)]).await;
+    let transport = Arc::new(RedirectingTransport {
+        base_url: server.url.clone(),
+        seen: std::sync::Mutex::new(vec![]),
+    });
+    let m = GeminiApiModel {
+        transport: Some(transport.clone()),
+        ..make_direct_dummy(GeminiModel::Gemini25Flash)
+    };
+
+    let completion = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .expect("completion should succeed");
+
+    assert_eq!(completion.completion, "Hi!");
+    assert_eq!(
+        *transport.seen.lock().unwrap(),
+        vec![
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent"
+                .to_string()
+        ]
+    );
+    assert_eq!(server.requests()[0].header("x-goog-api-key"), Some("dummy"));
+}
+
+#[cfg(feature = "middleware")]
+#[tokio::test]
+async fn requests_go_through_a_middleware_stack() {
+    use crate::test_server::{MockResponse, MockServer};
+    use reqwest_middleware::{ClientBuilder, Middleware, Next};
+    use std::sync::Arc;
+
+    struct Redirect {
+        base_url: String,
+        seen: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for Redirect {
+        async fn handle(
+            &self,
+            mut req: reqwest::Request,
+            extensions: &mut http::Extensions,
+            next: Next<'_>,
+        ) -> reqwest_middleware::Result<reqwest::Response> {
+            self.seen.lock().unwrap().push(req.url().path().to_string());
+            redirect(&mut req, &self.base_url);
+            next.run(req, extensions).await
+        }
+    }
+
+    let server = MockServer::start(vec![MockResponse::json(200, HELLO_RESPONSE)]).await;
+    let seen = Arc::new(std::sync::Mutex::new(vec![]));
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(Redirect {
+            base_url: server.url.clone(),
+            seen: seen.clone(),
+        })
+        .build();
+    let m = GeminiApiModel {
+        transport: Some(Arc::new(client)),
+        ..make_direct_dummy(GeminiModel::Gemini25Flash)
+    };
+
+    let completion = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .expect("completion should succeed");
+
+    assert_eq!(completion.completion, "Hi!");
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec!["/v1beta/models/gemini-2.5-flash:generateContent".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn identical_deterministic_request_is_served_from_cache() {
+    use crate::client::CompletionCache;
+    use crate::test_server::{MockResponse, MockServer};
+    use std::{sync::Arc, time::Duration};
+
+    let server = MockServer::start(vec![MockResponse::json(200, HELLO_RESPONSE)]).await;
+    let cache = Arc::new(CompletionCache::new(16, Duration::from_secs(60)).unwrap());
+    let m = GeminiVertexModel {
+        token_provider: Some(Arc::new(StaticToken)),
+        cache: Some(cache.clone()),
+        ..vertex_dummy(Some(&server.url))
+    };
+    let ask = |temperature: i16| {
+        let mut builder = m.new_request();
+        builder
+            .with_message(Message::user("hello".to_string()))
+            .with_settings(Settings {
+                temperature: Some(temperature),
+                ..Default::default()
+            });
+        builder
+    };
+
+    let first = ask(0)
+        .completion()
+        .await
+        .expect("completion should succeed");
+    let second = ask(0)
+        .completion()
+        .await
+        .expect("completion should succeed");
+    assert_eq!(first.completion, "Hi!");
+    assert_eq!(second.completion, "Hi!");
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(cache.len(), 1);
+
+    // A non-deterministic request always goes to the API.
+    ask(1)
+        .completion()
+        .await
+        .expect("completion should succeed");
+    ask(1)
+        .completion()
+        .await
+        .expect("completion should succeed");
+    assert_eq!(server.requests().len(), 3);
+    assert_eq!(cache.len(), 1);
+}
+
+#[tokio::test]
+async fn response_over_max_response_bytes_fails() {
+    use crate::client::LangrustError;
+    use crate::test_server::{MockResponse, MockServer};
+
+    let padding = "x".repeat(64 * 1024);
+    let huge = format!(
+        r#"{{"candidates":[{{"content":{{"role":"model","parts":[{{"text":"{}"}}]}},"index":0}}]}}"#,
+        padding
+    );
+    // The second body never ends, so only the limit can stop the read.
+    let server = MockServer::start(vec![
+        MockResponse::json(200, &huge),
+        MockResponse::json(200, &huge).held_open(),
+        MockResponse::json(200, HELLO_RESPONSE),
+    ])
+    .await;
+    let m = MockGeminiModel::new(&server);
+    let ask = || async {
+        m.new_request()
+            .with_message(Message::user("hello".to_string()))
+            .with_max_response_bytes(16 * 1024)
+            .completion()
+            .await
+    };
+
+    for _ in 0..2 {
+        let err = tokio::time::timeout(std::time::Duration::from_secs(5), ask())
+            .await
+            .expect("the read should stop at the limit")
+            .expect_err("an over-limit body should fail");
+        assert_eq!(
+            err.downcast_ref::<LangrustError>(),
+            Some(&LangrustError::ResponseTooLarge { limit: 16 * 1024 })
+        );
+    }
+
+    let completion = ask().await.expect("a small body is read as usual");
+    assert_eq!(completion.completion, "Hi!");
+}
+
+#[tokio::test]
+async fn error_body_is_cut_off_at_max_response_bytes() {
+    use crate::client::LangrustError;
+    use crate::test_server::{MockResponse, MockServer};
+
+    let page = format!("<html><body>{}</body></html>", "x".repeat(10_000));
+    let server = MockServer::start(vec![MockResponse {
+        status: 502,
+        content_type: "text/html",
+        body: page.into_bytes(),
+        delay: None,
+        hold_open: false,
+        split_at: None,
+    }])
+    .await;
+
+    let m = MockGeminiModel::new(&server);
+    let err = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .with_max_response_bytes(100)
+        .completion()
+        .await
+        .expect_err("a 502 should fail");
+
+    let Some(LangrustError::ApiError { status, body, .. }) = err.downcast_ref::<LangrustError>()
+    else {
+        panic!("expected ApiError, got {}", err);
+    };
+    assert_eq!(*status, 502);
+    assert_eq!(body.len(), 100);
+    assert!(body.starts_with(b"<html><body>xxx"));
+}
+
+#[test]
+fn pricing_table_estimates_request_cost() {
+    use crate::gemini::PricingTable;
+
+    let m = make_direct_dummy(GeminiModel::Gemini31Pro);
+    let request = m
+        .new_request()
+        .with_system("x".repeat(400))
+        .with_message(Message::user("y".repeat(3_600)))
+        .to_model_request();
+
+    let mut pricing = PricingTable::default();
+    pricing.set_input_price(GeminiModel::Gemini31Pro, 2.5);
+    let estimate = pricing.estimate(&request, &GeminiModel::Gemini31Pro);
+    assert_eq!(estimate.input_tokens, 1_000);
+    assert!((estimate.estimated_input_cost - 0.0025).abs() < 1e-12);
+
+    let default = PricingTable::default().estimate(&request, &GeminiModel::Gemini31Pro);
+    assert_eq!(default.input_tokens, 1_000);
+    assert!((default.estimated_input_cost - 0.002).abs() < 1e-12);
+}
+
+#[tokio::test]
+async fn model_built_with_timeouts_fails_fast_on_slow_connect() {
+    use crate::client::HttpTimeouts;
+    use crate::test_server::StalledServer;
+    use std::sync::Arc;
+
+    let server = StalledServer::start().await;
+    let m = GeminiVertexModel::new("dummy-project", GeminiModel::Gemini25Flash)
+        .with_endpoint_override(&server.url)
+        .with_token_provider(Arc::new(StaticToken))
+        .with_timeouts(HttpTimeouts {
+            connect_timeout: Some(std::time::Duration::from_millis(200)),
+            read_timeout: None,
+        })
+        .unwrap();
+
+    let started = std::time::Instant::now();
+    let err = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .expect_err("the connection never completes");
+
+    let err = err
+        .downcast_ref::<reqwest::Error>()
+        .expect("a transport error");
+    assert!(err.is_connect() && err.is_timeout(), "{:?}", err);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn model_built_with_http_version_keeps_earlier_client_options() {
+    use crate::client::{HttpTimeouts, HttpVersionPref};
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![MockResponse::json(200, HELLO_RESPONSE)]).await;
+    let m = GeminiVertexModel::new("dummy-project", GeminiModel::Gemini25Flash)
+        .with_endpoint_override(&server.url)
+        .with_token_provider(Arc::new(StaticToken))
+        .with_timeouts(HttpTimeouts {
+            connect_timeout: Some(std::time::Duration::from_secs(5)),
+            read_timeout: None,
+        })
+        .unwrap()
+        .with_http_version(HttpVersionPref::Http1Only)
+        .unwrap();
+
+    m.new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        m.client_options.timeouts.connect_timeout,
+        Some(std::time::Duration::from_secs(5))
+    );
+    assert_eq!(server.requests()[0].version, "HTTP/1.1");
+
+    // Prior knowledge sends the HTTP/2 preface, which the mock server rejects.
+    let m = GeminiVertexModel::new("dummy-project", GeminiModel::Gemini25Flash)
+        .with_endpoint_override(&server.url)
+        .with_token_provider(Arc::new(StaticToken))
+        .with_http_version(HttpVersionPref::Http2Prior)
+        .unwrap();
+    assert!(
+        m.new_request()
+            .with_message(Message::user("hello".to_string()))
+            .completion()
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn model_built_with_proxy_sends_requests_through_it() {
+    use crate::client::ProxyConfig;
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let proxy = MockServer::start(vec![MockResponse::json(200, HELLO_RESPONSE)]).await;
+    let m = GeminiVertexModel::new("dummy-project", GeminiModel::Gemini25Flash)
+        .with_endpoint_override("http://vertex.example.invalid")
+        .with_token_provider(Arc::new(StaticToken))
+        .with_proxy(ProxyConfig {
+            http_proxy: Some(proxy.url.clone()),
+            ..ProxyConfig::default()
+        })
+        .unwrap();
+
+    let completion = m
+        .new_request()
+        .with_message(Message::user("hello".to_string()))
+        .completion()
+        .await
+        .expect("the proxy should answer");
+
+    assert_eq!(completion.completion, "Hi!");
+    let requests = proxy.requests();
+    assert!(
+        requests[0]
+            .path
+            .starts_with("http://vertex.example.invalid/v1/projects/dummy-project/"),
+        "{}",
+        requests[0].path
+    );
 }
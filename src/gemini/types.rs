@@ -1,10 +1,14 @@
 use std::collections::HashMap;
 
-use crate::client::{Role, Tool};
+use crate::client::{
+    CompletionPart, FunctionCall, FunctionCallingMode, HarmBlockThreshold, HarmCategory,
+    HarmProbability, LangrustError, MediaPart, Modality, Role, SafetyRating, SafetyRatings,
+    SafetySetting, TokenLogprob, TokenLogprobs, Tool, Usage,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum GeminiModel {
     Gemini25Flash,
     Gemini31Pro,
@@ -21,21 +25,103 @@ impl GeminiModel {
             GeminiModel::Gemini31FlashLite => "gemini-3.1-flash-lite-preview".to_string(),
         }
     }
+
+    /// Whether the model can be given tools to call.
+    pub fn supports_tools(&self) -> bool {
+        self.capabilities().tools
+    }
+
+    /// What the model accepts and can do, for choosing a model per request.
+    pub fn capabilities(&self) -> ModelCapabilities {
+        match self {
+            GeminiModel::Gemini25Flash
+            | GeminiModel::Gemini31Pro
+            | GeminiModel::Gemini3Flash
+            | GeminiModel::Gemini31FlashLite => ModelCapabilities {
+                context_window: 1_048_576,
+                output_limit: 65_536,
+                multimodal: true,
+                thinking: true,
+                tools: true,
+                json_mode: true,
+            },
+        }
+    }
+}
+
+/// What a model accepts and supports, from `GeminiModel::capabilities()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelCapabilities {
+    /// Input tokens the model accepts in one request.
+    pub context_window: u32,
+    /// Most tokens the model generates in one response, thinking included.
+    pub output_limit: u32,
+    /// Whether messages can carry images and other media.
+    pub multimodal: bool,
+    /// Whether `thinking_budget` applies.
+    pub thinking: bool,
+    /// Whether the model can be given tools to call.
+    pub tools: bool,
+    /// Whether `with_response_schema` and JSON output are supported.
+    pub json_mode: bool,
+}
+
+/// The method an endpoint URL ends with, after the model name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeminiMethod {
+    GenerateContent,
+    /// Streams over server-sent events (`alt=sse`).
+    StreamGenerateContent,
+    CountTokens,
+    EmbedContent,
+}
+
+impl GeminiMethod {
+    /// The `:method` suffix of the endpoint URL, with any query it needs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GeminiMethod::GenerateContent => "generateContent",
+            GeminiMethod::StreamGenerateContent => "streamGenerateContent?alt=sse",
+            GeminiMethod::CountTokens => "countTokens",
+            GeminiMethod::EmbedContent => "embedContent",
+        }
+    }
 }
 
 #[derive(Serialize)]
 pub struct ThinkingConfig {
     #[serde(rename = "thinkingBudget")]
     pub thinking_budget: i16,
+    /// Sent as `false` with a budget of `0`, so no thought summaries are
+    /// asked for either.
+    #[serde(rename = "includeThoughts", skip_serializing_if = "Option::is_none")]
+    pub include_thoughts: Option<bool>,
 }
 
 #[derive(Serialize)]
 pub struct GenerationConfig {
     #[serde(rename = "maxOutputTokens", skip_serializing_if = "Option::is_none")]
     pub max_output_tokens: Option<i16>,
-    pub temperature: i16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<i16>,
+    #[serde(rename = "topP", skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
     #[serde(rename = "thinkingConfig", skip_serializing_if = "Option::is_none")]
     pub thinking_config: Option<ThinkingConfig>,
+    #[serde(rename = "responseMimeType", skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(rename = "responseSchema", skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<Value>,
+    #[serde(rename = "responseLogprobs", skip_serializing_if = "Option::is_none")]
+    pub response_logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<i32>,
+    #[serde(rename = "responseModalities", skip_serializing_if = "Option::is_none")]
+    pub response_modalities: Option<Vec<&'static str>>,
+    /// Untyped fields from `ModelRequest::extra_generation_config`, minus any
+    /// key the typed fields above already set.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Serialize)]
@@ -52,16 +138,43 @@ pub enum Part {
         #[serde(rename = "functionResponse")]
         function_response: FunctionResponsePart,
     },
+    InlineData {
+        #[serde(rename = "inlineData")]
+        inline_data: InlineDataPart,
+    },
+    FileData {
+        #[serde(rename = "fileData")]
+        file_data: FileDataPart,
+    },
+}
+
+#[derive(Serialize)]
+pub struct InlineDataPart {
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Serialize)]
+pub struct FileDataPart {
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    #[serde(rename = "fileUri")]
+    pub file_uri: String,
 }
 
 #[derive(Serialize)]
 pub struct FunctionCallPart {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
     pub args: HashMap<String, Value>,
 }
 
 #[derive(Serialize)]
 pub struct FunctionResponsePart {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
     pub response: Value,
 }
@@ -109,7 +222,7 @@ fn to_gemini_type(json_schema_type: &str) -> String {
 /// Handles: type arrays like ["integer", "null"] → { type: "INTEGER", nullable: true },
 /// removes unsupported fields (format, minimum, maximum, $schema, title, etc.),
 /// and recursively converts nested objects/arrays.
-fn convert_property_to_gemini(value: &Value) -> Value {
+pub fn convert_property_to_gemini(value: &Value) -> Value {
     match value {
         Value::Object(map) => {
             let mut result = serde_json::Map::new();
@@ -210,46 +323,153 @@ pub struct GeminiTools {
     pub function_declarations: Vec<GeminiTool>,
 }
 
+#[derive(Serialize)]
+pub struct FunctionCallingConfig {
+    pub mode: &'static str, // "AUTO" | "ANY" | "NONE"
+    #[serde(
+        rename = "allowedFunctionNames",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub allowed_function_names: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub struct ToolConfig {
+    #[serde(rename = "functionCallingConfig")]
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+impl ToolConfig {
+    pub fn from_mode(mode: &FunctionCallingMode) -> ToolConfig {
+        let (mode, allowed_function_names) = match mode {
+            FunctionCallingMode::Auto => ("AUTO", None),
+            FunctionCallingMode::Any => ("ANY", None),
+            FunctionCallingMode::None => ("NONE", None),
+            FunctionCallingMode::Function(name) => ("ANY", Some(vec![name.clone()])),
+        };
+        ToolConfig {
+            function_calling_config: FunctionCallingConfig {
+                mode,
+                allowed_function_names,
+            },
+        }
+    }
+}
+
+pub fn modality_name(modality: &Modality) -> &'static str {
+    match modality {
+        Modality::Text => "TEXT",
+        Modality::Image => "IMAGE",
+        Modality::Audio => "AUDIO",
+    }
+}
+
+#[derive(Serialize)]
+pub struct GeminiSafetySetting {
+    pub category: String,
+    pub threshold: &'static str,
+}
+
+impl GeminiSafetySetting {
+    pub fn from_setting(setting: &SafetySetting) -> GeminiSafetySetting {
+        GeminiSafetySetting {
+            category: match &setting.category {
+                HarmCategory::Harassment => "HARM_CATEGORY_HARASSMENT".to_string(),
+                HarmCategory::HateSpeech => "HARM_CATEGORY_HATE_SPEECH".to_string(),
+                HarmCategory::SexuallyExplicit => "HARM_CATEGORY_SEXUALLY_EXPLICIT".to_string(),
+                HarmCategory::DangerousContent => "HARM_CATEGORY_DANGEROUS_CONTENT".to_string(),
+                HarmCategory::CivicIntegrity => "HARM_CATEGORY_CIVIC_INTEGRITY".to_string(),
+                HarmCategory::Other(name) => name.clone(),
+            },
+            threshold: match setting.threshold {
+                HarmBlockThreshold::BlockNone => "BLOCK_NONE",
+                HarmBlockThreshold::BlockOnlyHigh => "BLOCK_ONLY_HIGH",
+                HarmBlockThreshold::BlockMediumAndAbove => "BLOCK_MEDIUM_AND_ABOVE",
+                HarmBlockThreshold::BlockLowAndAbove => "BLOCK_LOW_AND_ABOVE",
+            },
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct GeminiRequest {
     pub system_instruction: Option<SystemInstructionContent>,
     pub contents: Vec<Content>,
     #[serde(rename = "generationConfig")]
-    pub generation_config: GenerationConfig, // TODO implement safetySettings
+    pub generation_config: GenerationConfig,
+    #[serde(rename = "safetySettings", skip_serializing_if = "Option::is_none")]
+    pub safety_settings: Option<Vec<GeminiSafetySetting>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<GeminiTools>>,
+    #[serde(rename = "toolConfig", skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GeminiResponse {
+    // Absent when the prompt itself was blocked.
+    #[serde(default)]
     pub candidates: Vec<Candidate>,
+    #[serde(rename = "promptFeedback", default)]
+    pub prompt_feedback: Option<PromptFeedback>,
     #[serde(rename = "usageMetadata")]
     pub usage_metadata: Option<UsageMetadata>,
+    #[serde(rename = "modelVersion", default)]
+    pub model_version: Option<String>,
+    #[serde(rename = "responseId", default)]
+    pub response_id: Option<String>,
 }
 
 impl GeminiResponse {
-    pub fn get_function(&self) -> Option<GeminiFunction> {
-        if self.candidates.is_empty() {
-            return None;
-        }
+    /// The first function call of the candidate at `index`, or `None` if
+    /// there is no such candidate or it made no call.
+    pub fn get_function_at(&self, index: usize) -> Option<GeminiFunction> {
+        self.get_functions_at(index).into_iter().next()
+    }
 
-        let candidate = &self.candidates[0];
-        let mut function_call: Option<GeminiFunction> = None;
-        for part in &candidate.content.parts {
-            if part.function_call.is_some() {
-                function_call = part.function_call.clone();
-            }
-        }
-        return function_call;
+    /// Every function call of the candidate at `index`, in order, whatever
+    /// text surrounds them; empty if there is no such candidate.
+    pub fn get_functions_at(&self, index: usize) -> Vec<GeminiFunction> {
+        let Some(candidate) = self.candidates.get(index) else {
+            return vec![];
+        };
+        candidate
+            .content
+            .parts
+            .iter()
+            .filter_map(|part| part.function_call.clone())
+            .collect()
     }
 
     pub fn get_text(&self) -> Option<String> {
-        if self.candidates.is_empty() {
-            return None;
+        self.get_text_at(0)
+    }
+
+    /// The text and function calls of the candidate at `index`, in order,
+    /// with adjacent text parts joined.
+    pub fn get_parts_at(&self, index: usize) -> Option<Vec<CompletionPart>> {
+        let candidate = self.candidates.get(index)?;
+        let mut parts = vec![];
+        for part in &candidate.content.parts {
+            if let Some(text) = &part.text {
+                CompletionPart::push_text(&mut parts, text);
+            }
+            if let Some(gf) = &part.function_call {
+                parts.push(CompletionPart::FunctionCall(FunctionCall {
+                    name: gf.name.clone(),
+                    args: gf.args.clone(),
+                    id: gf.id.clone(),
+                }));
+            }
         }
+        Some(parts)
+    }
 
+    /// The text of the candidate at `index`, or `None` if there is no such
+    /// candidate.
+    pub fn get_text_at(&self, index: usize) -> Option<String> {
+        let candidate = self.candidates.get(index)?;
         let mut response_text = String::new();
-        let candidate = &self.candidates[0];
         for part in &candidate.content.parts {
             match &part.text {
                 None => continue,
@@ -259,6 +479,98 @@ impl GeminiResponse {
         return Some(response_text);
     }
 
+    /// For the candidate at `index`, pairs each chosen token with the top
+    /// candidates at its position.
+    pub fn get_logprobs_at(&self, index: usize) -> Option<Vec<TokenLogprobs>> {
+        let result = self.candidates.get(index)?.logprobs_result.as_ref()?;
+        Some(
+            result
+                .chosen_candidates
+                .iter()
+                .enumerate()
+                .map(|(i, chosen)| TokenLogprobs {
+                    chosen: chosen.to_token_logprob(),
+                    top: result
+                        .top_candidates
+                        .get(i)
+                        .map(|t| t.candidates.iter().map(|c| c.to_token_logprob()).collect())
+                        .unwrap_or_default(),
+                })
+                .collect(),
+        )
+    }
+
+    /// The error to report when the prompt was blocked, if it was.
+    pub fn get_prompt_block(&self) -> Option<LangrustError> {
+        let feedback = self.prompt_feedback.as_ref()?;
+        Some(LangrustError::PromptBlocked {
+            reason: feedback.block_reason.clone()?,
+            safety_ratings: to_safety_ratings(&feedback.safety_ratings),
+            usage: self.get_usage(),
+        })
+    }
+
+    /// The media parts of the candidate at `index`, such as generated images.
+    pub fn get_media_at(&self, index: usize) -> Vec<MediaPart> {
+        let Some(candidate) = self.candidates.get(index) else {
+            return vec![];
+        };
+        candidate
+            .content
+            .parts
+            .iter()
+            .filter_map(|part| part.inline_data.as_ref())
+            .map(|inline| MediaPart {
+                mime_type: inline.mime_type.clone(),
+                data: inline.data.clone(),
+            })
+            .collect()
+    }
+
+    /// Mean log probability of the tokens of the candidate at `index`, when
+    /// reported.
+    pub fn get_avg_logprobs_at(&self, index: usize) -> Option<f64> {
+        self.candidates.get(index)?.avg_logprobs
+    }
+
+    /// Ratings of the candidate at `index`, falling back to those of the
+    /// prompt.
+    pub fn get_safety_ratings_at(&self, index: usize) -> Option<SafetyRatings> {
+        let candidate_ratings = self
+            .candidates
+            .get(index)
+            .map(|c| &c.safety_ratings)
+            .filter(|r| !r.is_empty());
+        let prompt_ratings = self
+            .prompt_feedback
+            .as_ref()
+            .map(|f| &f.safety_ratings)
+            .filter(|r| !r.is_empty());
+        candidate_ratings
+            .or(prompt_ratings)
+            .map(|r| to_safety_ratings(r))
+    }
+
+    /// The finish reason, if the provider stopped generation because the
+    /// content was filtered (safety, recitation, blocklist, ...).
+    pub fn get_filtered_finish_reason(&self) -> Option<&str> {
+        const FILTERED: [&str; 5] = [
+            "SAFETY",
+            "RECITATION",
+            "BLOCKLIST",
+            "PROHIBITED_CONTENT",
+            "SPII",
+        ];
+        self.get_finish_reason()
+            .filter(|reason| FILTERED.contains(reason))
+    }
+
+    pub fn get_finish_reason(&self) -> Option<&str> {
+        self.candidates
+            .first()
+            .and_then(|c| c.finish_reason.as_deref())
+    }
+
     pub fn get_prompt_tokens(&self) -> Option<i32> {
         self.usage_metadata
             .as_ref()
@@ -276,6 +588,17 @@ impl GeminiResponse {
             .as_ref()
             .and_then(|m| m.total_token_count)
     }
+
+    /// The reported usage, or `None` if the response has no `usageMetadata`.
+    /// A count missing from reported usage means none were used, e.g. no
+    /// `candidatesTokenCount` for an empty answer.
+    pub fn get_usage(&self) -> Option<Usage> {
+        self.usage_metadata.as_ref().map(|_| Usage {
+            prompt_tokens: self.get_prompt_tokens().unwrap_or(0),
+            completion_tokens: self.get_completion_tokens().unwrap_or(0),
+            total_tokens: self.get_total_tokens().unwrap_or(0),
+        })
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -283,10 +606,89 @@ pub struct Candidate {
     #[serde(default)]
     pub content: ResponseContent,
     #[serde(rename = "finishReason")]
-    #[allow(dead_code)]
     pub finish_reason: Option<String>,
     #[allow(dead_code)]
     pub index: Option<i32>,
+    #[serde(rename = "logprobsResult", default)]
+    pub logprobs_result: Option<LogprobsResult>,
+    #[serde(rename = "avgLogprobs", default)]
+    pub avg_logprobs: Option<f64>,
+    #[serde(rename = "safetyRatings", default)]
+    pub safety_ratings: Vec<GeminiSafetyRating>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromptFeedback {
+    #[serde(rename = "blockReason", default)]
+    pub block_reason: Option<String>,
+    #[serde(rename = "safetyRatings", default)]
+    pub safety_ratings: Vec<GeminiSafetyRating>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GeminiSafetyRating {
+    pub category: String,
+    #[serde(default)]
+    pub probability: String,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+fn to_safety_ratings(ratings: &[GeminiSafetyRating]) -> SafetyRatings {
+    SafetyRatings {
+        ratings: ratings
+            .iter()
+            .map(|r| SafetyRating {
+                category: match r.category.as_str() {
+                    "HARM_CATEGORY_HARASSMENT" => HarmCategory::Harassment,
+                    "HARM_CATEGORY_HATE_SPEECH" => HarmCategory::HateSpeech,
+                    "HARM_CATEGORY_SEXUALLY_EXPLICIT" => HarmCategory::SexuallyExplicit,
+                    "HARM_CATEGORY_DANGEROUS_CONTENT" => HarmCategory::DangerousContent,
+                    "HARM_CATEGORY_CIVIC_INTEGRITY" => HarmCategory::CivicIntegrity,
+                    other => HarmCategory::Other(other.to_string()),
+                },
+                probability: match r.probability.as_str() {
+                    "NEGLIGIBLE" => HarmProbability::Negligible,
+                    "LOW" => HarmProbability::Low,
+                    "MEDIUM" => HarmProbability::Medium,
+                    "HIGH" => HarmProbability::High,
+                    _ => HarmProbability::Unspecified,
+                },
+                blocked: r.blocked,
+            })
+            .collect(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogprobsResult {
+    #[serde(rename = "topCandidates", default)]
+    pub top_candidates: Vec<TopCandidates>,
+    #[serde(rename = "chosenCandidates", default)]
+    pub chosen_candidates: Vec<LogprobsCandidate>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopCandidates {
+    #[serde(default)]
+    pub candidates: Vec<LogprobsCandidate>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogprobsCandidate {
+    #[serde(default)]
+    pub token: String,
+    #[serde(rename = "logProbability", default)]
+    pub log_probability: f64,
+}
+
+impl LogprobsCandidate {
+    fn to_token_logprob(&self) -> TokenLogprob {
+        TokenLogprob {
+            token: self.token.clone(),
+            logprob: self.log_probability,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -299,6 +701,8 @@ pub struct ResponseContent {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct GeminiFunction {
+    #[serde(default)]
+    pub id: Option<String>,
     pub name: String,
     pub args: HashMap<String, Value>,
 }
@@ -308,6 +712,16 @@ pub struct ResponsePart {
     pub text: Option<String>,
     #[serde(rename = "functionCall")]
     pub function_call: Option<GeminiFunction>,
+    #[serde(rename = "inlineData", default)]
+    pub inline_data: Option<ResponseInlineData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseInlineData {
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    #[serde(with = "crate::client::base64_bytes")]
+    pub data: Vec<u8>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -319,3 +733,28 @@ pub struct UsageMetadata {
     #[serde(rename = "totalTokenCount")]
     pub total_token_count: Option<i32>,
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ModelInfo {
+    /// Resource name, e.g. `models/gemini-2.5-flash`.
+    pub name: String,
+    #[serde(rename = "displayName", default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename = "inputTokenLimit", default)]
+    pub input_token_limit: Option<i32>,
+    #[serde(rename = "outputTokenLimit", default)]
+    pub output_token_limit: Option<i32>,
+    #[serde(rename = "supportedGenerationMethods", default)]
+    pub supported_generation_methods: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListModelsResponse {
+    #[serde(default)]
+    pub models: Vec<ModelInfo>,
+    /// Set when there are more models to list; sent back as `pageToken`.
+    #[serde(rename = "nextPageToken", default)]
+    pub next_page_token: Option<String>,
+}
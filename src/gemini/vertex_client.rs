@@ -1,20 +1,173 @@
-use std::error::Error;
+use std::{error::Error, sync::Arc};
 
 use crate::{
-    client::{Completion, Model, ModelRequest, StreamResult},
+    client::{
+        ClientOptions, Completion, CompletionCache, CompletionsFuture, HttpTimeouts, HttpTransport,
+        HttpVersionPref, KeyProvider, Model, ModelDefaults, ModelObserver, ModelRequest,
+        ProxyConfig, RateLimiter, StreamResult,
+    },
     gemini::{
         base::GeminiClient,
         gcloud_helpers::get_access_token,
-        types::{GeminiModel, GeminiRequest},
+        types::{GeminiMethod, GeminiModel, GeminiRequest},
     },
 };
 use async_trait::async_trait;
 use reqwest::RequestBuilder;
 
+const DEFAULT_ENDPOINT: &str = "https://aiplatform.googleapis.com";
+
+/// A Gemini model on Vertex AI, authenticated with an OAuth access token.
+///
+/// Build one with `GeminiVertexModel::new` and the `with_*` methods; fields
+/// may be added in future versions.
+#[non_exhaustive]
 pub struct GeminiVertexModel {
     pub project_name: String,
     pub client: reqwest::Client,
     pub model: GeminiModel,
+    /// Base URL used instead of the public `aiplatform.googleapis.com`, e.g. a
+    /// Private Service Connect hostname. A bare host is assumed to be HTTPS.
+    pub endpoint_override: Option<String>,
+    /// Publisher whose model is served, `google` when unset, e.g. a partner
+    /// publisher under `publishers/{publisher}/models/...`. The request body
+    /// is still Gemini's, so the model must accept `generateContent`.
+    pub publisher: Option<String>,
+    /// When set, asked for the OAuth access token before every request
+    /// instead of the metadata server or `gcloud`.
+    pub token_provider: Option<Arc<dyn KeyProvider>>,
+    /// When set, every request is sent through it instead of `client`, e.g. a
+    /// `reqwest_middleware::ClientWithMiddleware` with the `middleware`
+    /// feature.
+    pub transport: Option<Arc<dyn HttpTransport>>,
+    /// See `ModelDefaults`.
+    pub defaults: Option<ModelDefaults>,
+    /// See `CompletionCache`.
+    pub cache: Option<Arc<CompletionCache>>,
+    /// See `Model::rate_limiter`.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// See `Model::observer`.
+    pub observer: Option<Arc<dyn ModelObserver>>,
+    // What `client` was last built from by a `with_*` method.
+    pub(crate) client_options: ClientOptions,
+}
+
+impl GeminiVertexModel {
+    /// `model` on Vertex AI in `project_name`, sent through a default
+    /// `reqwest::Client` and authenticated through the metadata server or
+    /// `gcloud`.
+    pub fn new(project_name: impl Into<String>, model: GeminiModel) -> GeminiVertexModel {
+        GeminiVertexModel {
+            project_name: project_name.into(),
+            client: reqwest::Client::new(),
+            model,
+            endpoint_override: None,
+            publisher: None,
+            token_provider: None,
+            transport: None,
+            defaults: None,
+            cache: None,
+            rate_limiter: None,
+            observer: None,
+            client_options: ClientOptions::default(),
+        }
+    }
+
+    /// Sends requests to `endpoint` instead of `aiplatform.googleapis.com`.
+    pub fn with_endpoint_override(mut self, endpoint: &str) -> Self {
+        self.endpoint_override = Some(endpoint.to_string());
+        self
+    }
+
+    /// Calls the model of `publisher` instead of `google`.
+    pub fn with_publisher(mut self, publisher: &str) -> Self {
+        self.publisher = Some(publisher.to_string());
+        self
+    }
+
+    /// Asks `provider` for the access token before every request.
+    pub fn with_token_provider(mut self, provider: Arc<dyn KeyProvider>) -> Self {
+        self.token_provider = Some(provider);
+        self
+    }
+
+    /// Sends requests through `client`, e.g. one with custom TLS settings,
+    /// instead of a default `reqwest::Client`.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Rebuilds the client with `timeouts`, replacing one set with
+    /// `with_client`. Fails if the client can't be built.
+    pub fn with_timeouts(mut self, timeouts: HttpTimeouts) -> Result<Self, reqwest::Error> {
+        self.client_options.timeouts = timeouts;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Rebuilds the client to speak `version`, replacing one set with
+    /// `with_client`. Fails if the client can't be built.
+    pub fn with_http_version(mut self, version: HttpVersionPref) -> Result<Self, reqwest::Error> {
+        self.client_options.http_version = version;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Rebuilds the client to send requests through the proxies in `proxy`,
+    /// replacing one set with `with_client`. Fails if a proxy URL doesn't
+    /// parse.
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Result<Self, reqwest::Error> {
+        self.client_options.proxy = proxy;
+        self.client = self.client_options.build()?;
+        Ok(self)
+    }
+
+    /// Sends every request through `transport` instead of `client`.
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Settings used where a request leaves them unset.
+    pub fn with_defaults(mut self, defaults: ModelDefaults) -> Self {
+        self.defaults = Some(defaults);
+        self
+    }
+
+    /// Caches completions in `cache`; see `CompletionCache`.
+    pub fn with_cache(mut self, cache: Arc<CompletionCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Waits on `limiter` before every request built with `new_request`.
+    /// Share one limiter between all models that draw from the same quota.
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Reports every request built with `new_request` to `observer`.
+    pub fn with_observer(mut self, observer: Arc<dyn ModelObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    fn endpoint_base(&self) -> String {
+        match self.endpoint_override.as_deref() {
+            None => DEFAULT_ENDPOINT.to_string(),
+            Some(base) if base.contains("://") => base.trim_end_matches('/').to_string(),
+            Some(host) => format!("https://{}", host.trim_end_matches('/')),
+        }
+    }
+
+    async fn access_token(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        match &self.token_provider {
+            Some(provider) => provider.api_key().await,
+            None => get_access_token().await,
+        }
+    }
 }
 
 #[async_trait]
@@ -34,16 +187,47 @@ impl Model for GeminiVertexModel {
         self.stream_generate_content(request).await
     }
 
+    fn completions(&self, request: ModelRequest, n: u32) -> CompletionsFuture<'_> {
+        Box::pin(self.generate_contents(request, n))
+    }
+
     fn model_name(&self) -> String {
         self.model.to_string()
     }
+
+    fn defaults(&self) -> Option<&ModelDefaults> {
+        self.defaults.as_ref()
+    }
+
+    fn cache(&self) -> Option<&CompletionCache> {
+        self.cache.as_deref()
+    }
+
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_deref()
+    }
+
+    fn observer(&self) -> Option<&dyn ModelObserver> {
+        self.observer.as_deref()
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.model.supports_tools()
+    }
 }
 
 impl GeminiClient for GeminiVertexModel {
-    fn get_endpoint(&self, model: &String, method: String) -> String {
+    fn transport(&self) -> Option<&dyn HttpTransport> {
+        self.transport.as_deref()
+    }
+
+    fn get_endpoint(&self, model: &String, method: GeminiMethod) -> String {
         return format!(
-            "https://aiplatform.googleapis.com/v1/projects/{}/locations/global/publishers/google/models/{model}:{method}",
-            self.project_name
+            "{}/v1/projects/{}/locations/global/publishers/{}/models/{model}:{}",
+            self.endpoint_base(),
+            self.project_name,
+            self.publisher.as_deref().unwrap_or("google"),
+            method.as_str()
         );
     }
 
@@ -52,7 +236,9 @@ impl GeminiClient for GeminiVertexModel {
         endpoint: &String,
         request_body: &GeminiRequest,
     ) -> Result<RequestBuilder, Box<dyn Error + Send + Sync>> {
-        let access_token = get_access_token().await?;
+        // Streaming and unary calls share this; the token is only sent with
+        // the initial request, never per chunk.
+        let access_token = self.access_token().await?;
         return Ok(self
             .client
             .post(endpoint)
@@ -1,11 +1,35 @@
+// Every provider endpoint is HTTPS, so a reqwest built without a TLS backend
+// would only fail at runtime with an opaque "scheme is not http" error.
+#[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+compile_error!(
+    "langrust needs a TLS backend: enable the `native-tls` (default) or `rustls-tls` feature"
+);
+
 pub mod claude;
 pub mod client;
 pub mod gemini;
 pub mod openai;
 
+#[cfg(test)]
+mod test_server;
+
 pub use claude::{ClaudeApiModel, ClaudeModel};
+#[cfg(any(test, feature = "testing"))]
+pub use client::ReplayModel;
 pub use client::{
-    Message, MessageType, ModelRequest, Role, Settings, StreamEvent, StreamResult, Tool,
+    BeforeSendHook, CachedKeyProvider, CompletionCache, CompletionPart, CompletionStream,
+    CompletionsFuture, ContentPart, Conversation, DuplicateToolPolicy, ErrorBodyKind, ErrorEvent,
+    FallbackModel, FunctionCallingMode, GenerationPreset, HarmBlockThreshold, HarmCategory,
+    HarmProbability, HttpTimeouts, HttpTransport, HttpVersionPref, JitterMode, JsonStream,
+    JsonStreamEvent, KeyProvider, LangrustError, ListMergePolicy, LoadBalancedModel, MediaPart,
+    Message, MessageType, Modality, ModelDefaults, ModelObserver, ModelRequest, PrivacyMode,
+    PromptTemplate, ProxyConfig, RateLimiter, RequestEvent, ResponseEvent, RetryConfig, Role,
+    SafetyRating, SafetyRatings, SafetySetting, SafetySettings, Settings, StreamEvent,
+    StreamHandle, StreamResult, TokenLogprob, TokenLogprobs, Tool, ToolTranslation, ask,
+    chunk_text, estimate_text_tokens,
+};
+pub use gemini::{
+    CostEstimate, GeminiApiModel, GeminiMethod, GeminiModel, GeminiVertexModel, ModelCapabilities,
+    PricingTable,
 };
-pub use gemini::{GeminiApiModel, GeminiModel, GeminiVertexModel};
 pub use openai::{OpenAiApiModel, OpenAiModel};
@@ -2,22 +2,24 @@ use std::collections::HashMap;
 use std::error::Error;
 
 use eventsource_stream::Eventsource;
-use futures::{StreamExt, TryFutureExt, stream};
+use futures::{StreamExt, stream};
 use reqwest::RequestBuilder;
 
 use crate::{
     client::{
-        Completion, FunctionCall, MessageType, Model, ModelRequest, StreamEvent, StreamResult,
-        Usage,
+        Completion, CompletionPart, ContentPart, FunctionCall, HttpTransport, MessageType, Model,
+        ModelRequest, StreamEvent, StreamResult, Usage, api_error, log_request, read_json,
+        reject_gcs_file_uris, route, send,
     },
     openai::types::{
-        OpenAiInputItem, OpenAiRequest, OpenAiResponse, OpenAiTool, ResponsesStreamEvent,
-        synth_call_id,
+        OpenAiInputItem, OpenAiMessageContent, OpenAiRequest, OpenAiResponse, OpenAiTextConfig,
+        OpenAiTextFormat, OpenAiTool, ResponsesStreamEvent, synth_call_id, tool_choice_from_mode,
     },
 };
 
 pub trait OpenAiClient: Model {
     fn create_request_body(&self, request: ModelRequest, stream: bool) -> OpenAiRequest {
+        let request = request.with_defaults(self.defaults());
         let settings = request.settings.clone();
 
         let max_output_tokens = settings
@@ -34,32 +36,34 @@ pub trait OpenAiClient: Model {
         let mut input: Vec<OpenAiInputItem> = Vec::new();
 
         for m in request.messages.clone().unwrap_or_default().iter() {
+            if m.parts
+                .iter()
+                .any(|p| matches!(p, ContentPart::FunctionCall(_)))
+            {
+                input.extend(OpenAiInputItem::from_turn(m));
+                continue;
+            }
             match &m.message_type {
                 MessageType::Text => match m.role {
                     Some(crate::client::Role::Model) => {
                         input.push(OpenAiInputItem::Message {
                             role: "assistant".to_string(),
-                            content: m.content.clone(),
+                            content: OpenAiMessageContent::from_message(m, true),
                         });
                     }
                     _ => {
                         input.push(OpenAiInputItem::Message {
                             role: "user".to_string(),
-                            content: m.content.clone(),
+                            content: OpenAiMessageContent::from_message(m, false),
                         });
                     }
                 },
                 MessageType::FunctionCall(fc) => {
-                    let arguments = serde_json::to_string(&fc.args).unwrap_or("{}".to_string());
-                    input.push(OpenAiInputItem::FunctionCall {
-                        call_id: synth_call_id(&fc.name),
-                        name: fc.name.clone(),
-                        arguments,
-                    });
+                    input.push(OpenAiInputItem::function_call(fc));
                 }
-                MessageType::FunctionResponse { name, response } => {
+                MessageType::FunctionResponse { name, response, id } => {
                     input.push(OpenAiInputItem::FunctionCallOutput {
-                        call_id: synth_call_id(name),
+                        call_id: id.clone().unwrap_or_else(|| synth_call_id(name)),
                         output: response
                             .as_ref()
                             .map(|v| v.to_string())
@@ -74,6 +78,18 @@ pub trait OpenAiClient: Model {
             .clone()
             .map(|ts| ts.iter().map(OpenAiTool::from_tool).collect());
 
+        let text = request
+            .response_schema
+            .clone()
+            .map(|schema| OpenAiTextConfig {
+                format: OpenAiTextFormat {
+                    kind: "json_schema",
+                    name: "response",
+                    schema,
+                    strict: false,
+                },
+            });
+
         let stream_flag = if stream { Some(true) } else { None };
 
         OpenAiRequest {
@@ -82,7 +98,13 @@ pub trait OpenAiClient: Model {
             instructions: request.system.clone(),
             max_output_tokens,
             temperature,
+            top_p: settings.as_ref().and_then(|s| s.top_p),
             tools,
+            tool_choice: request
+                .function_calling_mode
+                .as_ref()
+                .map(tool_choice_from_mode),
+            text,
             stream: stream_flag,
             store: false,
         }
@@ -92,22 +114,29 @@ pub trait OpenAiClient: Model {
         &self,
         request: ModelRequest,
     ) -> Result<Completion, Box<dyn Error + Send + Sync>> {
+        request.validate()?;
+        reject_gcs_file_uris(&request, "OpenAI")?;
+        log_request("openai", &self.model_name(), &request);
         let endpoint = self.get_endpoint();
+        let client = request.client.clone();
+        let max_response_bytes = request.max_response_bytes;
+        let enum_variants = request.enum_response.clone();
         let body = self.create_request_body(request, false);
-        let response = self.build_request(&endpoint, &body).await?.send().await?;
+        let response = send(
+            self.build_request(&endpoint, &body).await?,
+            route(client.as_ref(), self.transport()),
+        )
+        .await?;
 
-        let status = response.status();
-        if !status.is_success() {
-            let err = response.text().map_err(|e| e.to_string()).await?;
-            return Err(format!("OpenAI request failed with status {}: {}", status, err).into());
+        if !response.status().is_success() {
+            return Err(api_error(response, max_response_bytes).await?.into());
         }
 
-        let body: OpenAiResponse = response.json().await?;
+        let body: OpenAiResponse = read_json(response, max_response_bytes).await?;
 
         let text = body.get_text();
-        let function = body
-            .get_function()
-            .map(|(name, args)| FunctionCall { name, args });
+        let parts = body.get_parts()?;
+        let function_calls = CompletionPart::calls(&parts);
 
         let usage = body.usage.map(|u| Usage {
             prompt_tokens: u.input_tokens,
@@ -117,12 +146,19 @@ pub trait OpenAiClient: Model {
 
         Ok(Completion {
             completion: text,
-            usage: usage.unwrap_or(Usage {
-                prompt_tokens: 0,
-                completion_tokens: 0,
-                total_tokens: 0,
-            }),
-            function,
+            usage,
+            function: function_calls.first().cloned(),
+            function_calls,
+            model: self.model_name(),
+            model_version: body.model,
+            response_id: body.id,
+            logprobs: None,
+            avg_logprobs: None,
+            safety_ratings: None,
+            enum_variants,
+            ordered_parts: Some(parts),
+            media: vec![],
+            truncated: false,
         })
     }
 
@@ -130,18 +166,21 @@ pub trait OpenAiClient: Model {
         &self,
         request: ModelRequest,
     ) -> Result<StreamResult, Box<dyn Error + Send + Sync>> {
+        request.validate()?;
+        reject_gcs_file_uris(&request, "OpenAI")?;
+        log_request("openai", &self.model_name(), &request);
         let endpoint = self.get_endpoint();
+        let client = request.client.clone();
+        let max_response_bytes = request.max_response_bytes;
         let body = self.create_request_body(request, true);
-        let response = self.build_request(&endpoint, &body).await?.send().await?;
+        let response = send(
+            self.build_request(&endpoint, &body).await?,
+            route(client.as_ref(), self.transport()),
+        )
+        .await?;
 
-        let status = response.status();
-        if !status.is_success() {
-            let err = response.text().map_err(|e| e.to_string()).await?;
-            return Err(format!(
-                "OpenAI streaming request failed with status {}: {}",
-                status, err
-            )
-            .into());
+        if !response.status().is_success() {
+            return Err(api_error(response, max_response_bytes).await?.into());
         }
 
         let sse = Box::pin(response.bytes_stream().eventsource());
@@ -179,6 +218,12 @@ pub trait OpenAiClient: Model {
         Ok(Box::pin(out))
     }
 
+    /// Where built requests are sent instead of the model's client, if
+    /// anywhere.
+    fn transport(&self) -> Option<&dyn HttpTransport> {
+        None
+    }
+
     fn get_endpoint(&self) -> String;
 
     async fn build_request(
@@ -197,6 +242,17 @@ fn handle_stream_event(event: ResponsesStreamEvent, state: &mut State) {
                 }
             }
         }
+        "response.output_item.added" => {
+            if let Some(item) = event.item
+                && item.item_type.as_deref() == Some("function_call")
+                && let Some(name) = item.name
+            {
+                state.push_event(StreamEvent::FunctionCallStarted {
+                    name,
+                    id: item.call_id,
+                });
+            }
+        }
         "response.output_item.done" => {
             if let Some(item) = event.item {
                 if item.item_type.as_deref() == Some("function_call") {
@@ -216,7 +272,11 @@ fn handle_stream_event(event: ResponsesStreamEvent, state: &mut State) {
                                 }
                             }
                         };
-                        state.push_event(StreamEvent::FunctionCall(FunctionCall { name, args }));
+                        state.push_event(StreamEvent::FunctionCall(FunctionCall {
+                            name,
+                            args,
+                            id: item.call_id,
+                        }));
                     }
                 }
             }
@@ -1,6 +1,5 @@
 use std::env;
 
-use futures::StreamExt;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -13,11 +12,10 @@ use crate::{
 };
 
 fn make_model(model: OpenAiModel) -> OpenAiApiModel {
-    OpenAiApiModel {
-        client: reqwest::Client::new(),
-        api_key: env::var("OPENAI_KEY").expect("OPENAI_KEY env var must be set"),
+    OpenAiApiModel::new(
+        env::var("OPENAI_KEY").expect("OPENAI_KEY env var must be set"),
         model,
-    }
+    )
 }
 
 fn default_settings() -> Settings {
@@ -25,7 +23,10 @@ fn default_settings() -> Settings {
         max_tokens: Some(8000),
         timeout: None,
         temperature: None,
+        top_p: None,
         thinking_budget: None,
+        privacy_mode: None,
+        merge_consecutive_roles: None,
     }
 }
 
@@ -61,6 +62,7 @@ async fn run_generate_content(model: OpenAiModel) {
         .completion()
         .await;
     assert!(response.is_ok(), "completion failed: {:?}", response.err());
+    assert_eq!(response.unwrap().model, m.model_name());
 }
 
 async fn run_with_messages(model: OpenAiModel) {
@@ -162,7 +164,14 @@ async fn run_stream_generate_content(model: OpenAiModel) {
                 assert!(total_tokens > 0);
             }
             StreamEvent::FunctionCall(_) => {}
+            StreamEvent::FunctionCallStarted { .. } => {}
             StreamEvent::Error(e) => panic!("stream event should not be an error: {}", e),
+            StreamEvent::Truncated(_) => panic!("stream should not be truncated"),
+            StreamEvent::Stopped(_) => panic!("stream should not be stopped"),
+            StreamEvent::Cancelled => panic!("stream should not be cancelled"),
+            StreamEvent::Filtered { finish_reason, .. } => {
+                panic!("stream should not be filtered: {}", finish_reason)
+            }
         }
     }
 
@@ -197,6 +206,12 @@ async fn run_stream_function_call(model: OpenAiModel) {
                 assert_eq!(fc.name, "get_weather");
             }
             StreamEvent::Error(e) => panic!("stream event should not be an error: {}", e),
+            StreamEvent::Truncated(_) => panic!("stream should not be truncated"),
+            StreamEvent::Stopped(_) => panic!("stream should not be stopped"),
+            StreamEvent::Cancelled => panic!("stream should not be cancelled"),
+            StreamEvent::Filtered { finish_reason, .. } => {
+                panic!("stream should not be filtered: {}", finish_reason)
+            }
             _ => {}
         }
     }
@@ -346,38 +361,290 @@ fn test_openai_tool_emits_required_and_name_and_description() {
 
 #[test]
 fn test_model_name_openai_api() {
-    let m = OpenAiApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy-key".to_string(),
-        model: OpenAiModel::Gpt5_4,
-    };
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4);
     assert_eq!(m.model_name(), "gpt-5.4");
 
-    let m = OpenAiApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy-key".to_string(),
-        model: OpenAiModel::Gpt5_4Mini,
-    };
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4Mini);
     assert_eq!(m.model_name(), "gpt-5.4-mini");
 
-    let m = OpenAiApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy-key".to_string(),
-        model: OpenAiModel::Gpt5_4Nano,
-    };
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4Nano);
     assert_eq!(m.model_name(), "gpt-5.4-nano");
 
-    let m = OpenAiApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy-key".to_string(),
-        model: OpenAiModel::Gpt5_5,
-    };
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_5);
     assert_eq!(m.model_name(), "gpt-5.5");
 
-    let m = OpenAiApiModel {
-        client: reqwest::Client::new(),
-        api_key: "dummy-key".to_string(),
-        model: OpenAiModel::Gpt5_3Codex,
-    };
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_3Codex);
     assert_eq!(m.model_name(), "gpt-5.3-codex");
 }
+
+#[test]
+fn test_response_schema_serializes_as_json_schema_text_format() {
+    use crate::openai::base::OpenAiClient;
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct Sentiment {
+        label: String,
+    }
+
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4Mini);
+    let request = m
+        .new_request()
+        .with_message(Message::user("I love it".to_string()))
+        .with_response_schema::<Sentiment>()
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request, false)).unwrap();
+
+    let format = &json["text"]["format"];
+    assert_eq!(format["type"], "json_schema");
+    assert_eq!(format["name"], "response");
+    assert_eq!(format["schema"]["type"], "object");
+    assert!(format["schema"]["properties"].get("label").is_some());
+}
+
+fn tool_choice_for(mode: crate::client::FunctionCallingMode) -> serde_json::Value {
+    use crate::openai::base::OpenAiClient;
+
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4Mini);
+    let request = m
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .with_tool(Tool::new("get_weather", "Get the weather"))
+        .with_function_calling_mode(mode)
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request, false)).unwrap();
+    json.get("tool_choice").cloned().unwrap()
+}
+
+#[test]
+fn test_text_and_function_call_turn_is_sent_as_message_then_call() {
+    use crate::client::{Completion, FunctionCall};
+    use crate::openai::base::OpenAiClient;
+
+    let completion = Completion {
+        completion: "Let me check.".to_string(),
+        function: Some(FunctionCall {
+            name: "get_weather".to_string(),
+            args: std::collections::HashMap::from([(
+                "city".to_string(),
+                serde_json::json!("Paris"),
+            )]),
+            id: Some("call_1".to_string()),
+        }),
+        ..Default::default()
+    };
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4Mini);
+    let request = m
+        .new_request()
+        .with_message(Message::user("weather in Paris?".to_string()))
+        .with_message(Message::from_completion(&completion))
+        .to_model_request();
+    let json = serde_json::to_value(m.create_request_body(request, false)).unwrap();
+
+    assert_eq!(
+        json["input"][1],
+        serde_json::json!({
+            "type": "message",
+            "role": "assistant",
+            "content": [{ "type": "output_text", "text": "Let me check." }]
+        })
+    );
+    assert_eq!(
+        json["input"][2],
+        serde_json::json!({
+            "type": "function_call",
+            "call_id": "call_1",
+            "name": "get_weather",
+            "arguments": "{\"city\":\"Paris\"}"
+        })
+    );
+}
+
+#[test]
+fn test_function_calling_mode_maps_to_openai_tool_choice() {
+    use crate::client::FunctionCallingMode;
+
+    assert_eq!(tool_choice_for(FunctionCallingMode::Auto), "auto");
+    assert_eq!(tool_choice_for(FunctionCallingMode::Any), "required");
+    assert_eq!(tool_choice_for(FunctionCallingMode::None), "none");
+    assert_eq!(
+        tool_choice_for(FunctionCallingMode::Function("get_weather".to_string())),
+        serde_json::json!({ "type": "function", "name": "get_weather" })
+    );
+}
+
+// Sends every request to a local mock server, keeping its path.
+struct ToMockServer(String);
+
+#[async_trait::async_trait]
+impl crate::client::HttpTransport for ToMockServer {
+    async fn execute(
+        &self,
+        mut request: reqwest::Request,
+    ) -> Result<reqwest::Response, Box<dyn std::error::Error + Send + Sync>> {
+        let url = format!("{}{}", self.0, request.url().path());
+        *request.url_mut() = url.parse()?;
+        Ok(reqwest::Client::new().execute(request).await?)
+    }
+}
+
+#[tokio::test]
+async fn test_fallback_model_falls_back_after_openai_rate_limit() {
+    use crate::client::FallbackModel;
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![
+        MockResponse::json(429, r#"{"error":{"type":"rate_limit_exceeded"}}"#),
+        MockResponse::json(
+            200,
+            r#"{"output":[],"output_text":"hello","usage":{"input_tokens":3,"output_tokens":1,"total_tokens":4}}"#,
+        ),
+    ])
+    .await;
+    let model_at = |model| {
+        OpenAiApiModel::new("dummy-key", model)
+            .with_transport(Arc::new(ToMockServer(server.url.clone())))
+    };
+    let model = FallbackModel::new(vec![
+        Box::new(model_at(OpenAiModel::Gpt5_5)),
+        Box::new(model_at(OpenAiModel::Gpt5_4Mini)),
+    ]);
+
+    let completion = model
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .completion()
+        .await
+        .expect("the second model should answer");
+
+    assert_eq!(completion.completion, "hello");
+    assert_eq!(completion.model, "gpt-5.4-mini");
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_load_balanced_model_skips_openai_model_after_rate_limit() {
+    use crate::client::LoadBalancedModel;
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let ok = r#"{"output":[],"output_text":"hello","usage":{"input_tokens":3,"output_tokens":1,"total_tokens":4}}"#;
+    let server = MockServer::start(vec![
+        MockResponse::json(429, r#"{"error":{"type":"rate_limit_exceeded"}}"#),
+        MockResponse::json(200, ok),
+        MockResponse::json(200, ok),
+    ])
+    .await;
+    let model_at = |model| {
+        OpenAiApiModel::new("dummy-key", model)
+            .with_transport(Arc::new(ToMockServer(server.url.clone())))
+    };
+    let model = LoadBalancedModel::new(
+        vec![
+            Box::new(model_at(OpenAiModel::Gpt5_5)),
+            Box::new(model_at(OpenAiModel::Gpt5_4Mini)),
+        ],
+        Duration::from_secs(60),
+    )
+    .unwrap();
+    let ask = || async {
+        model
+            .new_request()
+            .with_message(Message::user("hi".to_string()))
+            .completion()
+            .await
+            .map(|c| c.model)
+    };
+
+    assert!(ask().await.is_err());
+    assert_eq!(ask().await.unwrap(), "gpt-5.4-mini");
+    // gpt-5.5's turn, but it is still cooling down.
+    assert_eq!(ask().await.unwrap(), "gpt-5.4-mini");
+}
+
+#[tokio::test]
+async fn test_malformed_tool_arguments_are_an_error() {
+    use crate::client::LangrustError;
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"output":[{"type":"function_call","call_id":"call_1","name":"get_weather","arguments":"{\"city\":"}]}"#,
+    )])
+    .await;
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4Mini)
+        .with_transport(Arc::new(ToMockServer(server.url.clone())));
+
+    let err = m
+        .new_request()
+        .with_message(Message::user("weather in Paris?".to_string()))
+        .completion()
+        .await
+        .expect_err("the arguments are not JSON");
+
+    assert!(
+        matches!(
+            err.downcast_ref::<LangrustError>(),
+            Some(LangrustError::InvalidResponse(message))
+                if message.starts_with("failed to parse tool arguments JSON")
+        ),
+        "{}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn test_gcs_file_uris_are_rejected() {
+    use crate::client::LangrustError;
+
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4Mini);
+    let err = m
+        .new_request()
+        .with_message(
+            Message::user("Summarize".to_string())
+                .with_file_uri("gs://bucket/report.pdf", "application/pdf"),
+        )
+        .completion()
+        .await
+        .expect_err("gs:// is Gemini only");
+
+    assert_eq!(
+        err.downcast_ref::<LangrustError>(),
+        Some(&LangrustError::InvalidRequest(
+            "OpenAI can't fetch gs:// file URIs, only https:// URLs; got `gs://bucket/report.pdf`"
+                .to_string()
+        ))
+    );
+}
+
+#[tokio::test]
+async fn test_requests_go_through_the_transport_when_set() {
+    use crate::test_server::{MockResponse, MockServer};
+    use std::sync::Arc;
+
+    let server = MockServer::start(vec![MockResponse::json(
+        200,
+        r#"{"output":[],"output_text":"hello","usage":{"input_tokens":3,"output_tokens":1,"total_tokens":4}}"#,
+    )])
+    .await;
+    let m = OpenAiApiModel::new("dummy-key", OpenAiModel::Gpt5_4Mini)
+        .with_transport(Arc::new(ToMockServer(server.url.clone())));
+
+    let completion = m
+        .new_request()
+        .with_message(Message::user("hi".to_string()))
+        .completion()
+        .await
+        .unwrap();
+
+    assert_eq!(completion.completion, "hello");
+    let requests = server.requests();
+    assert_eq!(requests[0].path, "/v1/responses");
+    assert_eq!(
+        requests[0].header("authorization"),
+        Some("Bearer dummy-key")
+    );
+}
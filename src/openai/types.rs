@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 
-use crate::client::Tool;
+use crate::client::{
+    CompletionPart, ContentPart, FunctionCall, FunctionCallingMode, LangrustError, Message, Tool,
+    encode_base64,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -42,7 +45,10 @@ pub fn synth_call_id(name: &str) -> String {
 #[serde(tag = "type")]
 pub enum OpenAiInputItem {
     #[serde(rename = "message")]
-    Message { role: String, content: String },
+    Message {
+        role: String,
+        content: OpenAiMessageContent,
+    },
     #[serde(rename = "function_call")]
     FunctionCall {
         call_id: String,
@@ -53,6 +59,123 @@ pub enum OpenAiInputItem {
     FunctionCallOutput { call_id: String, output: String },
 }
 
+impl OpenAiInputItem {
+    pub fn function_call(fc: &FunctionCall) -> OpenAiInputItem {
+        OpenAiInputItem::FunctionCall {
+            call_id: fc.id.clone().unwrap_or_else(|| synth_call_id(&fc.name)),
+            name: fc.name.clone(),
+            arguments: serde_json::to_string(&fc.args).unwrap_or("{}".to_string()),
+        }
+    }
+
+    /// A model turn with function calls among its parts: each run of text
+    /// and media as an assistant message, each call as a `function_call`
+    /// item, in order.
+    pub fn from_turn(message: &Message) -> Vec<OpenAiInputItem> {
+        let mut items = Vec::new();
+        let mut pending = Vec::new();
+        let flush = |items: &mut Vec<OpenAiInputItem>, pending: &mut Vec<ContentPart>| {
+            if !pending.is_empty() {
+                items.push(OpenAiInputItem::Message {
+                    role: "assistant".to_string(),
+                    content: OpenAiMessageContent::from_parts(&std::mem::take(pending), true),
+                });
+            }
+        };
+        for part in &message.parts {
+            match part {
+                ContentPart::FunctionCall(fc) => {
+                    flush(&mut items, &mut pending);
+                    items.push(OpenAiInputItem::function_call(fc));
+                }
+                part => pending.push(part.clone()),
+            }
+        }
+        flush(&mut items, &mut pending);
+        items
+    }
+}
+
+/// Plain text, or a list of typed parts for messages carrying media.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum OpenAiMessageContent {
+    Text(String),
+    Parts(Vec<OpenAiInputPart>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OpenAiInputPart {
+    InputText { text: String },
+    OutputText { text: String },
+    InputImage { image_url: String },
+    InputFile(OpenAiInputFile),
+}
+
+/// A file sent inline as a `data:` URL, or by URL.
+#[derive(Serialize)]
+pub struct OpenAiInputFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_url: Option<String>,
+}
+
+impl OpenAiMessageContent {
+    /// Inline media is sent as `data:` URLs, file URIs as they are; assistant
+    /// text must be `output_text`.
+    pub fn from_message(message: &Message, assistant: bool) -> OpenAiMessageContent {
+        if message.parts.is_empty() {
+            return OpenAiMessageContent::Text(message.content.clone());
+        }
+        OpenAiMessageContent::from_parts(&message.parts, assistant)
+    }
+
+    /// Like `from_message`, for the given parts. Function calls are left
+    /// out; they are items of their own (see `OpenAiInputItem::from_turn`).
+    pub fn from_parts(parts: &[ContentPart], assistant: bool) -> OpenAiMessageContent {
+        let parts = parts
+            .iter()
+            .filter(|part| !matches!(part, ContentPart::FunctionCall(_)))
+            .map(|part| match part {
+                ContentPart::Text(text) if assistant => {
+                    OpenAiInputPart::OutputText { text: text.clone() }
+                }
+                ContentPart::Text(text) => OpenAiInputPart::InputText { text: text.clone() },
+                ContentPart::InlineData { mime_type, data } => {
+                    let url = format!("data:{};base64,{}", mime_type, encode_base64(data));
+                    match mime_type.starts_with("image/") {
+                        true => OpenAiInputPart::InputImage { image_url: url },
+                        false => OpenAiInputPart::InputFile(OpenAiInputFile {
+                            filename: Some("attachment".to_string()),
+                            file_data: Some(url),
+                            file_url: None,
+                        }),
+                    }
+                }
+                ContentPart::FileData {
+                    mime_type,
+                    file_uri,
+                } => match mime_type.starts_with("image/") {
+                    true => OpenAiInputPart::InputImage {
+                        image_url: file_uri.clone(),
+                    },
+                    false => OpenAiInputPart::InputFile(OpenAiInputFile {
+                        filename: None,
+                        file_data: None,
+                        file_url: Some(file_uri.clone()),
+                    }),
+                },
+                ContentPart::FunctionCall(_) => unreachable!("function calls are filtered out"),
+            })
+            .collect();
+        OpenAiMessageContent::Parts(parts)
+    }
+}
+
 /// Tool definition for the Responses API (internally tagged, flat structure).
 #[derive(Serialize)]
 pub struct OpenAiTool {
@@ -98,6 +221,34 @@ impl OpenAiTool {
     }
 }
 
+/// `tool_choice` is either a bare mode string or an object forcing a specific
+/// function.
+pub fn tool_choice_from_mode(mode: &FunctionCallingMode) -> Value {
+    match mode {
+        FunctionCallingMode::Auto => Value::String("auto".to_string()),
+        FunctionCallingMode::Any => Value::String("required".to_string()),
+        FunctionCallingMode::None => Value::String("none".to_string()),
+        FunctionCallingMode::Function(name) => {
+            serde_json::json!({ "type": "function", "name": name })
+        }
+    }
+}
+
+/// `text.format` for structured outputs.
+#[derive(Serialize)]
+pub struct OpenAiTextFormat {
+    #[serde(rename = "type")]
+    pub kind: &'static str, // "json_schema"
+    pub name: &'static str,
+    pub schema: Value,
+    pub strict: bool,
+}
+
+#[derive(Serialize)]
+pub struct OpenAiTextConfig {
+    pub format: OpenAiTextFormat,
+}
+
 /// Request body for the Responses API (`POST /v1/responses`).
 #[derive(Serialize)]
 pub struct OpenAiRequest {
@@ -110,8 +261,14 @@ pub struct OpenAiRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<OpenAiTool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<OpenAiTextConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
     pub store: bool,
 }
@@ -129,10 +286,7 @@ pub struct OpenAiUsage {
 #[serde(tag = "type")]
 pub enum OpenAiContentPart {
     #[serde(rename = "output_text")]
-    OutputText {
-        #[allow(dead_code)]
-        text: String,
-    },
+    OutputText { text: String },
 }
 
 #[derive(Debug, Deserialize)]
@@ -141,13 +295,11 @@ pub enum OpenAiOutputItem {
     #[serde(rename = "message")]
     Message {
         #[serde(default)]
-        #[allow(dead_code)]
         content: Vec<OpenAiContentPart>,
     },
     #[serde(rename = "function_call")]
     FunctionCall {
         #[serde(default)]
-        #[allow(dead_code)]
         call_id: Option<String>,
         name: String,
         arguments: String,
@@ -156,6 +308,10 @@ pub enum OpenAiOutputItem {
 
 #[derive(Debug, Deserialize)]
 pub struct OpenAiResponse {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
     pub output: Vec<OpenAiOutputItem>,
     #[serde(default)]
     pub output_text: Option<String>,
@@ -167,22 +323,40 @@ impl OpenAiResponse {
         self.output_text.clone().unwrap_or_default()
     }
 
-    pub fn get_function(&self) -> Option<(String, HashMap<String, Value>)> {
+    /// The output's text and function calls, in order. Fails if a call's
+    /// arguments aren't a JSON object.
+    pub fn get_parts(&self) -> Result<Vec<CompletionPart>, LangrustError> {
+        let mut parts = vec![];
         for item in &self.output {
-            if let OpenAiOutputItem::FunctionCall {
-                name, arguments, ..
-            } = item
-            {
-                let args: HashMap<String, Value> = if arguments.is_empty() {
-                    HashMap::new()
-                } else {
-                    serde_json::from_str(arguments).unwrap_or_default()
-                };
-                return Some((name.clone(), args));
+            match item {
+                OpenAiOutputItem::Message { content } => {
+                    for OpenAiContentPart::OutputText { text } in content {
+                        CompletionPart::push_text(&mut parts, text);
+                    }
+                }
+                OpenAiOutputItem::FunctionCall {
+                    call_id,
+                    name,
+                    arguments,
+                } => parts.push(CompletionPart::FunctionCall(FunctionCall {
+                    name: name.clone(),
+                    args: parse_arguments(arguments)?,
+                    id: call_id.clone(),
+                })),
             }
         }
-        None
+        Ok(parts)
+    }
+}
+
+/// A call's arguments: an empty string is no arguments.
+fn parse_arguments(arguments: &str) -> Result<HashMap<String, Value>, LangrustError> {
+    if arguments.is_empty() {
+        return Ok(HashMap::new());
     }
+    serde_json::from_str(arguments).map_err(|e| {
+        LangrustError::InvalidResponse(format!("failed to parse tool arguments JSON: {}", e))
+    })
 }
 
 // ---------------- Streaming event types (Responses API) ----------------
@@ -196,6 +370,8 @@ pub struct StreamOutputItem {
     pub name: Option<String>,
     #[serde(default)]
     pub arguments: Option<String>,
+    #[serde(default)]
+    pub call_id: Option<String>,
 }
 
 /// Generic shape for all SSE data payloads from the Responses API.
@@ -0,0 +1,278 @@
+#![allow(dead_code)]
+
+// Minimal HTTP/1.1 server for offline tests. Serves canned responses in order
+// (repeating the last one) and records every request it receives.
+
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpSocket, TcpStream},
+};
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    /// How long to wait after reading the request before responding.
+    pub delay: Option<Duration>,
+    /// Whether to keep the connection open after the body, as a stream that
+    /// hasn't finished would, until the client closes it.
+    pub hold_open: bool,
+    /// Sends the body in two writes, split at this byte offset, with a pause
+    /// in between so the client reads them separately.
+    pub split_at: Option<usize>,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: &str) -> MockResponse {
+        MockResponse {
+            status,
+            content_type: "application/json",
+            body: body.as_bytes().to_vec(),
+            delay: None,
+            hold_open: false,
+            split_at: None,
+        }
+    }
+
+    /// A `text/event-stream` body with one `data:` event per payload.
+    pub fn sse(events: &[&str]) -> MockResponse {
+        let body: String = events.iter().map(|e| format!("data: {}\n\n", e)).collect();
+        MockResponse {
+            status: 200,
+            content_type: "text/event-stream",
+            body: body.into_bytes(),
+            delay: None,
+            hold_open: false,
+            split_at: None,
+        }
+    }
+
+    /// A `text/event-stream` body sent exactly as given, for streams with
+    /// comments or other lines `sse` can't express.
+    pub fn sse_raw(body: &str) -> MockResponse {
+        MockResponse {
+            status: 200,
+            content_type: "text/event-stream",
+            body: body.as_bytes().to_vec(),
+            delay: None,
+            hold_open: false,
+            split_at: None,
+        }
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> MockResponse {
+        self.delay = Some(delay);
+        self
+    }
+
+    pub fn held_open(mut self) -> MockResponse {
+        self.hold_open = true;
+        self
+    }
+
+    pub fn split_at(mut self, offset: usize) -> MockResponse {
+        self.split_at = Some(offset);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Protocol from the request line, e.g. `HTTP/1.1`; `HTTP/2.0` for the
+    /// HTTP/2 connection preface.
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    open_connections: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    pub async fn start(responses: Vec<MockResponse>) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let open_connections = Arc::new(AtomicUsize::new(0));
+
+        let recorded = requests.clone();
+        let open = open_connections.clone();
+        tokio::spawn(async move {
+            let mut served = 0;
+            loop {
+                let Ok((socket, _)) = listener.accept().await else {
+                    return;
+                };
+                let response = responses[served.min(responses.len() - 1)].clone();
+                served += 1;
+                let recorded = recorded.clone();
+                let open = open.clone();
+                open.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    handle_connection(socket, response, recorded).await;
+                    open.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        MockServer {
+            url,
+            requests,
+            open_connections,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Connections accepted and not yet closed by either side.
+    pub fn open_connections(&self) -> usize {
+        self.open_connections.load(Ordering::SeqCst)
+    }
+}
+
+/// A listener that never accepts and whose accept queue is already full, so
+/// new connections hang in the TCP handshake. Connections to it only end
+/// through a connect timeout.
+pub struct StalledServer {
+    pub url: String,
+    _listener: TcpListener,
+    _queued: Vec<TcpStream>,
+}
+
+impl StalledServer {
+    pub async fn start() -> StalledServer {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut queued = Vec::new();
+        loop {
+            let connect = TcpStream::connect(addr);
+            match tokio::time::timeout(Duration::from_millis(100), connect).await {
+                Ok(Ok(stream)) => queued.push(stream),
+                _ => break,
+            }
+        }
+
+        StalledServer {
+            url: format!("http://{}", addr),
+            _listener: listener,
+            _queued: queued,
+        }
+    }
+}
+
+async fn handle_connection(
+    mut socket: TcpStream,
+    response: MockResponse,
+    recorded: Arc<Mutex<Vec<RecordedRequest>>>,
+) {
+    let mut raw = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_end = loop {
+        let n = match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        raw.extend_from_slice(&buf[..n]);
+        if let Some(pos) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&raw[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let version = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = raw[head_end..].to_vec();
+    while body.len() < content_length {
+        let n = match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        body.extend_from_slice(&buf[..n]);
+    }
+
+    recorded.lock().unwrap().push(RecordedRequest {
+        method,
+        path,
+        version,
+        headers,
+        body,
+    });
+
+    if let Some(delay) = response.delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    if response.hold_open {
+        // No length: the body runs until the connection closes, which only
+        // the client does.
+        let head = format!(
+            "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nConnection: close\r\n\r\n",
+            response.status, response.content_type
+        );
+        let _ = socket.write_all(head.as_bytes()).await;
+        let _ = socket.write_all(&response.body).await;
+        while let Ok(n) = socket.read(&mut buf).await
+            && n > 0
+        {}
+        return;
+    }
+
+    let head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    let _ = socket.write_all(head.as_bytes()).await;
+    let (first, rest) = response
+        .body
+        .split_at(response.split_at.unwrap_or(response.body.len()));
+    let _ = socket.write_all(first).await;
+    if !rest.is_empty() {
+        let _ = socket.flush().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let _ = socket.write_all(rest).await;
+    }
+    let _ = socket.shutdown().await;
+}